@@ -21,11 +21,14 @@ fn main() {
     // `memory.x` is changed.
     println!("cargo:rerun-if-changed=memory.x");
 
-    println!("cargo:rustc-link-arg-bins=--nmagic");
-    println!("cargo:rustc-linker=flip-link");
-    println!("cargo:rustc-link-arg-bins=-Tlink.x");
-    println!("cargo:rustc-link-arg-bins=-Tlink-rp.x");
-    println!("cargo:rustc-link-arg-bins=-Tdefmt.x");
+    // Firmware linking only, the library's unit tests run on the host
+    if env::var("TARGET").unwrap().starts_with("thumb") {
+        println!("cargo:rustc-link-arg-bins=--nmagic");
+        println!("cargo:rustc-linker=flip-link");
+        println!("cargo:rustc-link-arg-bins=-Tlink.x");
+        println!("cargo:rustc-link-arg-bins=-Tlink-rp.x");
+        println!("cargo:rustc-link-arg-bins=-Tdefmt.x");
+    }
 
     println!("cargo:rerun-if-changed=default.toml");
     println!("cargo:rerun-if-changed=local.toml");
@@ -37,17 +40,171 @@ fn main() {
         .build()
         .expect("Failed to load configuration");
 
-    // Extract WiFi settings
-    let wifi_ssid = settings.get_string("wifi.ssid").expect("Missing wifi.ssid");
-    let wifi_password = settings
-        .get_string("wifi.password")
-        .expect("Missing wifi.password");
+    // Extract WiFi settings: `[[wifi.networks]]`, or a single `wifi.ssid`/`wifi.password`.
+    // One file may only use one form; a form in local.toml replaces default.toml's.
+    let wifi_forms = |file: &str| {
+        let layer = config::Config::builder()
+            .add_source(config::File::from(Path::new(file)).required(false))
+            .build()
+            .unwrap_or_else(|e| panic!("Failed to load {file}: {e}"));
+        let single = layer.get_string("wifi.ssid").is_ok();
+        let networks = layer.get_array("wifi.networks").is_ok();
+        if single && networks {
+            panic!("{file} sets both wifi.ssid and [[wifi.networks]], keep only one of them");
+        }
+        (single, networks)
+    };
+    let (default_single, _) = wifi_forms("default.toml");
+    let (local_single, local_networks) = wifi_forms("local.toml");
+    let single_network = local_single || (default_single && !local_networks);
+    let mut wifi_networks: Vec<(String, String, u8)> = if single_network {
+        let ssid = settings.get_string("wifi.ssid").expect("Missing wifi.ssid");
+        let password = settings
+            .get_string("wifi.password")
+            .expect("Missing wifi.password");
+        vec![(ssid, password, 0)]
+    } else {
+        settings
+            .get_array("wifi.networks")
+            .expect("Missing wifi.networks")
+            .into_iter()
+            .map(|network| {
+                let network = network
+                    .into_table()
+                    .expect("wifi.networks entries must be tables");
+                let field = |name: &str| {
+                    network
+                        .get(name)
+                        .cloned()
+                        .unwrap_or_else(|| panic!("Missing {name} in wifi.networks entry"))
+                };
+                let ssid = field("ssid").into_string().expect("Invalid WiFi ssid");
+                let password = field("password")
+                    .into_string()
+                    .expect("Invalid WiFi password");
+                let priority = match network.get("priority") {
+                    Some(priority) => priority.clone().into_int().expect("Invalid WiFi priority"),
+                    None => 0,
+                };
+                let priority = u8::try_from(priority).unwrap_or_else(|_| {
+                    panic!("Invalid priority {priority} for WiFi '{ssid}', expected 0 to 255")
+                });
+                (ssid, password, priority)
+            })
+            .collect()
+    };
+    if wifi_networks.is_empty() || wifi_networks.len() > 8 {
+        panic!(
+            "Invalid number of wifi.networks ({}), expected 1 to 8",
+            wifi_networks.len()
+        );
+    }
+    for (ssid, password, _) in &wifi_networks {
+        if !(1..=32).contains(&ssid.len()) {
+            panic!("Invalid WiFi ssid '{ssid}', expected 1 to 32 bytes");
+        }
+        if !password.is_empty() && !(8..=63).contains(&password.len()) {
+            panic!("Invalid password for WiFi '{ssid}', expected 8 to 63 characters or empty");
+        }
+    }
+    // Highest priority first, networks of equal priority in config order
+    wifi_networks.sort_by_key(|&(_, _, priority)| std::cmp::Reverse(priority));
+    let wifi_networks: String = wifi_networks
+        .iter()
+        .map(|(ssid, password, priority)| {
+            format!("        WifiNetwork {{ ssid: {ssid:?}, password: {password:?}, priority: {priority} }},\n")
+        })
+        .collect();
 
     // Extract image download settings
     let image_url = settings.get_string("image.url").expect("Missing image.url");
+    let (image_https, rest) = if let Some(rest) = image_url.strip_prefix("https://") {
+        (true, rest)
+    } else if let Some(rest) = image_url.strip_prefix("http://") {
+        (false, rest)
+    } else {
+        panic!("Invalid image.url '{image_url}', expected http:// or https://");
+    };
+    let (authority, image_path) = match rest.find('/') {
+        Some(slash) => rest.split_at(slash),
+        None => (rest, "/"),
+    };
+    let (image_host, image_port) = match authority.rsplit_once(':') {
+        Some((host, port)) => (
+            host,
+            port.parse::<u16>()
+                .unwrap_or_else(|_| panic!("Invalid port '{port}' in image.url")),
+        ),
+        None => (authority, if image_https { 443 } else { 80 }),
+    };
+    if image_host.is_empty() {
+        panic!("Missing host in image.url '{image_url}'");
+    }
     let update_interval_minutes = settings
         .get::<u32>("image.update_interval_minutes")
         .expect("Missing image.update_interval_minutes");
+    let retry_initial_secs = settings
+        .get::<u32>("image.retry_initial_secs")
+        .expect("Missing image.retry_initial_secs");
+    if retry_initial_secs < 30 {
+        panic!("Invalid image.retry_initial_secs {retry_initial_secs}, expected at least 30");
+    }
+    let retry_multiplier = settings
+        .get::<u32>("image.retry_multiplier")
+        .expect("Missing image.retry_multiplier");
+    if !(1..=10).contains(&retry_multiplier) {
+        panic!("Invalid image.retry_multiplier {retry_multiplier}, expected 1 to 10");
+    }
+    let retry_jitter_percent = settings
+        .get::<u8>("image.retry_jitter_percent")
+        .expect("Missing image.retry_jitter_percent");
+    if retry_jitter_percent > 50 {
+        panic!("Invalid image.retry_jitter_percent {retry_jitter_percent}, expected 0 to 50");
+    }
+
+    let dither = settings
+        .get_string("image.dither")
+        .expect("Missing image.dither");
+    let image_dither = match dither.as_str() {
+        "floyd-steinberg" => "FloydSteinberg",
+        "ordered" => "Ordered",
+        "none" => "None",
+        other => panic!(
+            "Invalid image.dither '{other}', expected 'floyd-steinberg', 'ordered' or 'none'"
+        ),
+    };
+
+    // Extract TLS settings: a pin is required for https:// URLs
+    let pin_hash = |key: &str| {
+        let hex = settings.get_string(key).unwrap_or_default();
+        if hex.is_empty() {
+            return None;
+        }
+        let bytes: Vec<u8> = (0..hex.len())
+            .step_by(2)
+            .filter_map(|i| hex.get(i..i + 2))
+            .map(|byte| u8::from_str_radix(byte, 16))
+            .collect::<Result<_, _>>()
+            .unwrap_or_else(|_| panic!("Invalid {key}, expected 64 hex digits"));
+        if bytes.len() != 32 || hex.len() != 64 {
+            panic!("Invalid {key}, expected 64 hex digits");
+        }
+        Some(format!("{bytes:?}"))
+    };
+    let tls_pin = match (
+        pin_hash("tls.public_key_sha256"),
+        pin_hash("tls.certificate_sha256"),
+    ) {
+        (Some(_), Some(_)) => {
+            panic!("Set only one of tls.public_key_sha256 and tls.certificate_sha256")
+        }
+        (Some(hash), None) => format!("Some(crate::tls::Pin::PublicKey({hash}))"),
+        (None, Some(hash)) => format!("Some(crate::tls::Pin::Certificate({hash}))"),
+        (None, None) if image_https => {
+            panic!("image.url is https://, set tls.public_key_sha256 or tls.certificate_sha256")
+        }
+        (None, None) => "None".to_string(),
+    };
 
     // Extract e-Paper settings
     let epaper_width = settings
@@ -56,9 +213,117 @@ fn main() {
     let epaper_height = settings
         .get::<u16>("epaper.height")
         .expect("Missing epaper.height");
+    // The panel itself is chosen by cargo feature
+    let (panel, panel_width, panel_height) = if env::var_os("CARGO_FEATURE_PANEL_4IN2").is_some() {
+        ("4.2\" (panel-4in2)", 400, 300)
+    } else {
+        ("5.65\"", 600, 448)
+    };
+    if (epaper_width, epaper_height) != (panel_width, panel_height) {
+        panic!(
+            "epaper.width/height {epaper_width}x{epaper_height} do not match the {panel} panel, expected {panel_width}x{panel_height}"
+        );
+    }
     let spi_frequency = settings
         .get::<u32>("epaper.spi_frequency")
         .expect("Missing epaper.spi_frequency");
+    let spi_mode = settings
+        .get_string("epaper.spi_mode")
+        .expect("Missing epaper.spi_mode");
+    let epaper_hardware_spi = match spi_mode.as_str() {
+        "hardware" => true,
+        "bitbang" => false,
+        other => panic!("Invalid epaper.spi_mode '{other}', expected 'hardware' or 'bitbang'"),
+    };
+    let epaper_rotation = settings
+        .get::<u16>("epaper.rotation")
+        .expect("Missing epaper.rotation");
+    if ![0, 90, 180, 270].contains(&epaper_rotation) {
+        panic!("Invalid epaper.rotation {epaper_rotation}, expected 0, 90, 180 or 270");
+    }
+    let epaper_mirror = settings
+        .get_bool("epaper.mirror")
+        .expect("Missing epaper.mirror");
+    let clean_every_refreshes = settings
+        .get::<u32>("epaper.clean_every_refreshes")
+        .expect("Missing epaper.clean_every_refreshes");
+    let clean_interval_hours = settings
+        .get::<u32>("epaper.clean_interval_hours")
+        .expect("Missing epaper.clean_interval_hours");
+    let clean_color = settings
+        .get_string("epaper.clean_color")
+        .expect("Missing epaper.clean_color");
+    let epaper_clean_with_clean_color = match clean_color.as_str() {
+        "white" => false,
+        "clean" => true,
+        other => panic!("Invalid epaper.clean_color '{other}', expected 'white' or 'clean'"),
+    };
+    let max_skipped_refreshes = settings
+        .get::<u32>("epaper.max_skipped_refreshes")
+        .expect("Missing epaper.max_skipped_refreshes");
+    let min_refresh_temperature = settings
+        .get::<i8>("epaper.min_refresh_temperature")
+        .expect("Missing epaper.min_refresh_temperature");
+    let max_refresh_temperature = settings
+        .get::<i8>("epaper.max_refresh_temperature")
+        .expect("Missing epaper.max_refresh_temperature");
+    if min_refresh_temperature >= max_refresh_temperature {
+        panic!(
+            "Invalid epaper.min_refresh_temperature {min_refresh_temperature}, \
+             must be below epaper.max_refresh_temperature {max_refresh_temperature}"
+        );
+    }
+
+    // Extract status bar settings
+    let widgets = settings
+        .get_array("display.widgets")
+        .expect("Missing display.widgets");
+    if widgets.len() > 8 {
+        panic!("Too many display.widgets ({}), at most 8", widgets.len());
+    }
+    let status_widgets: String = widgets
+        .into_iter()
+        .map(|widget| {
+            let widget = widget
+                .into_table()
+                .expect("display.widgets entries must be tables");
+            let field = |name: &str| {
+                widget
+                    .get(name)
+                    .cloned()
+                    .unwrap_or_else(|| panic!("Missing {name} in display.widgets entry"))
+            };
+            let kind = field("kind").into_string().expect("Invalid widget kind");
+            let variant = match kind.as_str() {
+                "battery" => "Battery",
+                "temperature" => "Temperature",
+                "rssi" => "Rssi",
+                "updated" => "Updated",
+                "age" => "Age",
+                "version" => "Version",
+                "countdown" => "Countdown",
+                other => panic!("Unknown widget kind '{other}' in display.widgets"),
+            };
+            let x = u16::try_from(field("x").into_int().expect("Invalid widget x"))
+                .expect("Widget x out of range");
+            let y = u16::try_from(field("y").into_int().expect("Invalid widget y"))
+                .expect("Widget y out of range");
+            let scale = match widget.get("scale") {
+                Some(scale) => scale.clone().into_int().expect("Invalid widget scale"),
+                None => 1,
+            };
+            if !(1..=8).contains(&scale) {
+                panic!("Invalid scale {scale} for widget '{kind}', expected 1 to 8");
+            }
+            format!("        WidgetSlot::new(Widget::{variant}, {x}, {y}, {scale}),\n")
+        })
+        .collect();
+    let utc_offset_minutes = settings
+        .get::<i16>("display.utc_offset_minutes")
+        .expect("Missing display.utc_offset_minutes");
+    if !(-12 * 60..=14 * 60).contains(&utc_offset_minutes) {
+        panic!("Invalid display.utc_offset_minutes {utc_offset_minutes}, expected -720 to 840");
+    }
 
     // Generate Rust code with constants
     let out_dir = env::var("OUT_DIR").unwrap();
@@ -69,34 +334,105 @@ fn main() {
 // Auto-generated configuration file from default.toml and local.toml
 // Do not edit this file manually!
 
-// WiFi SSID
-pub const WIFI_SSID: &str = "{}";
-
-// WiFi password
-pub const WIFI_PASSWORD: &str = "{}";
+// WiFi networks, highest priority first
+pub const WIFI_NETWORKS: &[crate::network::WifiNetwork] = {{
+    use crate::network::WifiNetwork;
+    &[
+{}    ]
+}};
 
 // Image URL (full HTTP URL)
 pub const IMAGE_URL: &str = "{}";
 
+// Parts of the image URL
+pub const IMAGE_HTTPS: bool = {};
+pub const IMAGE_HOST: &str = "{}";
+pub const IMAGE_PORT: u16 = {};
+pub const IMAGE_PATH: &str = "{}";
+
+// Server key or certificate pin for https:// URLs
+pub const TLS_PIN: Option<crate::tls::Pin> = {};
+
 // Image update interval in minutes
 pub const UPDATE_INTERVAL_MINUTES: u32 = {};
 
-// e-Paper display width
-pub const EPAPER_WIDTH: u16 = {};
+// First retry after a failed update, in seconds
+pub const RETRY_INITIAL_SECS: u32 = {};
+
+// Growth of the wait between consecutive retries
+pub const RETRY_MULTIPLIER: u32 = {};
+
+// Random spread of each retry wait, in percent either way
+pub const RETRY_JITTER_PERCENT: u8 = {};
 
-// e-Paper display height
-pub const EPAPER_HEIGHT: u16 = {};
+// Dithering of RGB/gray images
+pub const IMAGE_DITHER: crate::image::dither::DitherMethod =
+    crate::image::dither::DitherMethod::{};
 
 // SPI frequency for e-Paper
 pub const SPI_FREQUENCY: u32 = {};
+
+// Drive e-Paper through SPI1 + DMA (true) or bit-banged GPIO (false)
+pub const EPAPER_HARDWARE_SPI: bool = {};
+
+// Clockwise image rotation on the panel in degrees (0, 90, 180 or 270)
+pub const EPAPER_ROTATION: u16 = {};
+
+// Mirror the image horizontally before rotating
+pub const EPAPER_MIRROR: bool = {};
+
+// Deep-clean the panel on every Nth full refresh (0 = not by count)
+pub const CLEAN_EVERY_REFRESHES: u32 = {};
+
+// Deep-clean the panel at least this often, in hours (0 = not by time)
+pub const CLEAN_INTERVAL_HOURS: u32 = {};
+
+// Deep clean with the CLEAN color index (true) or with white (false)
+pub const EPAPER_CLEAN_WITH_CLEAN_COLOR: bool = {};
+
+// Consecutive unchanged frames to skip before refreshing anyway (0 = never skip)
+pub const MAX_SKIPPED_REFRESHES: u32 = {};
+
+// Lowest chip temperature (°C) at which the panel is refreshed
+pub const MIN_REFRESH_TEMPERATURE: i8 = {};
+
+// Highest chip temperature (°C) at which the panel is refreshed
+pub const MAX_REFRESH_TEMPERATURE: i8 = {};
+
+// Status bar widgets drawn over every image
+pub const STATUS_WIDGETS: &[crate::layout::WidgetSlot] = {{
+    use crate::layout::{{Widget, WidgetSlot}};
+    &[
+{}    ]
+}};
+
+// Offset of local time from UTC in minutes
+pub const UTC_OFFSET_MINUTES: i16 = {};
 "#,
-        wifi_ssid,
-        wifi_password,
+        wifi_networks,
         image_url,
+        image_https,
+        image_host,
+        image_port,
+        image_path,
+        tls_pin,
         update_interval_minutes,
-        epaper_width,
-        epaper_height,
-        spi_frequency
+        retry_initial_secs,
+        retry_multiplier,
+        retry_jitter_percent,
+        image_dither,
+        spi_frequency,
+        epaper_hardware_spi,
+        epaper_rotation,
+        epaper_mirror,
+        clean_every_refreshes,
+        clean_interval_hours,
+        epaper_clean_with_clean_color,
+        max_skipped_refreshes,
+        min_refresh_temperature,
+        max_refresh_temperature,
+        status_widgets,
+        utc_offset_minutes
     );
 
     fs::write(&dest_path, generated_code).expect("Failed to write generated config");
@@ -1,6 +1,8 @@
 MEMORY {
     BOOT2 : ORIGIN = 0x10000000, LENGTH = 0x100
-    FLASH : ORIGIN = 0x10000100, LENGTH = 2048K - 0x100
+    /* The last 256K hold the image on display, see src/store.rs. Streaming */
+    /* builds leave them unused; the firmware fits the rest either way.     */
+    FLASH : ORIGIN = 0x10000100, LENGTH = 2048K - 0x100 - 256K
 
     /* Pick one of the two options for RAM layout     */
 
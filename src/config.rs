@@ -1,32 +1,150 @@
-//! GPIO configuration and helper initializers for the 5.65" e-Paper display.
-//! Bit-banged SPI pins (CLK/MOSI) are provided via GPIOs.
+//! GPIO configuration and helper initializers for the e-Paper display.
+//! The SPI lines (CLK/MOSI) are driven either by the SPI1 peripheral with DMA
+//! or bit-banged via GPIOs, selected by `epaper.spi_mode`.
 
 #![allow(dead_code)]
 
 include!(concat!(env!("OUT_DIR"), "/config_generated.rs"));
 
+use embassy_embedded_hal::shared_bus::asynch::spi::SpiDevice;
 use embassy_rp::{
     Peri,
     gpio::{Input, Level, Output, Pull},
     peripherals,
+    spi::{self, Async, Spi},
 };
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, mutex::Mutex};
+use embedded_hal_1::spi::{ErrorKind, ErrorType};
+use embedded_hal_async::spi::SpiBus;
+use static_cell::StaticCell;
 
-/// Pins for e-Paper display (bit-banged SPI).
+use crate::epd::{self, Display, Orientation, Rotation};
+use crate::layout::StatusLayout;
+
+/// e-Paper SPI device: the shared `EpdBus` with CS (GPIO9) asserted per transaction
+pub type EpdSpiDevice =
+    SpiDevice<'static, CriticalSectionRawMutex, EpdBus<'static>, Output<'static>>;
+
+/// The selected panel driver as wired on the Pico-ePaper board
+pub type Epd =
+    epd::Panel<EpdSpiDevice, Output<'static>, Output<'static>, Input<'static>, embassy_time::Delay>;
+
+/// Image orientation from `epaper.rotation`/`epaper.mirror` (validated by build.rs)
+pub const EPD_ORIENTATION: Orientation = match Rotation::from_degrees(EPAPER_ROTATION) {
+    Some(rotation) => Orientation::new(rotation, EPAPER_MIRROR),
+    None => panic!("invalid epaper.rotation"),
+};
+
+/// Status bar from `display.widgets`, used unless the server sends `X-Status-Bar`
+pub const STATUS_LAYOUT: StatusLayout = StatusLayout::new(STATUS_WIDGETS);
+
+/// Color of the deep-clean pass from `epaper.clean_color`
+pub const DEEP_CLEAN_COLOR: u8 = if EPAPER_CLEAN_WITH_CLEAN_COLOR {
+    epd::CLEAN
+} else {
+    epd::WHITE
+};
+
+/// Owner of the e-Paper bus so the `SpiDevice` can borrow it for `'static`
+static EPD_BUS: StaticCell<Mutex<CriticalSectionRawMutex, EpdBus<'static>>> = StaticCell::new();
+
+/// Pins for e-Paper display.
 ///
 /// Mapping matches lib/config.c:
 /// - RST  -> GPIO12
 /// - DC   -> GPIO8
 /// - CS   -> GPIO9
 /// - BUSY -> GPIO13
-/// - CLK  -> GPIO10
-/// - MOSI -> GPIO11
-pub struct EpdPins<'d> {
-    pub rst: Output<'d>,
-    pub dc: Output<'d>,
-    pub cs: Output<'d>,
-    pub busy: Input<'d>,
-    pub clk: Output<'d>,
-    pub mosi: Output<'d>,
+/// - CLK  -> GPIO10 (SPI1 SCK)
+/// - MOSI -> GPIO11 (SPI1 TX)
+pub struct EpdPins {
+    pub spi: EpdSpiDevice,
+    pub rst: Output<'static>,
+    pub dc: Output<'static>,
+    pub busy: Input<'static>,
+}
+
+impl EpdPins {
+    /// Build the e-Paper driver on top of these pins
+    pub fn into_driver(self) -> Epd {
+        let mut epd = Epd::new(self.spi, self.dc, self.rst, self.busy, embassy_time::Delay);
+        epd.set_orientation(EPD_ORIENTATION);
+        epd
+    }
+}
+
+/// Serial data path to the e-Paper controller (write-only, SPI mode 0, MSB first).
+/// Implements `SpiBus`; chip select is added by `EpdSpiDevice`.
+pub enum EpdBus<'d> {
+    /// SPI1 peripheral, transfers are pushed by DMA
+    Hardware(Spi<'d, peripherals::SPI1, Async>),
+    /// Bit-banged fallback for boards wired to non-SPI pins
+    BitBang { clk: Output<'d>, mosi: Output<'d> },
+}
+
+/// Error for operations the write-only e-Paper bus cannot perform
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum EpdBusError {
+    /// SPI1 reported a transfer error
+    Transfer,
+    /// Reads are not wired (no MISO line)
+    ReadUnsupported,
+}
+
+impl embedded_hal_1::spi::Error for EpdBusError {
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::Other
+    }
+}
+
+impl ErrorType for EpdBus<'_> {
+    type Error = EpdBusError;
+}
+
+impl SpiBus for EpdBus<'_> {
+    async fn read(&mut self, _words: &mut [u8]) -> Result<(), Self::Error> {
+        Err(EpdBusError::ReadUnsupported)
+    }
+
+    async fn write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
+        match self {
+            EpdBus::Hardware(spi) => spi.write(words).await.map_err(|_| EpdBusError::Transfer),
+            EpdBus::BitBang { clk, mosi } => {
+                for &byte in words {
+                    let mut value = byte;
+                    for _ in 0..8 {
+                        clk.set_low();
+                        if (value & 0x80) != 0 {
+                            mosi.set_high();
+                        } else {
+                            mosi.set_low();
+                        }
+                        clk.set_high();
+                        value <<= 1;
+                    }
+                    clk.set_low();
+                }
+                Ok(())
+            }
+        }
+    }
+
+    async fn transfer(&mut self, _read: &mut [u8], _write: &[u8]) -> Result<(), Self::Error> {
+        Err(EpdBusError::ReadUnsupported)
+    }
+
+    async fn transfer_in_place(&mut self, _words: &mut [u8]) -> Result<(), Self::Error> {
+        Err(EpdBusError::ReadUnsupported)
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        match self {
+            EpdBus::Hardware(spi) => embedded_hal_async::spi::SpiBus::<u8>::flush(spi)
+                .await
+                .map_err(|_| EpdBusError::Transfer),
+            EpdBus::BitBang { .. } => Ok(()),
+        }
+    }
 }
 
 /// Keys (buttons) per lib/epd_5in65f.h:
@@ -40,7 +158,8 @@ pub struct Keys<'d> {
 }
 
 /// Initialize all components (consumes Peripherals).
-/// Returns bit-banged SPI GPIOs for the e-Paper and the three keys.
+/// Returns the e-Paper pins with the configured SPI backend and the three keys.
+#[allow(clippy::too_many_arguments)]
 pub fn init_all(
     pin_12: Peri<'static, peripherals::PIN_12>,
     pin_8: Peri<'static, peripherals::PIN_8>,
@@ -48,27 +167,52 @@ pub fn init_all(
     pin_13: Peri<'static, peripherals::PIN_13>,
     pin_10: Peri<'static, peripherals::PIN_10>,
     pin_11: Peri<'static, peripherals::PIN_11>,
+    spi1: Peri<'static, peripherals::SPI1>,
+    dma_ch1: Peri<'static, peripherals::DMA_CH1>,
     pin_15: Peri<'static, peripherals::PIN_15>,
     pin_17: Peri<'static, peripherals::PIN_17>,
     pin_2: Peri<'static, peripherals::PIN_2>,
-) -> (EpdPins<'static>, Keys<'static>) {
+) -> (EpdPins, Keys<'static>) {
     // e-Paper control pins
     let rst = Output::new(pin_12, Level::High);
     let dc = Output::new(pin_8, Level::High);
     let cs = Output::new(pin_9, Level::High);
     let busy = Input::new(pin_13, Pull::None);
 
-    // Bit-banged SPI lines
-    let clk = Output::new(pin_10, Level::Low);
-    let mosi = Output::new(pin_11, Level::Low);
+    let bus = if EPAPER_HARDWARE_SPI {
+        // SPI cannot run faster than half of clk_peri, which follows the
+        // (deliberately low) system clock.
+        let max_frequency = embassy_rp::clocks::clk_peri_freq() / 2;
+        let mut spi_config = spi::Config::default();
+        spi_config.frequency = SPI_FREQUENCY.min(max_frequency);
+        defmt::info!(
+            "EPD bus: SPI1 + DMA at {} Hz (requested {} Hz)",
+            spi_config.frequency,
+            SPI_FREQUENCY
+        );
+
+        EpdBus::Hardware(Spi::new_txonly(
+            spi1,
+            pin_10,
+            pin_11,
+            dma_ch1,
+            crate::Irqs,
+            spi_config,
+        ))
+    } else {
+        defmt::info!("EPD bus: bit-banged GPIO");
+        EpdBus::BitBang {
+            clk: Output::new(pin_10, Level::Low),
+            mosi: Output::new(pin_11, Level::Low),
+        }
+    };
 
+    let bus = EPD_BUS.init(Mutex::new(bus));
     let epd_pins = EpdPins {
+        spi: SpiDevice::new(bus, cs),
         rst,
         dc,
-        cs,
         busy,
-        clk,
-        mosi,
     };
 
     // Keys
@@ -0,0 +1,524 @@
+//! Overlay drawing on image buffers (text, status bar, battery and warning icons)
+//! `Framebuffer` wraps the image buffer as an embedded-graphics `DrawTarget`;
+//! pixels are packed in the selected panel's format, see `crate::epd`. Overlays
+//! over a downloaded image are drawn into small bands instead, see `overlay`.
+
+pub mod overlay;
+pub mod status;
+pub mod text;
+
+use core::fmt::Write as _;
+
+use embedded_graphics::pixelcolor::raw::RawU4;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::{
+    Arc, Circle, Line, PrimitiveStyle, PrimitiveStyleBuilder, Rectangle, StrokeAlignment, Triangle,
+};
+use heapless::String;
+
+use crate::epd::{self, Orientation, Window, fill_byte, native_color, put_pixel, row_bytes};
+use text::{Alignment, TextStyle, draw_text};
+
+/// Colors the firmware draws with, numbered like the ACeP color indices
+/// (lib/epd_5in65f.h). Black/white panels show `White` and `Clean` as white
+/// and every other color as black.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+#[repr(u8)]
+pub enum EpdColor {
+    Black = epd::BLACK,
+    White = epd::WHITE,
+    Green = 0x2,
+    Blue = 0x3,
+    Red = epd::RED,
+    Yellow = 0x5,
+    Orange = 0x6,
+    /// Drives all pigments to their rest position
+    Clean = epd::CLEAN,
+}
+
+impl EpdColor {
+    /// Color for a 3-bit color index (the high bit of a nibble is ignored)
+    pub const fn from_index(index: u8) -> Self {
+        match index & 0x7 {
+            0x0 => Self::Black,
+            0x1 => Self::White,
+            0x2 => Self::Green,
+            0x3 => Self::Blue,
+            0x4 => Self::Red,
+            0x5 => Self::Yellow,
+            0x6 => Self::Orange,
+            _ => Self::Clean,
+        }
+    }
+
+    /// Color index as used by the panel drivers
+    pub const fn index(self) -> u8 {
+        self as u8
+    }
+}
+
+impl PixelColor for EpdColor {
+    type Raw = RawU4;
+}
+
+impl From<RawU4> for EpdColor {
+    fn from(raw: RawU4) -> Self {
+        Self::from_index(raw.into_inner())
+    }
+}
+
+impl From<EpdColor> for RawU4 {
+    fn from(color: EpdColor) -> Self {
+        RawU4::new(color.index())
+    }
+}
+
+/// Where a `Framebuffer` keeps its pixels
+enum Pixels<'a> {
+    /// Whole image, packed in the panel format with `stride` bytes per row
+    Packed { image: &'a mut [u8], stride: usize },
+    /// One color index per pixel of `area`, row-major; pixels outside are dropped
+    Band {
+        pixels: &'a mut [u8],
+        area: Rectangle,
+    },
+}
+
+/// Image being drawn on, in image coordinates.
+/// Its size follows the display orientation (portrait when rotated by 90°/270°).
+pub struct Framebuffer<'a> {
+    pixels: Pixels<'a>,
+    width: u16,
+    height: u16,
+}
+
+impl<'a> Framebuffer<'a> {
+    /// Draw straight into a panel-format image
+    pub fn new(image: &'a mut [u8], orientation: Orientation) -> Self {
+        let (width, height) = orientation.size();
+        Self {
+            pixels: Pixels::Packed {
+                image,
+                stride: row_bytes(width),
+            },
+            width,
+            height,
+        }
+    }
+
+    /// Draw only the part of the image inside `area`, keeping one color index
+    /// per pixel in `pixels` (at least `area` pixels, untouched ones keep their value)
+    pub fn band(pixels: &'a mut [u8], orientation: Orientation, area: Rectangle) -> Self {
+        let (width, height) = orientation.size();
+        let image = Rectangle::new(Point::zero(), Size::new(width.into(), height.into()));
+        Self {
+            pixels: Pixels::Band {
+                pixels,
+                area: area.intersection(&image),
+            },
+            width,
+            height,
+        }
+    }
+
+    pub fn width(&self) -> u16 {
+        self.width
+    }
+
+    pub fn height(&self) -> u16 {
+        self.height
+    }
+
+    /// Part of the image that drawing can change
+    fn clip(&self) -> Rectangle {
+        match &self.pixels {
+            Pixels::Packed { .. } => self.bounding_box(),
+            Pixels::Band { area, .. } => *area,
+        }
+    }
+
+    /// Whether drawing inside `area` can change any stored pixel
+    pub fn touches(&self, area: &Rectangle) -> bool {
+        !self.clip().intersection(area).is_zero_sized()
+    }
+
+    fn set_pixel(&mut self, point: Point, color: EpdColor) {
+        match &mut self.pixels {
+            Pixels::Packed { image, stride } => {
+                let (Ok(x), Ok(y)) = (u16::try_from(point.x), u16::try_from(point.y)) else {
+                    return;
+                };
+                if x < self.width && y < self.height {
+                    put_pixel(image, *stride, (x, y), native_color(color.index()));
+                }
+            }
+            Pixels::Band { pixels, area } => {
+                if area.contains(point) {
+                    let offset = point - area.top_left;
+                    let index = offset.y as usize * area.size.width as usize + offset.x as usize;
+                    if let Some(pixel) = pixels.get_mut(index) {
+                        *pixel = color.index();
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl OriginDimensions for Framebuffer<'_> {
+    fn size(&self) -> Size {
+        Size::new(u32::from(self.width), u32::from(self.height))
+    }
+}
+
+impl DrawTarget for Framebuffer<'_> {
+    type Color = EpdColor;
+    type Error = core::convert::Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, color) in pixels {
+            self.set_pixel(point, color);
+        }
+        Ok(())
+    }
+
+    /// Only the part of `area` that can change a stored pixel is visited
+    fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
+        let area = area.intersection(&self.clip());
+        if area.is_zero_sized() {
+            return Ok(());
+        }
+        if let Pixels::Band { pixels, area: band } = &mut self.pixels {
+            let stride = band.size.width as usize;
+            let offset = area.top_left - band.top_left;
+            for y in 0..area.size.height as usize {
+                let start = (offset.y as usize + y) * stride + offset.x as usize;
+                if let Some(row) = pixels.get_mut(start..start + area.size.width as usize) {
+                    row.fill(color.index());
+                }
+            }
+            return Ok(());
+        }
+        for point in area.points() {
+            self.set_pixel(point, color);
+        }
+        Ok(())
+    }
+
+    fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
+        match &mut self.pixels {
+            Pixels::Packed { image, stride } => {
+                let len = *stride * usize::from(self.height);
+                let end = len.min(image.len());
+                image[..end].fill(fill_byte(color.index()));
+                Ok(())
+            }
+            Pixels::Band { .. } => self.fill_solid(&self.bounding_box(), color),
+        }
+    }
+}
+
+/// Fill a `width` x `height` rectangle at (x, y); parts outside the image are clipped
+fn fill_rect(fb: &mut Framebuffer, x: u16, y: u16, width: u16, height: u16, color: EpdColor) {
+    let area = Rectangle::new(
+        Point::new(i32::from(x), i32::from(y)),
+        Size::new(u32::from(width), u32::from(height)),
+    );
+    let Ok(()) = fb.fill_solid(&area, color);
+}
+
+/// Draw a temperature in °C with its top left at `position`: number, degree mark and "C"
+/// (the font has no degree sign, so the mark is a small hollow square).
+/// Returns the width of the drawn text in pixels
+pub fn draw_temperature(
+    fb: &mut Framebuffer,
+    position: Point,
+    celsius: i8,
+    style: TextStyle,
+) -> u32 {
+    let scale = style.scale;
+    let mut number: String<4> = String::new();
+    let _ = write!(number, "{}", celsius);
+    let mut x = draw_text(fb, &number, position, style).size.width as i32;
+
+    // Degree mark: hollow square at the top right
+    x += scale as i32;
+    let mark = Rectangle::new(position + Point::new(x, 0), Size::new_equal(3 * scale));
+    let Ok(()) = mark
+        .into_styled(
+            PrimitiveStyleBuilder::new()
+                .stroke_color(style.color)
+                .stroke_width(scale)
+                .stroke_alignment(StrokeAlignment::Inside)
+                .build(),
+        )
+        .draw(fb);
+    x += 4 * scale as i32;
+
+    x += draw_text(fb, "C", position + Point::new(x, 0), style)
+        .size
+        .width as i32;
+    x as u32
+}
+
+/// Warning overlay block in the bottom-right quarter of the screen.
+const WARNING_OVERLAY_WIDTH: u16 = 336;
+const WARNING_OVERLAY_HEIGHT: u16 = 180;
+const WARNING_OVERLAY_RIGHT_MARGIN: u16 = 25;
+const WARNING_OVERLAY_BOTTOM_MARGIN: u16 = 14;
+const WARNING_OVERLAY_PADDING: u16 = 10;
+
+/// Top-left corner of the warning overlay block on a `width` x `height` image
+/// (clamped to the left/top edge on panels narrower than the block)
+fn warning_overlay_origin(width: u16, height: u16) -> (u16, u16) {
+    (
+        width
+            .saturating_sub(WARNING_OVERLAY_WIDTH + WARNING_OVERLAY_RIGHT_MARGIN)
+            .max(WARNING_OVERLAY_PADDING),
+        height
+            .saturating_sub(WARNING_OVERLAY_HEIGHT + WARNING_OVERLAY_BOTTOM_MARGIN)
+            .max(WARNING_OVERLAY_PADDING),
+    )
+}
+
+/// Area touched by the warning overlays (including their white padding)
+/// on a `width` x `height` image, in image coordinates for `Display::display_window`.
+pub fn warning_overlay_window(width: u16, height: u16) -> Window {
+    let (x, y) = warning_overlay_origin(width, height);
+    let x = x - WARNING_OVERLAY_PADDING;
+    let y = y - WARNING_OVERLAY_PADDING;
+    Window::new(
+        x,
+        y,
+        (WARNING_OVERLAY_WIDTH + WARNING_OVERLAY_PADDING * 2).min(width - x),
+        (WARNING_OVERLAY_HEIGHT + WARNING_OVERLAY_PADDING * 2).min(height - y),
+    )
+}
+
+/// Text scale of the caption under the warning icons (20 pixels high)
+const WARNING_CAPTION_SCALE: u32 = 2;
+
+/// Draw `caption` centered under a warning icon, `y` pixels below the overlay top
+fn draw_warning_caption(fb: &mut Framebuffer, caption: &str, y: u16) {
+    let (overlay_x, overlay_y) = warning_overlay_origin(fb.width, fb.height);
+    let position = Point::new(
+        i32::from(overlay_x + WARNING_OVERLAY_WIDTH / 2),
+        i32::from(overlay_y + y),
+    );
+    let style = TextStyle::new(EpdColor::Black, WARNING_CAPTION_SCALE).aligned(Alignment::Center);
+    draw_text(fb, caption, position, style);
+}
+
+/// Draw low-battery warning in the bottom-right quarter of the screen.
+pub fn draw_low_battery_warning(fb: &mut Framebuffer, battery_percent: u8) {
+    let (overlay_x, overlay_y) = warning_overlay_origin(fb.width, fb.height);
+
+    let icon_w = 284;
+    let icon_h = 142;
+    let cap_w = 26;
+    let icon_x = overlay_x;
+    let icon_y = overlay_y;
+    let border = 10;
+    let background_padding = WARNING_OVERLAY_PADDING;
+
+    // Clear icon and caption area with padding so they have whitespace around them.
+    fill_rect(
+        fb,
+        icon_x.saturating_sub(background_padding),
+        icon_y.saturating_sub(background_padding),
+        icon_w + cap_w + (background_padding * 2),
+        WARNING_OVERLAY_HEIGHT + (background_padding * 2),
+        EpdColor::White,
+    );
+
+    // Battery body border.
+    fill_rect(fb, icon_x, icon_y, icon_w, icon_h, EpdColor::Black);
+    fill_rect(
+        fb,
+        icon_x + border,
+        icon_y + border,
+        icon_w - (border * 2),
+        icon_h - (border * 2),
+        EpdColor::White,
+    );
+
+    // Battery cap on the right side (horizontal battery orientation).
+    fill_rect(fb, icon_x + icon_w, icon_y + 42, cap_w, 58, EpdColor::Black);
+    fill_rect(
+        fb,
+        icon_x + icon_w + 6,
+        icon_y + 48,
+        14,
+        46,
+        EpdColor::White,
+    );
+
+    // "Almost empty" red segment at the left side.
+    fill_rect(
+        fb,
+        icon_x + border + 20,
+        icon_y + border + 20,
+        38,
+        icon_h - (border * 2) - 40,
+        EpdColor::Red,
+    );
+
+    let mut caption: String<16> = String::new();
+    let _ = write!(caption, "Battery low: {}%", battery_percent);
+    draw_warning_caption(fb, &caption, icon_h + 8);
+}
+
+/// Clear the whole warning block with its padding and return its top-left corner
+fn clear_warning_overlay(fb: &mut Framebuffer) -> (u16, u16) {
+    let (overlay_x, overlay_y) = warning_overlay_origin(fb.width, fb.height);
+    let background_padding = WARNING_OVERLAY_PADDING;
+
+    fill_rect(
+        fb,
+        overlay_x.saturating_sub(background_padding),
+        overlay_y.saturating_sub(background_padding),
+        WARNING_OVERLAY_WIDTH + (background_padding * 2),
+        WARNING_OVERLAY_HEIGHT + (background_padding * 2),
+        EpdColor::White,
+    );
+    (overlay_x, overlay_y)
+}
+
+/// Draw a simple "broken WiFi" warning icon in the same area as low-battery icon
+pub fn draw_broken_wifi_warning(fb: &mut Framebuffer, caption: &str) {
+    let overlay_width = WARNING_OVERLAY_WIDTH;
+    let overlay_height = WARNING_OVERLAY_HEIGHT;
+    let (overlay_x, overlay_y) = clear_warning_overlay(fb);
+
+    let center = Point::new(
+        i32::from(overlay_x + overlay_width / 2),
+        // Move icon a bit higher inside the block.
+        i32::from(overlay_y + overlay_height - 42),
+    );
+
+    // Real WiFi arcs: three 90-degree ring segments with decreasing radii.
+    draw_wifi_arc(fb, center, 108, 18);
+    draw_wifi_arc(fb, center, 76, 16);
+    draw_wifi_arc(fb, center, 46, 14);
+
+    // WiFi dot.
+    let Ok(()) = Circle::with_center(center, 25)
+        .into_styled(PrimitiveStyle::with_fill(EpdColor::Black))
+        .draw(fb);
+
+    // Red slash to indicate "broken".
+    let start = Point::new(i32::from(overlay_x) + 78, i32::from(overlay_y) + 22);
+    let Ok(()) = Line::new(start, start + Point::new(189, 94))
+        .into_styled(PrimitiveStyle::with_stroke(EpdColor::Red, 10))
+        .draw(fb);
+
+    draw_warning_caption(fb, caption, overlay_height - 22);
+}
+
+/// Draw a server rack with a red error badge, for error responses of the image server
+pub fn draw_server_warning(fb: &mut Framebuffer, caption: &str) {
+    let (overlay_x, overlay_y) = clear_warning_overlay(fb);
+
+    let unit_w = 184;
+    let unit_h = 36;
+    let unit_gap = 8;
+    let border = 6;
+    let rack_x = overlay_x + 56;
+    let rack_y = overlay_y + 12;
+
+    // Three rack units, each with two status lights on the right.
+    for unit in 0..3 {
+        let y = rack_y + unit * (unit_h + unit_gap);
+        fill_rect(fb, rack_x, y, unit_w, unit_h, EpdColor::Black);
+        fill_rect(
+            fb,
+            rack_x + border,
+            y + border,
+            unit_w - (border * 2),
+            unit_h - (border * 2),
+            EpdColor::White,
+        );
+        fill_rect(fb, rack_x + unit_w - 44, y + 14, 8, 8, EpdColor::Black);
+        fill_rect(fb, rack_x + unit_w - 28, y + 14, 8, 8, EpdColor::Black);
+    }
+
+    // Red badge with an exclamation mark over the bottom-right corner.
+    let badge = Point::new(
+        i32::from(rack_x + unit_w),
+        i32::from(rack_y + 3 * unit_h + 2 * unit_gap - 20),
+    );
+    let Ok(()) = Circle::with_center(badge, 64)
+        .into_styled(PrimitiveStyle::with_fill(EpdColor::Red))
+        .draw(fb);
+    let badge_x = badge.x as u16;
+    let badge_y = badge.y as u16;
+    fill_rect(fb, badge_x - 4, badge_y - 22, 8, 28, EpdColor::White);
+    fill_rect(fb, badge_x - 4, badge_y + 12, 8, 8, EpdColor::White);
+
+    draw_warning_caption(fb, caption, WARNING_OVERLAY_HEIGHT - 22);
+}
+
+/// Draw a crossed-out picture, for images the server sent but the device rejected
+pub fn draw_bad_image_warning(fb: &mut Framebuffer, caption: &str) {
+    let (overlay_x, overlay_y) = clear_warning_overlay(fb);
+
+    let frame_w = 220;
+    let frame_h = 136;
+    let border = 10;
+    let frame_x = overlay_x + (WARNING_OVERLAY_WIDTH - frame_w) / 2;
+    let frame_y = overlay_y + 4;
+
+    // Picture frame.
+    fill_rect(fb, frame_x, frame_y, frame_w, frame_h, EpdColor::Black);
+    fill_rect(
+        fb,
+        frame_x + border,
+        frame_y + border,
+        frame_w - (border * 2),
+        frame_h - (border * 2),
+        EpdColor::White,
+    );
+
+    // Mountains and sun inside the frame.
+    let origin = Point::new(i32::from(frame_x), i32::from(frame_y));
+    let bottom = frame_h as i32 - border as i32;
+    let Ok(()) = Triangle::new(
+        origin + Point::new(20, bottom),
+        origin + Point::new(88, 46),
+        origin + Point::new(156, bottom),
+    )
+    .into_styled(PrimitiveStyle::with_fill(EpdColor::Black))
+    .draw(fb);
+    let Ok(()) = Triangle::new(
+        origin + Point::new(120, bottom),
+        origin + Point::new(160, 76),
+        origin + Point::new(200, bottom),
+    )
+    .into_styled(PrimitiveStyle::with_fill(EpdColor::Black))
+    .draw(fb);
+    let Ok(()) = Circle::with_center(origin + Point::new(166, 42), 28)
+        .into_styled(PrimitiveStyle::with_fill(EpdColor::Black))
+        .draw(fb);
+
+    // Red slash across the picture.
+    let start = origin + Point::new(-10, 8);
+    let end = start + Point::new(frame_w as i32 + 20, frame_h as i32 - 16);
+    let Ok(()) = Line::new(start, end)
+        .into_styled(PrimitiveStyle::with_stroke(EpdColor::Red, 10))
+        .draw(fb);
+
+    draw_warning_caption(fb, caption, WARNING_OVERLAY_HEIGHT - 22);
+}
+
+/// Upper 90-degree sector (between -45° and +45° around vertical) of a ring
+/// with outer radius `radius_outer`
+fn draw_wifi_arc(fb: &mut Framebuffer, center: Point, radius_outer: u32, thickness: u32) {
+    // The stroke is centered on the arc, so trace the middle of the ring
+    let diameter = 2 * radius_outer - thickness;
+    let Ok(()) = Arc::with_center(center, diameter, 225.0.deg(), 90.0.deg())
+        .into_styled(PrimitiveStyle::with_stroke(EpdColor::Black, thickness))
+        .draw(fb);
+}
@@ -0,0 +1,128 @@
+//! Overlays composed into the frame while it is sent to the panel
+//! The downloaded image is never drawn on. For the panel rows under the overlay
+//! a band of `BAND_ROWS` rows is drawn into a small buffer, and its pixels
+//! replace the image pixels on their way to the panel.
+
+use core::ops::Range;
+
+use crc::Digest;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+
+use super::Framebuffer;
+use super::status::Overlay;
+use crate::epd::{
+    FrameSource, Orientation, PANEL_HEIGHT, PANEL_ROW_BYTES, PANEL_WIDTH, PIXELS_PER_BYTE,
+    native_color, put_pixel,
+};
+
+/// Panel rows drawn at once
+const BAND_ROWS: u16 = 16;
+
+/// Pixels of one band (full panel rows, a stripe of image columns when rotated by 90°/270°)
+const BAND_PIXELS: usize = BAND_ROWS as usize * PANEL_WIDTH as usize;
+
+/// Band pixel the overlay did not draw on (not a color index)
+const TRANSPARENT: u8 = 0xFF;
+
+/// An image with an overlay on top, composed one panel row at a time
+pub struct OverlayFrame<'a> {
+    image: &'a [u8],
+    overlay: Overlay,
+    /// Color index per pixel of `band_area`, or `TRANSPARENT`
+    band: [u8; BAND_PIXELS],
+    /// Orientation and first panel row of the band in `band`
+    band_key: Option<(Orientation, u16)>,
+    /// Image area of the band the overlay covers, zero-sized if none
+    band_area: Rectangle,
+    /// Panel columns showing `band_area`
+    columns: Range<u16>,
+}
+
+impl<'a> OverlayFrame<'a> {
+    /// `image` is row-major in the display orientation; an empty slice is a white page
+    pub fn new(image: &'a [u8], overlay: Overlay) -> Self {
+        Self {
+            image,
+            overlay,
+            band: [TRANSPARENT; BAND_PIXELS],
+            band_key: None,
+            band_area: Rectangle::zero(),
+            columns: 0..0,
+        }
+    }
+
+    /// Feed the composed panel rows under the overlay into `digest`
+    pub fn digest_overlay(&mut self, orientation: &Orientation, digest: &mut Digest<'_, u32>) {
+        let mut row = [0u8; PANEL_ROW_BYTES];
+        for py in 0..PANEL_HEIGHT {
+            self.draw_band(orientation, py);
+            if self.band_area.is_zero_sized() {
+                continue;
+            }
+            self.panel_row(orientation, py, &mut row);
+            digest.update(&py.to_le_bytes());
+            digest.update(&row);
+        }
+    }
+
+    /// Draw the part of the overlay in the band holding panel row `py`, unless already drawn
+    fn draw_band(&mut self, orientation: &Orientation, py: u16) {
+        let first = py - py % BAND_ROWS;
+        if self.band_key == Some((*orientation, first)) {
+            return;
+        }
+        self.band_key = Some((*orientation, first));
+
+        let last = (first + BAND_ROWS).min(PANEL_HEIGHT) - 1;
+        let (ax, ay) = orientation.image_pixel(0, first);
+        let (bx, by) = orientation.image_pixel(PANEL_WIDTH - 1, last);
+        let rows = Rectangle::with_corners(
+            Point::new(i32::from(ax), i32::from(ay)),
+            Point::new(i32::from(bx), i32::from(by)),
+        );
+        let (width, height) = orientation.size();
+        self.band_area = match self.overlay.bounds(width, height) {
+            Some(bounds) => rows.intersection(&bounds),
+            None => Rectangle::zero(),
+        };
+        let Some(bottom_right) = self.band_area.bottom_right() else {
+            self.columns = 0..0;
+            return;
+        };
+
+        let area = self.band_area;
+        let pixels = &mut self.band[..(area.size.width * area.size.height) as usize];
+        pixels.fill(TRANSPARENT);
+        self.overlay
+            .draw(&mut Framebuffer::band(pixels, *orientation, area));
+
+        let (ax, _) = orientation.panel_pixel(area.top_left.x as u16, area.top_left.y as u16);
+        let (bx, _) = orientation.panel_pixel(bottom_right.x as u16, bottom_right.y as u16);
+        self.columns = ax.min(bx)..ax.max(bx) + 1;
+    }
+}
+
+impl FrameSource for OverlayFrame<'_> {
+    fn panel_row(&mut self, orientation: &Orientation, py: u16, row: &mut [u8]) {
+        orientation.panel_row(self.image, py, row);
+
+        self.draw_band(orientation, py);
+        let area = self.band_area;
+        let stride = area.size.width as usize;
+        let end = (row.len() * PIXELS_PER_BYTE) as u16;
+        let row_len = row.len();
+        for px in self.columns.start..self.columns.end.min(end) {
+            let (x, y) = orientation.image_pixel(px, py);
+            let point = Point::new(i32::from(x), i32::from(y));
+            if !area.contains(point) {
+                continue;
+            }
+            let offset = point - area.top_left;
+            let color = self.band[offset.y as usize * stride + offset.x as usize];
+            if color != TRANSPARENT {
+                put_pixel(row, row_len, (px, 0), native_color(color));
+            }
+        }
+    }
+}
@@ -0,0 +1,233 @@
+//! Status bar compositor
+//! Widgets (battery, WiFi signal, update time, ...) are placed by a `StatusLayout`
+//! (see `layout`) and drawn over each image together with the
+//! low-battery or failed-update warnings. The image itself is never changed: an
+//! `Overlay` is drawn again for every refresh.
+
+use core::fmt::Write as _;
+
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+use heapless::String;
+
+use super::text::{TextStyle, draw_text};
+use super::{
+    EpdColor, Framebuffer, draw_bad_image_warning, draw_broken_wifi_warning,
+    draw_low_battery_warning, draw_server_warning, draw_temperature, warning_overlay_window,
+};
+use crate::FIRMWARE_VERSION;
+use crate::epd::Window;
+use crate::layout::{StatusLayout, Widget, WidgetSlot, envelope};
+use crate::network::{ErrorClass, NetworkError};
+
+/// Below this battery level the low-battery warning covers the bottom-right corner
+const LOW_BATTERY_PERCENT: u8 = 30;
+
+/// Area of a window, in image coordinates
+fn window_area(window: Window) -> Rectangle {
+    Rectangle::new(
+        Point::new(i32::from(window.x), i32::from(window.y)),
+        Size::new(u32::from(window.width), u32::from(window.height)),
+    )
+}
+
+/// Values shown by the widgets, taken from the app state when drawing
+#[derive(Debug, Clone, Copy, defmt::Format)]
+pub struct StatusInfo {
+    pub battery_percent: u8,
+    pub temperature: Option<i8>,
+    /// WiFi join retries of the latest update cycle
+    pub wifi_retries: u8,
+    /// Why the latest update cycle failed, `None` after a successful download
+    pub failure: Option<NetworkError>,
+    pub rssi_dbm: Option<i16>,
+    /// Minutes since local midnight of the last successful download
+    pub updated_minute: Option<u16>,
+    /// Seconds since the last successful download
+    pub age_secs: Option<u64>,
+    /// Seconds until the next scheduled download
+    pub next_update_secs: Option<u64>,
+}
+
+/// Full-size warning drawn in the bottom-right corner
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Alert {
+    LowBattery,
+    /// WiFi or the connection to the server failed
+    BrokenWifi,
+    /// The server answered with an error
+    ServerError,
+    /// The server sent an image that was rejected
+    BadImage,
+}
+
+impl Alert {
+    /// Warning to show for the battery level and the latest failed update;
+    /// low battery wins over a failed update.
+    /// Device failures have no warning, they are reported to the server.
+    pub fn new(battery_percent: u8, failure: Option<NetworkError>) -> Option<Self> {
+        if battery_percent < LOW_BATTERY_PERCENT {
+            return Some(Self::LowBattery);
+        }
+        match failure?.class() {
+            ErrorClass::Network => Some(Self::BrokenWifi),
+            ErrorClass::Server => Some(Self::ServerError),
+            ErrorClass::Content => Some(Self::BadImage),
+            ErrorClass::Device => None,
+        }
+    }
+}
+
+/// Everything drawn over the image for one refresh: the warning due and the status bar
+#[derive(Debug, Clone, Copy, defmt::Format)]
+pub struct Overlay {
+    layout: StatusLayout,
+    info: StatusInfo,
+    alert: Option<Alert>,
+}
+
+impl Overlay {
+    pub fn new(layout: StatusLayout, info: StatusInfo) -> Self {
+        Self {
+            layout,
+            info,
+            alert: Alert::new(info.battery_percent, info.failure),
+        }
+    }
+
+    /// Warning drawn by this overlay
+    pub fn alert(&self) -> Option<Alert> {
+        self.alert
+    }
+
+    /// Smallest area covering the overlay on a `width` x `height` image,
+    /// `None` if nothing is drawn
+    pub fn bounds(&self, width: u16, height: u16) -> Option<Rectangle> {
+        let bar = self.layout.window(width, height).map(window_area);
+        let warning = self
+            .alert
+            .map(|_| window_area(warning_overlay_window(width, height)));
+        match (bar, warning) {
+            (Some(bar), Some(warning)) => Some(envelope(bar, warning)),
+            (bar, warning) => bar.or(warning),
+        }
+    }
+
+    /// Draw the warning (if any) and then the status bar, skipping what `fb` does not hold
+    pub fn draw(&self, fb: &mut Framebuffer) {
+        let warning = window_area(warning_overlay_window(fb.width(), fb.height()));
+        if fb.touches(&warning) {
+            let caption = failure_caption(&self.info);
+            match self.alert {
+                Some(Alert::LowBattery) => draw_low_battery_warning(fb, self.info.battery_percent),
+                Some(Alert::BrokenWifi) => draw_broken_wifi_warning(fb, &caption),
+                Some(Alert::ServerError) => draw_server_warning(fb, &caption),
+                Some(Alert::BadImage) => draw_bad_image_warning(fb, &caption),
+                None => {}
+            }
+        }
+        draw_status_bar(fb, &self.layout, &self.info);
+    }
+}
+
+/// Caption under a failed-update warning: "No WiFi (8 retries)", "Server error 503",
+/// "TLS error: pin", "Bad image: checksum", ...
+fn failure_caption(info: &StatusInfo) -> String<28> {
+    let mut caption = String::new();
+    let _ = match info.failure {
+        Some(NetworkError::JoinFailed) => {
+            write!(caption, "No WiFi ({} retries)", info.wifi_retries)
+        }
+        Some(NetworkError::HttpStatus(status)) => write!(caption, "Server error {}", status),
+        Some(NetworkError::Tls(failure)) => write!(caption, "TLS error: {}", failure.as_str()),
+        Some(error) if error.class() == ErrorClass::Content => {
+            write!(caption, "Bad image: {}", error.as_str())
+        }
+        Some(error) => write!(caption, "No connection: {}", error.as_str()),
+        None => Ok(()),
+    };
+    caption
+}
+
+/// Draw every widget of `layout` in black on white
+pub fn draw_status_bar(fb: &mut Framebuffer, layout: &StatusLayout, info: &StatusInfo) {
+    for slot in layout.slots() {
+        if !fb.touches(&slot.area()) {
+            continue;
+        }
+        let Ok(()) = fb.fill_solid(&slot.area(), EpdColor::White);
+        draw_widget(fb, slot, info);
+    }
+}
+
+fn draw_widget(fb: &mut Framebuffer, slot: &WidgetSlot, info: &StatusInfo) {
+    let position = Point::new(i32::from(slot.x), i32::from(slot.y));
+    let style = TextStyle::new(EpdColor::Black, u32::from(slot.scale));
+    let mut text: String<24> = String::new();
+    match slot.widget {
+        Widget::Battery => {
+            let _ = write!(text, "{}%", info.battery_percent);
+        }
+        Widget::Temperature => {
+            match info.temperature {
+                Some(celsius) => {
+                    draw_temperature(fb, position, celsius, style);
+                }
+                None => {
+                    draw_text(fb, "--C", position, style);
+                }
+            }
+            return;
+        }
+        Widget::Rssi => match info.rssi_dbm {
+            Some(rssi) => {
+                let _ = write!(text, "{}dBm", rssi);
+            }
+            None => {
+                let _ = text.push_str("--dBm");
+            }
+        },
+        Widget::Updated => match info.updated_minute {
+            Some(minute) => {
+                let _ = write!(text, "{:02}:{:02}", minute / 60, minute % 60);
+            }
+            None => {
+                let _ = text.push_str("--:--");
+            }
+        },
+        Widget::Age => match info.age_secs {
+            Some(secs) => {
+                write_duration(&mut text, secs);
+                let _ = text.push_str(" ago");
+            }
+            None => {
+                let _ = text.push_str("never");
+            }
+        },
+        Widget::Version => {
+            let _ = write!(text, "v{}", FIRMWARE_VERSION);
+        }
+        Widget::Countdown => {
+            let _ = text.push_str("next ");
+            match info.next_update_secs {
+                Some(secs) => write_duration(&mut text, secs),
+                None => {
+                    let _ = text.push_str("--");
+                }
+            }
+        }
+    }
+    draw_text(fb, &text, position, style);
+}
+
+/// Append `secs` rounded down to minutes, hours or days ("45m", "12h", "3d")
+fn write_duration(text: &mut String<24>, secs: u64) {
+    let minutes = secs / 60;
+    let _ = if minutes < 100 {
+        write!(text, "{}m", minutes)
+    } else if minutes < 48 * 60 {
+        write!(text, "{}h", minutes / 60)
+    } else {
+        write!(text, "{}d", (minutes / (24 * 60)).min(999))
+    };
+}
@@ -0,0 +1,203 @@
+//! Text rendering for overlays
+//! One monospaced bitmap font covering ASCII and Cyrillic (ISO 8859-5), scaled
+//! by an integer factor, with alignment, measurement and word wrapping.
+
+use embedded_graphics::mono_font::MonoTextStyle;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+use embedded_graphics::text::{Baseline, Text};
+
+pub use embedded_graphics::text::Alignment;
+
+use super::{EpdColor, Framebuffer};
+use crate::layout::FONT;
+
+/// Gap between wrapped lines, in font pixels
+const LINE_SPACING: u32 = 2;
+
+/// How text is drawn
+#[derive(Debug, Clone, Copy)]
+pub struct TextStyle {
+    pub color: EpdColor,
+    /// Every font pixel becomes a `scale` x `scale` block
+    pub scale: u32,
+    pub alignment: Alignment,
+}
+
+impl TextStyle {
+    /// Left-aligned text
+    pub const fn new(color: EpdColor, scale: u32) -> Self {
+        Self {
+            color,
+            scale,
+            alignment: Alignment::Left,
+        }
+    }
+
+    pub const fn aligned(self, alignment: Alignment) -> Self {
+        Self { alignment, ..self }
+    }
+
+    /// Height of one line in pixels
+    pub const fn line_height(&self) -> u32 {
+        FONT.character_size.height * self.scale
+    }
+
+    /// Distance between the tops of two wrapped lines in pixels
+    const fn line_pitch(&self) -> u32 {
+        (FONT.character_size.height + LINE_SPACING) * self.scale
+    }
+}
+
+/// Width and height of one line of `text` drawn at `scale`, in pixels
+pub fn measure(text: &str, scale: u32) -> Size {
+    Size::new(
+        columns_width(text.chars().count()) * scale,
+        FONT.character_size.height * scale,
+    )
+}
+
+/// Unscaled width of `columns` characters
+fn columns_width(columns: usize) -> u32 {
+    let advance = FONT.character_size.width + FONT.character_spacing;
+    (columns as u32 * advance).saturating_sub(FONT.character_spacing)
+}
+
+/// Characters of a line that fits into `width` pixels at `scale`
+fn columns_in(width: u32, scale: u32) -> usize {
+    let advance = FONT.character_size.width + FONT.character_spacing;
+    ((width / scale.max(1) + FONT.character_spacing) / advance) as usize
+}
+
+/// Draw one line of `text` with its top at `position.y`.
+/// `position.x` is the left edge, center or right edge depending on `style.alignment`.
+/// Returns the area covered by the line.
+pub fn draw_text(fb: &mut Framebuffer, text: &str, position: Point, style: TextStyle) -> Rectangle {
+    let size = measure(text, style.scale);
+    let left = match style.alignment {
+        Alignment::Left => position.x,
+        Alignment::Center => position.x - (size.width / 2) as i32,
+        Alignment::Right => position.x - size.width as i32,
+    };
+    let area = Rectangle::new(Point::new(left, position.y), size);
+    if !fb.touches(&area) {
+        return area;
+    }
+
+    let mut target = Scaled {
+        fb,
+        origin: area.top_left,
+        scale: style.scale,
+    };
+    let character_style = MonoTextStyle::new(FONT, style.color);
+    let Ok(_) =
+        Text::with_baseline(text, Point::zero(), character_style, Baseline::Top).draw(&mut target);
+    area
+}
+
+/// Draw `text` word-wrapped into `area`; `\n` starts a new line. Lines that do
+/// not fit below each other are dropped, words longer than a line are split.
+/// Returns the height used in pixels.
+pub fn draw_paragraph(fb: &mut Framebuffer, text: &str, area: Rectangle, style: TextStyle) -> u32 {
+    let x = match style.alignment {
+        Alignment::Left => area.top_left.x,
+        Alignment::Center => area.top_left.x + (area.size.width / 2) as i32,
+        Alignment::Right => area.top_left.x + area.size.width as i32,
+    };
+    let max_lines = match area.size.height.checked_sub(style.line_height()) {
+        Some(rest) => rest / style.line_pitch() + 1,
+        None => 0,
+    };
+
+    let mut lines = 0;
+    let mut y = area.top_left.y;
+    for line in wrap(text, columns_in(area.size.width, style.scale)).take(max_lines as usize) {
+        draw_text(fb, line, Point::new(x, y), style);
+        y += style.line_pitch() as i32;
+        lines += 1;
+    }
+
+    if lines == 0 {
+        0
+    } else {
+        (lines - 1) * style.line_pitch() + style.line_height()
+    }
+}
+
+/// Split `text` into lines of at most `columns` characters, breaking at spaces
+pub fn wrap(text: &str, columns: usize) -> Lines<'_> {
+    Lines {
+        rest: text,
+        columns,
+    }
+}
+
+/// Iterator over wrapped lines, see `wrap`
+pub struct Lines<'a> {
+    rest: &'a str,
+    columns: usize,
+}
+
+impl<'a> Iterator for Lines<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        if self.rest.is_empty() || self.columns == 0 {
+            return None;
+        }
+
+        let (paragraph, after) = match self.rest.split_once('\n') {
+            Some((paragraph, after)) => (paragraph, after),
+            None => (self.rest, ""),
+        };
+
+        // Byte offset of the first character that does not fit
+        let Some((limit, _)) = paragraph.char_indices().nth(self.columns) else {
+            self.rest = after;
+            return Some(paragraph.trim_end());
+        };
+
+        // Break at the last space before the limit, or inside an over-long word
+        let split = if paragraph[limit..].starts_with(' ') {
+            limit
+        } else {
+            match paragraph[..limit].rfind(' ') {
+                Some(space) if space > 0 => space,
+                _ => limit,
+            }
+        };
+        self.rest = self.rest[split..].trim_start_matches(' ');
+        Some(paragraph[..split].trim_end())
+    }
+}
+
+/// Draw target stretching every pixel into a `scale` x `scale` block at `origin`
+struct Scaled<'a, 'b> {
+    fb: &'a mut Framebuffer<'b>,
+    origin: Point,
+    scale: u32,
+}
+
+impl OriginDimensions for Scaled<'_, '_> {
+    fn size(&self) -> Size {
+        self.fb.size() / self.scale.max(1)
+    }
+}
+
+impl DrawTarget for Scaled<'_, '_> {
+    type Color = EpdColor;
+    type Error = core::convert::Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        let block = Size::new_equal(self.scale);
+        for Pixel(point, color) in pixels {
+            let top_left = self.origin + point * self.scale as i32;
+            self.fb
+                .fill_solid(&Rectangle::new(top_left, block), color)?;
+        }
+        Ok(())
+    }
+}
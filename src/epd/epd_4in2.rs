@@ -0,0 +1,468 @@
+//! Driver for 4.2 inch e-Paper display V2 (400x300 pixels, black/white)
+//! SSD1683 controller, aligned with the Waveshare Pico-ePaper-4.2 V2 C reference.
+//!
+//! Frames are 1bpp, eight pixels per byte with the leftmost pixel in the most
+//! significant bit; a set bit is white.
+
+use core::ops::Range;
+
+use embassy_futures::select::{Either, select};
+use embedded_hal_1::digital::{InputPin, OutputPin};
+use embedded_hal_async::delay::DelayNs;
+use embedded_hal_async::digital::Wait;
+use embedded_hal_async::spi::SpiDevice;
+
+use super::{Display, EpdError, FrameSource, Orientation, Window, fill_byte};
+
+/// Display dimensions
+pub const EPD_4IN2_WIDTH: u16 = 400;
+pub const EPD_4IN2_HEIGHT: u16 = 300;
+
+/// Bytes per panel row (1bpp, eight pixels per byte)
+const ROW_BYTES: usize = EPD_4IN2_WIDTH as usize / 8;
+
+/// Pixel depth of the frame buffer
+pub const EPD_4IN2_BITS_PER_PIXEL: usize = 1;
+
+/// RGB of each pixel value (0 = black, 1 = white)
+pub const EPD_4IN2_PALETTE: &[[u8; 3]] = &[[0, 0, 0], [255, 255, 255]];
+
+/// Longest wait for BUSY after a hardware reset before the panel is considered absent
+const RESET_BUSY_TIMEOUT_MS: u32 = 2_000;
+
+/// Longest wait for BUSY during a full refresh (~4 s typical)
+const REFRESH_BUSY_TIMEOUT_MS: u32 = 10_000;
+
+/// RAM holding the frame to show
+const NEW_DATA_RAM: u8 = 0x24;
+
+/// RAM holding the frame on screen, the partial update only drives the pixels that differ
+const OLD_DATA_RAM: u8 = 0x26;
+
+/// e-Paper driver structure
+pub struct Epd4in2<SPI, DC, RST, BUSY, DELAY> {
+    spi: SPI,
+    dc: DC,
+    rst: RST,
+    busy: BUSY,
+    delay: DELAY,
+    orientation: Orientation,
+}
+
+impl<SPI, DC, RST, BUSY, DELAY> Epd4in2<SPI, DC, RST, BUSY, DELAY>
+where
+    SPI: SpiDevice,
+    DC: OutputPin,
+    RST: OutputPin,
+    BUSY: InputPin + Wait,
+    DELAY: DelayNs,
+{
+    /// Create new driver instance. `spi` must assert the panel chip select per transaction.
+    pub fn new(spi: SPI, dc: DC, rst: RST, busy: BUSY, delay: DELAY) -> Self {
+        Self {
+            spi,
+            dc,
+            rst,
+            busy,
+            delay,
+            orientation: Orientation::default(),
+        }
+    }
+
+    /// Hardware reset (EPD_RST high->low->high with delays)
+    async fn reset(&mut self) -> Result<(), EpdError> {
+        self.rst.set_high().map_err(|_| EpdError::Pin)?;
+        self.delay.delay_ms(100).await;
+        self.rst.set_low().map_err(|_| EpdError::Pin)?;
+        self.delay.delay_ms(2).await;
+        self.rst.set_high().map_err(|_| EpdError::Pin)?;
+        self.delay.delay_ms(100).await;
+        Ok(())
+    }
+
+    /// Send command
+    async fn send_command(&mut self, reg: u8) -> Result<(), EpdError> {
+        self.dc.set_low().map_err(|_| EpdError::Pin)?;
+        self.spi.write(&[reg]).await.map_err(|_| EpdError::Spi)
+    }
+
+    /// Send data buffer in a single chip-select frame (one DMA transfer on hardware SPI)
+    async fn send_data_buffer(&mut self, data: &[u8]) -> Result<(), EpdError> {
+        self.dc.set_high().map_err(|_| EpdError::Pin)?;
+        self.spi.write(data).await.map_err(|_| EpdError::Spi)
+    }
+
+    /// Wait until the controller is idle (BUSY low), giving up after `timeout_ms`
+    async fn wait_idle(&mut self, timeout_ms: u32) -> Result<(), EpdError> {
+        defmt::debug!(
+            "wait_idle: starting, current state={}",
+            self.busy.is_high().unwrap_or(false)
+        );
+        match select(self.busy.wait_for_low(), self.delay.delay_ms(timeout_ms)).await {
+            Either::First(result) => result.map_err(|_| EpdError::Pin)?,
+            Either::Second(()) => {
+                defmt::warn!("wait_idle: still busy after {} ms", timeout_ms);
+                return Err(EpdError::BusyTimeout);
+            }
+        }
+        defmt::debug!("wait_idle: done");
+        Ok(())
+    }
+
+    /// Limit RAM access to the full-width panel `rows` and point the address
+    /// counter at the first of them
+    async fn set_ram_area(&mut self, rows: Range<u16>) -> Result<(), EpdError> {
+        let x_end = (EPD_4IN2_WIDTH - 1) >> 3;
+        let [start_low, start_high] = rows.start.to_le_bytes();
+        let [end_low, end_high] = (rows.end - 1).to_le_bytes();
+        self.send_command(0x44).await?;
+        self.send_data_buffer(&[0x00, x_end as u8]).await?;
+        self.send_command(0x45).await?;
+        self.send_data_buffer(&[start_low, start_high, end_low, end_high])
+            .await?;
+        self.send_command(0x4E).await?;
+        self.send_data_buffer(&[0x00]).await?;
+        self.send_command(0x4F).await?;
+        self.send_data_buffer(&[start_low, start_high]).await
+    }
+
+    /// Send panel `rows` of `frame` into `ram`
+    async fn write_rows(
+        &mut self,
+        ram: u8,
+        frame: &mut impl FrameSource,
+        rows: Range<u16>,
+    ) -> Result<(), EpdError> {
+        self.set_ram_area(rows.clone()).await?;
+        self.send_command(ram).await?;
+
+        // One panel row per transfer
+        let orientation = self.orientation;
+        let mut row = [0u8; ROW_BYTES];
+        for py in rows {
+            frame.panel_row(&orientation, py, &mut row);
+            self.send_data_buffer(&row).await?;
+        }
+        Ok(())
+    }
+
+    /// Full update: power on, refresh with the OTP waveform, power off
+    async fn turn_on_display(&mut self) -> Result<(), EpdError> {
+        self.send_command(0x22).await?;
+        self.send_data_buffer(&[0xF7]).await?;
+        self.send_command(0x20).await?;
+        self.wait_idle(REFRESH_BUSY_TIMEOUT_MS).await
+    }
+
+    /// Partial update (mirrors C `EPD_4IN2_V2_TurnOnDisplay_Partial`)
+    async fn turn_on_display_partial(&mut self) -> Result<(), EpdError> {
+        self.send_command(0x22).await?;
+        self.send_data_buffer(&[0xFF]).await?;
+        self.send_command(0x20).await?;
+        self.wait_idle(REFRESH_BUSY_TIMEOUT_MS).await
+    }
+}
+
+impl<SPI, DC, RST, BUSY, DELAY> Display for Epd4in2<SPI, DC, RST, BUSY, DELAY>
+where
+    SPI: SpiDevice,
+    DC: OutputPin,
+    RST: OutputPin,
+    BUSY: InputPin + Wait,
+    DELAY: DelayNs,
+{
+    fn orientation(&self) -> Orientation {
+        self.orientation
+    }
+
+    fn set_orientation(&mut self, orientation: Orientation) {
+        self.orientation = orientation;
+    }
+
+    /// Initialize display (sequence mirrors C `EPD_4IN2_V2_Init`)
+    async fn init(&mut self) -> Result<(), EpdError> {
+        self.reset().await?;
+        self.wait_idle(RESET_BUSY_TIMEOUT_MS)
+            .await
+            .map_err(|e| match e {
+                EpdError::BusyTimeout => EpdError::NotResponding,
+                e => e,
+            })?;
+
+        self.send_command(0x12).await?; // Software reset
+        self.wait_idle(RESET_BUSY_TIMEOUT_MS).await?;
+
+        self.send_command(0x21).await?; // Display update control
+        self.send_data_buffer(&[0x40, 0x00]).await?;
+
+        self.send_command(0x3C).await?; // Border waveform
+        self.send_data_buffer(&[0x05]).await?;
+
+        self.send_command(0x11).await?; // Data entry mode: X then Y increment
+        self.send_data_buffer(&[0x03]).await?;
+
+        self.set_ram_area(0..EPD_4IN2_HEIGHT).await?;
+        self.wait_idle(RESET_BUSY_TIMEOUT_MS).await
+    }
+
+    /// Clear screen; white and clean show as white, every other color as black
+    async fn clear(&mut self, color: u8) -> Result<(), EpdError> {
+        let row = [fill_byte(color); ROW_BYTES];
+        for ram in [NEW_DATA_RAM, OLD_DATA_RAM] {
+            self.set_ram_area(0..EPD_4IN2_HEIGHT).await?;
+            self.send_command(ram).await?;
+            for _y in 0..EPD_4IN2_HEIGHT {
+                self.send_data_buffer(&row).await?;
+            }
+        }
+
+        self.turn_on_display().await
+    }
+
+    /// Display a frame, 1bpp packed (eight pixels per byte), row-major
+    /// Image is rotated/mirrored according to `orientation()`
+    async fn display(&mut self, mut frame: impl FrameSource) -> Result<(), EpdError> {
+        self.write_rows(NEW_DATA_RAM, &mut frame, 0..EPD_4IN2_HEIGHT)
+            .await?;
+        // Reference for the next `display_window`
+        self.write_rows(OLD_DATA_RAM, &mut frame, 0..EPD_4IN2_HEIGHT)
+            .await?;
+        self.turn_on_display().await
+    }
+
+    /// Start a full-frame transfer into the black/white RAM
+    async fn begin_frame(&mut self) -> Result<(), EpdError> {
+        self.set_ram_area(0..EPD_4IN2_HEIGHT).await?;
+        self.send_command(NEW_DATA_RAM).await
+    }
+
+    /// Send frame bytes that are already in panel scan order (1bpp, rotated)
+    async fn write_frame(&mut self, data: &[u8]) -> Result<(), EpdError> {
+        self.send_data_buffer(data).await
+    }
+
+    /// Refresh the panel with the frame sent since `begin_frame`
+    async fn finish_frame(&mut self) -> Result<(), EpdError> {
+        self.turn_on_display().await
+    }
+
+    /// Partial update of the panel rows showing `window` (sequence mirrors C
+    /// `EPD_4IN2_V2_PartialDisplay`). Only these rows are sent, and only their
+    /// pixels that differ from the old-data RAM change, so the frame on screen
+    /// must have come from `display` or `clear` (streamed frames leave the
+    /// old-data RAM as it was). The partial settings last until the next `init`.
+    async fn display_window(
+        &mut self,
+        mut frame: impl FrameSource,
+        window: Window,
+    ) -> Result<(), EpdError> {
+        let rows = self.orientation.panel_rows(window)?;
+
+        self.send_command(0x21).await?; // Display update control: compare with the old data
+        self.send_data_buffer(&[0x00, 0x00]).await?;
+
+        self.send_command(0x3C).await?; // Border waveform: keep the border
+        self.send_data_buffer(&[0x80]).await?;
+
+        self.write_rows(NEW_DATA_RAM, &mut frame, rows.clone())
+            .await?;
+        self.turn_on_display_partial().await?;
+
+        // Reference for the next partial update
+        self.write_rows(OLD_DATA_RAM, &mut frame, rows).await
+    }
+
+    /// Enter deep sleep (only a hardware reset wakes the controller)
+    async fn sleep(&mut self) -> Result<(), EpdError> {
+        self.send_command(0x10).await?;
+        self.send_data_buffer(&[0x01]).await?;
+        self.delay.delay_ms(100).await;
+        self.rst.set_low().map_err(|_| EpdError::Pin)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec;
+    use std::vec::Vec;
+
+    use embassy_futures::block_on;
+
+    use super::*;
+    use crate::epd::mock::{Bus, Busy, Event};
+    use crate::epd::{BLACK, RED, Rotation, WHITE};
+
+    fn epd(bus: &Bus, busy: Busy) -> impl Display {
+        let (spi, dc, rst, busy, delay) = bus.parts(busy);
+        Epd4in2::new(spi, dc, rst, busy, delay)
+    }
+
+    /// Commands limiting RAM access to panel rows `first..=last`
+    fn ram_area(first: u8, last: u16) -> Vec<(u8, Vec<u8>)> {
+        let [end_low, end_high] = last.to_le_bytes();
+        vec![
+            (0x44, vec![0x00, 0x31]),
+            (0x45, vec![first, 0x00, end_low, end_high]),
+            (0x4E, vec![0x00]),
+            (0x4F, vec![first, 0x00]),
+        ]
+    }
+
+    /// Commands from `begin_frame` up to the frame data
+    fn frame_start() -> Vec<(u8, Vec<u8>)> {
+        ram_area(0, EPD_4IN2_HEIGHT - 1)
+    }
+
+    /// Commands of a full update
+    fn full_update() -> Vec<(u8, Vec<u8>)> {
+        vec![(0x22, vec![0xF7]), (0x20, vec![])]
+    }
+
+    #[test]
+    fn init_resets_and_configures_the_panel() {
+        let bus = Bus::new();
+        block_on(epd(&bus, Busy::Ready).init()).unwrap();
+
+        assert_eq!(
+            bus.events()[..6],
+            [
+                Event::Reset(true),
+                Event::DelayMs(100),
+                Event::Reset(false),
+                Event::DelayMs(2),
+                Event::Reset(true),
+                Event::DelayMs(100),
+            ]
+        );
+        let mut expected = vec![
+            (0x12, vec![]),
+            (0x21, vec![0x40, 0x00]),
+            (0x3C, vec![0x05]),
+            (0x11, vec![0x03]),
+        ];
+        expected.extend(frame_start());
+        assert_eq!(bus.commands(), expected);
+    }
+
+    #[test]
+    fn init_without_busy_reports_not_responding() {
+        let bus = Bus::new();
+        let result = block_on(epd(&bus, Busy::Stuck).init());
+
+        assert_eq!(result, Err(EpdError::NotResponding));
+        assert_eq!(
+            bus.events().last(),
+            Some(&Event::DelayMs(RESET_BUSY_TIMEOUT_MS))
+        );
+        assert!(bus.commands().is_empty());
+    }
+
+    #[test]
+    fn clear_shows_colors_as_black_or_white() {
+        for (color, byte) in [(WHITE, 0xFF), (BLACK, 0x00), (RED, 0x00)] {
+            let bus = Bus::new();
+            block_on(epd(&bus, Busy::Ready).clear(color)).unwrap();
+
+            let mut expected = frame_start();
+            expected.push((0x24, vec![byte; ROW_BYTES * usize::from(EPD_4IN2_HEIGHT)]));
+            expected.extend(frame_start());
+            expected.push((0x26, vec![byte; ROW_BYTES * usize::from(EPD_4IN2_HEIGHT)]));
+            expected.extend(full_update());
+            assert_eq!(bus.commands(), expected);
+        }
+    }
+
+    #[test]
+    fn display_sends_the_frame_in_panel_order() {
+        let frame: Vec<u8> = (0..ROW_BYTES * usize::from(EPD_4IN2_HEIGHT))
+            .map(|i| i as u8)
+            .collect();
+        let bus = Bus::new();
+        let mut epd = epd(&bus, Busy::Ready);
+        epd.set_orientation(Orientation::new(Rotation::Deg0, false));
+        block_on(epd.display(frame.as_slice())).unwrap();
+
+        let mut expected = frame_start();
+        expected.push((0x24, frame.clone()));
+        expected.extend(frame_start());
+        expected.push((0x26, frame));
+        expected.extend(full_update());
+        assert_eq!(bus.commands(), expected);
+    }
+
+    #[test]
+    fn display_window_updates_only_the_covered_rows() {
+        let frame: Vec<u8> = (0..ROW_BYTES * usize::from(EPD_4IN2_HEIGHT))
+            .map(|i| i as u8)
+            .collect();
+        let bus = Bus::new();
+        let mut epd = epd(&bus, Busy::Ready);
+        epd.set_orientation(Orientation::new(Rotation::Deg0, false));
+        let window = Window::new(8, 16, 64, 32);
+        block_on(epd.display_window(frame.as_slice(), window)).unwrap();
+
+        let rows = frame[16 * ROW_BYTES..48 * ROW_BYTES].to_vec();
+        let mut expected = vec![(0x21, vec![0x00, 0x00]), (0x3C, vec![0x80])];
+        expected.extend(ram_area(16, 47));
+        expected.push((0x24, rows.clone()));
+        expected.extend([(0x22, vec![0xFF]), (0x20, vec![])]);
+        expected.extend(ram_area(16, 47));
+        expected.push((0x26, rows));
+        assert_eq!(bus.commands(), expected);
+    }
+
+    #[test]
+    fn display_window_follows_the_orientation() {
+        // Upside down, the top 20 image rows are the bottom 20 panel rows
+        let frame = vec![0xFF; ROW_BYTES * usize::from(EPD_4IN2_HEIGHT)];
+        let bus = Bus::new();
+        let mut epd = epd(&bus, Busy::Ready);
+        epd.set_orientation(Orientation::new(Rotation::Deg180, false));
+        let window = Window::new(0, 0, EPD_4IN2_WIDTH, 20);
+        block_on(epd.display_window(frame.as_slice(), window)).unwrap();
+
+        let commands = bus.commands();
+        assert_eq!(commands[3], (0x45, vec![0x18, 0x01, 0x2B, 0x01]));
+        assert_eq!(commands[6], (0x24, vec![0xFF; 20 * ROW_BYTES]));
+    }
+
+    #[test]
+    fn display_window_rejects_a_window_outside_the_panel() {
+        let frame = [0xFF; 16];
+        let bus = Bus::new();
+        let window = Window::new(392, 0, 16, 8);
+        let result = block_on(epd(&bus, Busy::Ready).display_window(&frame[..], window));
+
+        assert_eq!(result, Err(EpdError::InvalidWindow));
+        assert!(bus.events().is_empty());
+    }
+
+    #[test]
+    fn stuck_refresh_times_out() {
+        let bus = Bus::new();
+        let result = block_on(epd(&bus, Busy::Stuck).clear(WHITE));
+
+        assert_eq!(result, Err(EpdError::BusyTimeout));
+        assert_eq!(bus.commands().last(), Some(&(0x20, vec![])));
+        assert_eq!(
+            bus.events().last(),
+            Some(&Event::DelayMs(REFRESH_BUSY_TIMEOUT_MS))
+        );
+    }
+
+    #[test]
+    fn sleep_sends_deep_sleep_and_holds_reset() {
+        let bus = Bus::new();
+        block_on(epd(&bus, Busy::Ready).sleep()).unwrap();
+
+        assert_eq!(
+            bus.events(),
+            [
+                Event::Command(vec![0x10]),
+                Event::Data(vec![0x01]),
+                Event::DelayMs(100),
+                Event::Reset(false),
+            ]
+        );
+    }
+}
@@ -0,0 +1,475 @@
+//! Driver for 5.65 inch e-Paper display (600x448 pixels)
+//! Generic over embedded-hal 1.0 traits, aligned with Waveshare C reference.
+//!
+//! The firmware plugs in SPI1 + DMA or a bit-banged bus (see `EpdBus`), but any
+//! `SpiDevice`/`OutputPin`/`InputPin + Wait`/`DelayNs` implementation works,
+//! including recording mocks on the host.
+
+use embassy_futures::select::{Either, select};
+use embedded_hal_1::digital::{InputPin, OutputPin};
+use embedded_hal_async::delay::DelayNs;
+use embedded_hal_async::digital::Wait;
+use embedded_hal_async::spi::SpiDevice;
+
+use super::{Display, EpdError, FrameSource, Orientation, Window};
+
+/// Display dimensions
+pub const EPD_5IN65F_WIDTH: u16 = 600;
+pub const EPD_5IN65F_HEIGHT: u16 = 448;
+
+/// Bytes per panel row (4bpp, two pixels per byte)
+const ROW_BYTES: usize = EPD_5IN65F_WIDTH as usize / 2;
+
+// Remaining 3-bit color indices from lib/epd_5in65f.h (black, white, red and
+// clean are shared, see `crate::epd`)
+/*
+pub const EPD_5IN65F_GREEN: u8 = 0x2;
+pub const EPD_5IN65F_BLUE: u8 = 0x3;
+pub const EPD_5IN65F_YELLOW: u8 = 0x5;
+pub const EPD_5IN65F_ORANGE: u8 = 0x6;
+*/
+
+/// Pixel depth of the frame buffer (two pixels per byte)
+pub const EPD_5IN65F_BITS_PER_PIXEL: usize = 4;
+
+/// Approximate RGB of each color index (clean shows as white)
+pub const EPD_5IN65F_PALETTE: &[[u8; 3]] = &[
+    [0, 0, 0],
+    [255, 255, 255],
+    [0, 255, 0],
+    [0, 0, 255],
+    [255, 0, 0],
+    [255, 255, 0],
+    [255, 128, 0],
+    [255, 255, 255],
+];
+
+/// Longest wait for BUSY after a hardware reset before the panel is considered absent
+const RESET_BUSY_TIMEOUT_MS: u32 = 2_000;
+
+/// Longest wait for BUSY around power on/off and a full refresh (~12 s typical on ACeP)
+const REFRESH_BUSY_TIMEOUT_MS: u32 = 40_000;
+
+/// e-Paper driver structure
+pub struct Epd5in65f<SPI, DC, RST, BUSY, DELAY> {
+    spi: SPI,
+    dc: DC,
+    rst: RST,
+    busy: BUSY,
+    delay: DELAY,
+    orientation: Orientation,
+}
+
+impl<SPI, DC, RST, BUSY, DELAY> Epd5in65f<SPI, DC, RST, BUSY, DELAY>
+where
+    SPI: SpiDevice,
+    DC: OutputPin,
+    RST: OutputPin,
+    BUSY: InputPin + Wait,
+    DELAY: DelayNs,
+{
+    /// Create new driver instance. `spi` must assert the panel chip select per transaction.
+    pub fn new(spi: SPI, dc: DC, rst: RST, busy: BUSY, delay: DELAY) -> Self {
+        Self {
+            spi,
+            dc,
+            rst,
+            busy,
+            delay,
+            orientation: Orientation::default(),
+        }
+    }
+
+    /// Software reset (EPD_RST high->low->high with delays)
+    async fn reset(&mut self) -> Result<(), EpdError> {
+        self.rst.set_high().map_err(|_| EpdError::Pin)?;
+        self.delay.delay_ms(200).await;
+        self.rst.set_low().map_err(|_| EpdError::Pin)?;
+        self.delay.delay_ms(2).await;
+        self.rst.set_high().map_err(|_| EpdError::Pin)?;
+        self.delay.delay_ms(200).await;
+        Ok(())
+    }
+
+    /// Send command
+    async fn send_command(&mut self, reg: u8) -> Result<(), EpdError> {
+        self.dc.set_low().map_err(|_| EpdError::Pin)?;
+        self.spi.write(&[reg]).await.map_err(|_| EpdError::Spi)
+    }
+
+    /// Send data byte
+    async fn send_data(&mut self, data: u8) -> Result<(), EpdError> {
+        self.send_data_buffer(&[data]).await
+    }
+
+    /// Send data buffer in a single chip-select frame (one DMA transfer on hardware SPI)
+    async fn send_data_buffer(&mut self, data: &[u8]) -> Result<(), EpdError> {
+        self.dc.set_high().map_err(|_| EpdError::Pin)?;
+        self.spi.write(data).await.map_err(|_| EpdError::Spi)
+    }
+
+    /// Wait until BUSY becomes high, giving up after `timeout_ms`
+    async fn wait_busy_high(&mut self, timeout_ms: u32) -> Result<(), EpdError> {
+        defmt::debug!(
+            "wait_busy_high: starting, current state={}",
+            self.busy.is_high().unwrap_or(false)
+        );
+        match select(self.busy.wait_for_high(), self.delay.delay_ms(timeout_ms)).await {
+            Either::First(result) => result.map_err(|_| EpdError::Pin)?,
+            Either::Second(()) => {
+                defmt::warn!("wait_busy_high: no change after {} ms", timeout_ms);
+                return Err(EpdError::BusyTimeout);
+            }
+        }
+        defmt::debug!("wait_busy_high: done");
+        Ok(())
+    }
+
+    /// Wait until BUSY becomes low, giving up after `timeout_ms`
+    async fn wait_busy_low(&mut self, timeout_ms: u32) -> Result<(), EpdError> {
+        defmt::debug!(
+            "wait_busy_low: starting, current state={}",
+            self.busy.is_high().unwrap_or(false)
+        );
+        match select(self.busy.wait_for_low(), self.delay.delay_ms(timeout_ms)).await {
+            Either::First(result) => result.map_err(|_| EpdError::Pin)?,
+            Either::Second(()) => {
+                defmt::warn!("wait_busy_low: no change after {} ms", timeout_ms);
+                return Err(EpdError::BusyTimeout);
+            }
+        }
+        defmt::debug!("wait_busy_low: done");
+        Ok(())
+    }
+
+    /// Set panel resolution (600x448) before a frame transfer
+    async fn set_resolution(&mut self) -> Result<(), EpdError> {
+        self.send_command(0x61).await?;
+        self.send_data_buffer(&[0x02, 0x58, 0x01, 0xC0]).await
+    }
+
+    /// Power on, refresh and power off after the frame data has been sent
+    async fn refresh(&mut self) -> Result<(), EpdError> {
+        self.send_command(0x04).await?;
+        self.wait_busy_high(REFRESH_BUSY_TIMEOUT_MS).await?;
+        self.send_command(0x12).await?;
+        self.wait_busy_high(REFRESH_BUSY_TIMEOUT_MS).await?;
+        self.send_command(0x02).await?;
+        self.wait_busy_low(REFRESH_BUSY_TIMEOUT_MS).await
+    }
+}
+
+impl<SPI, DC, RST, BUSY, DELAY> Display for Epd5in65f<SPI, DC, RST, BUSY, DELAY>
+where
+    SPI: SpiDevice,
+    DC: OutputPin,
+    RST: OutputPin,
+    BUSY: InputPin + Wait,
+    DELAY: DelayNs,
+{
+    fn orientation(&self) -> Orientation {
+        self.orientation
+    }
+
+    fn set_orientation(&mut self, orientation: Orientation) {
+        self.orientation = orientation;
+    }
+
+    /// Initialize display (sequence mirrors C)
+    async fn init(&mut self) -> Result<(), EpdError> {
+        self.reset().await?;
+        self.wait_busy_high(RESET_BUSY_TIMEOUT_MS)
+            .await
+            .map_err(|e| match e {
+                EpdError::BusyTimeout => EpdError::NotResponding,
+                e => e,
+            })?;
+
+        self.send_command(0x00).await?;
+        self.send_data(0xEF).await?;
+        self.send_data(0x08).await?;
+
+        self.send_command(0x01).await?;
+        self.send_data(0x37).await?;
+        self.send_data(0x00).await?;
+        self.send_data(0x23).await?;
+        self.send_data(0x23).await?;
+
+        self.send_command(0x03).await?;
+        self.send_data(0x00).await?;
+
+        self.send_command(0x06).await?;
+        self.send_data(0xC7).await?;
+        self.send_data(0xC7).await?;
+        self.send_data(0x1D).await?;
+
+        self.send_command(0x30).await?;
+        self.send_data(0x3C).await?;
+
+        self.send_command(0x41).await?;
+        self.send_data(0x00).await?;
+
+        self.send_command(0x50).await?;
+        self.send_data(0x37).await?;
+
+        self.send_command(0x60).await?;
+        self.send_data(0x22).await?;
+
+        self.set_resolution().await?;
+
+        self.send_command(0xE3).await?;
+        self.send_data(0xAA).await?;
+
+        self.delay.delay_ms(100).await;
+
+        self.send_command(0x50).await?;
+        self.send_data(0x37).await
+    }
+
+    /// Clear screen to given 3-bit color index
+    async fn clear(&mut self, color: u8) -> Result<(), EpdError> {
+        self.set_resolution().await?;
+        self.send_command(0x10).await?;
+
+        // Each byte is two pixels: high nibble and low nibble
+        let byte = ((color & 0x0F) << 4) | (color & 0x0F);
+        let row = [byte; ROW_BYTES];
+
+        for _y in 0..EPD_5IN65F_HEIGHT {
+            self.send_data_buffer(&row).await?;
+        }
+
+        self.refresh().await?;
+        self.delay.delay_ms(500).await;
+        Ok(())
+    }
+
+    /// Display a frame, 4bpp packed (two pixels per byte), row-major
+    /// Image is rotated/mirrored according to `orientation()`
+    async fn display(&mut self, mut frame: impl FrameSource) -> Result<(), EpdError> {
+        self.begin_frame().await?;
+
+        // One panel row per transfer
+        let orientation = self.orientation;
+        let mut row = [0u8; ROW_BYTES];
+        for py in 0..EPD_5IN65F_HEIGHT {
+            frame.panel_row(&orientation, py, &mut row);
+            self.write_frame(&row).await?;
+        }
+
+        self.finish_frame().await
+    }
+
+    /// Start a full-frame transfer; follow with `write_frame` and `finish_frame`
+    async fn begin_frame(&mut self) -> Result<(), EpdError> {
+        self.set_resolution().await?;
+        self.send_command(0x10).await
+    }
+
+    /// Send frame bytes that are already in panel scan order (4bpp, rotated)
+    async fn write_frame(&mut self, data: &[u8]) -> Result<(), EpdError> {
+        self.send_data_buffer(data).await
+    }
+
+    /// Refresh the panel with the frame sent since `begin_frame`
+    async fn finish_frame(&mut self) -> Result<(), EpdError> {
+        self.refresh().await?;
+        self.delay.delay_ms(200).await;
+        Ok(())
+    }
+
+    /// The ACeP controller's documented commands only refresh the whole panel
+    async fn display_window(
+        &mut self,
+        _frame: impl FrameSource,
+        _window: Window,
+    ) -> Result<(), EpdError> {
+        Err(EpdError::Unsupported)
+    }
+
+    /// Enter sleep mode
+    async fn sleep(&mut self) -> Result<(), EpdError> {
+        self.delay.delay_ms(100).await;
+        self.send_command(0x07).await?;
+        self.send_data(0xA5).await?;
+        self.delay.delay_ms(100).await;
+        self.rst.set_low().map_err(|_| EpdError::Pin) // Reset
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec;
+    use std::vec::Vec;
+
+    use embassy_futures::block_on;
+
+    use super::*;
+    use crate::epd::mock::{Bus, Busy, Event};
+    use crate::epd::{Rotation, WHITE};
+
+    fn epd(bus: &Bus, busy: Busy) -> impl Display {
+        let (spi, dc, rst, busy, delay) = bus.parts(busy);
+        Epd5in65f::new(spi, dc, rst, busy, delay)
+    }
+
+    /// Commands of one refresh: power on, refresh, power off
+    fn refresh_commands() -> Vec<(u8, Vec<u8>)> {
+        vec![(0x04, vec![]), (0x12, vec![]), (0x02, vec![])]
+    }
+
+    #[test]
+    fn init_resets_and_configures_the_panel() {
+        let bus = Bus::new();
+        block_on(epd(&bus, Busy::Ready).init()).unwrap();
+
+        assert_eq!(
+            bus.events()[..6],
+            [
+                Event::Reset(true),
+                Event::DelayMs(200),
+                Event::Reset(false),
+                Event::DelayMs(2),
+                Event::Reset(true),
+                Event::DelayMs(200),
+            ]
+        );
+        assert_eq!(
+            bus.commands(),
+            [
+                (0x00, vec![0xEF, 0x08]),
+                (0x01, vec![0x37, 0x00, 0x23, 0x23]),
+                (0x03, vec![0x00]),
+                (0x06, vec![0xC7, 0xC7, 0x1D]),
+                (0x30, vec![0x3C]),
+                (0x41, vec![0x00]),
+                (0x50, vec![0x37]),
+                (0x60, vec![0x22]),
+                (0x61, vec![0x02, 0x58, 0x01, 0xC0]),
+                (0xE3, vec![0xAA]),
+                (0x50, vec![0x37]),
+            ]
+        );
+    }
+
+    #[test]
+    fn init_without_busy_reports_not_responding() {
+        let bus = Bus::new();
+        let result = block_on(epd(&bus, Busy::Stuck).init());
+
+        assert_eq!(result, Err(EpdError::NotResponding));
+        assert_eq!(
+            bus.events().last(),
+            Some(&Event::DelayMs(RESET_BUSY_TIMEOUT_MS))
+        );
+        assert!(bus.commands().is_empty());
+    }
+
+    #[test]
+    fn clear_fills_every_row_with_the_color() {
+        let bus = Bus::new();
+        block_on(epd(&bus, Busy::Ready).clear(WHITE)).unwrap();
+
+        let mut expected = vec![
+            (0x61, vec![0x02, 0x58, 0x01, 0xC0]),
+            (0x10, vec![0x11; ROW_BYTES * usize::from(EPD_5IN65F_HEIGHT)]),
+        ];
+        expected.extend(refresh_commands());
+        assert_eq!(bus.commands(), expected);
+        assert_eq!(bus.events().last(), Some(&Event::DelayMs(500)));
+    }
+
+    #[test]
+    fn display_sends_the_frame_in_panel_order() {
+        let frame: Vec<u8> = (0..ROW_BYTES * usize::from(EPD_5IN65F_HEIGHT))
+            .map(|i| i as u8)
+            .collect();
+        let bus = Bus::new();
+        let mut epd = epd(&bus, Busy::Ready);
+        epd.set_orientation(Orientation::new(Rotation::Deg0, false));
+        block_on(epd.display(frame.as_slice())).unwrap();
+
+        let mut expected = vec![(0x61, vec![0x02, 0x58, 0x01, 0xC0]), (0x10, frame)];
+        expected.extend(refresh_commands());
+        assert_eq!(bus.commands(), expected);
+    }
+
+    #[test]
+    fn display_upside_down_reverses_the_pixels() {
+        // Only the first image byte is set: pixels 0 and 1 of row 0
+        let mut frame = vec![0x11; ROW_BYTES * usize::from(EPD_5IN65F_HEIGHT)];
+        frame[0] = 0x23;
+        let bus = Bus::new();
+        let mut epd = epd(&bus, Busy::Ready);
+        epd.set_orientation(Orientation::new(Rotation::Deg180, false));
+        block_on(epd.display(frame.as_slice())).unwrap();
+
+        let commands = bus.commands();
+        let sent = &commands[1].1;
+        assert_eq!(sent.last(), Some(&0x32));
+        assert!(sent[..sent.len() - 1].iter().all(|&b| b == 0x11));
+    }
+
+    #[test]
+    fn streamed_frame_is_sent_as_written() {
+        let bus = Bus::new();
+        let mut epd = epd(&bus, Busy::Ready);
+        block_on(async {
+            epd.begin_frame().await?;
+            epd.write_frame(&[0x12, 0x34]).await?;
+            epd.write_frame(&[0x56]).await?;
+            epd.finish_frame().await
+        })
+        .unwrap();
+
+        let mut expected = vec![
+            (0x61, vec![0x02, 0x58, 0x01, 0xC0]),
+            (0x10, vec![0x12, 0x34, 0x56]),
+        ];
+        expected.extend(refresh_commands());
+        assert_eq!(bus.commands(), expected);
+        assert_eq!(bus.events().last(), Some(&Event::DelayMs(200)));
+    }
+
+    #[test]
+    fn stuck_refresh_times_out() {
+        let bus = Bus::new();
+        let result = block_on(epd(&bus, Busy::Stuck).clear(WHITE));
+
+        assert_eq!(result, Err(EpdError::BusyTimeout));
+        // Power on is sent, the refresh never starts
+        assert_eq!(bus.commands().last(), Some(&(0x04, vec![])));
+        assert_eq!(
+            bus.events().last(),
+            Some(&Event::DelayMs(REFRESH_BUSY_TIMEOUT_MS))
+        );
+    }
+
+    #[test]
+    fn display_window_is_unsupported() {
+        let frame = vec![0x11; ROW_BYTES * usize::from(EPD_5IN65F_HEIGHT)];
+        let bus = Bus::new();
+        let window = Window::new(8, 16, 64, 32);
+        let result = block_on(epd(&bus, Busy::Ready).display_window(frame.as_slice(), window));
+
+        assert_eq!(result, Err(EpdError::Unsupported));
+        assert!(bus.events().is_empty());
+    }
+
+    #[test]
+    fn sleep_sends_deep_sleep_and_holds_reset() {
+        let bus = Bus::new();
+        block_on(epd(&bus, Busy::Ready).sleep()).unwrap();
+
+        assert_eq!(
+            bus.events(),
+            [
+                Event::DelayMs(100),
+                Event::Command(vec![0x07]),
+                Event::Data(vec![0xA5]),
+                Event::DelayMs(100),
+                Event::Reset(false),
+            ]
+        );
+    }
+}
@@ -0,0 +1,209 @@
+//! Recording embedded-hal mocks for the panel driver tests
+//!
+//! Every pin change, SPI write and delay lands in one shared log, so a test
+//! can assert the exact command/data sequence a driver puts on the bus.
+
+use std::cell::{Cell, RefCell};
+use std::convert::Infallible;
+use std::rc::Rc;
+use std::vec::Vec;
+
+use embedded_hal_1::digital::{ErrorType as PinErrorType, InputPin, OutputPin};
+use embedded_hal_1::spi::{ErrorType as SpiErrorType, Operation};
+use embedded_hal_async::delay::DelayNs;
+use embedded_hal_async::digital::Wait;
+use embedded_hal_async::spi::SpiDevice;
+
+/// What happened on the wires, in order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// Bytes written with DC low
+    Command(Vec<u8>),
+    /// Bytes written with DC high
+    Data(Vec<u8>),
+    /// RST driven to this level
+    Reset(bool),
+    /// A delay that ran to completion (not one that lost a race against BUSY)
+    DelayMs(u32),
+}
+
+/// How the BUSY line behaves
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Busy {
+    /// Reaches the awaited level at once
+    Ready,
+    /// Never changes, every wait runs into its timeout
+    Stuck,
+}
+
+/// Shared log and line state behind the mocks
+#[derive(Clone)]
+pub struct Bus {
+    events: Rc<RefCell<Vec<Event>>>,
+    dc: Rc<Cell<bool>>,
+}
+
+impl Bus {
+    pub fn new() -> Self {
+        Self {
+            events: Rc::default(),
+            dc: Rc::default(),
+        }
+    }
+
+    pub fn events(&self) -> Vec<Event> {
+        self.events.borrow().clone()
+    }
+
+    /// Each command with the data bytes sent until the next command
+    pub fn commands(&self) -> Vec<(u8, Vec<u8>)> {
+        let mut commands: Vec<(u8, Vec<u8>)> = Vec::new();
+        for event in self.events.borrow().iter() {
+            match event {
+                Event::Command(bytes) => commands.extend(bytes.iter().map(|&c| (c, Vec::new()))),
+                Event::Data(bytes) => commands
+                    .last_mut()
+                    .expect("data before the first command")
+                    .1
+                    .extend_from_slice(bytes),
+                Event::Reset(_) | Event::DelayMs(_) => {}
+            }
+        }
+        commands
+    }
+
+    /// Mocks for `Epd*::new`: SPI device, DC, RST, BUSY and delay
+    pub fn parts(&self, busy: Busy) -> (Spi, Dc, Rst, BusyPin, Delay) {
+        (
+            Spi(self.clone()),
+            Dc(self.clone()),
+            Rst(self.clone()),
+            BusyPin(busy),
+            Delay(self.clone()),
+        )
+    }
+
+    fn push(&self, event: Event) {
+        self.events.borrow_mut().push(event);
+    }
+}
+
+pub struct Spi(Bus);
+
+impl SpiErrorType for Spi {
+    type Error = Infallible;
+}
+
+impl SpiDevice for Spi {
+    async fn transaction(
+        &mut self,
+        operations: &mut [Operation<'_, u8>],
+    ) -> Result<(), Infallible> {
+        for operation in operations {
+            if let Operation::Write(bytes) = operation {
+                let bytes = bytes.to_vec();
+                self.0.push(if self.0.dc.get() {
+                    Event::Data(bytes)
+                } else {
+                    Event::Command(bytes)
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+pub struct Dc(Bus);
+
+impl PinErrorType for Dc {
+    type Error = Infallible;
+}
+
+impl OutputPin for Dc {
+    fn set_low(&mut self) -> Result<(), Infallible> {
+        self.0.dc.set(false);
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Infallible> {
+        self.0.dc.set(true);
+        Ok(())
+    }
+}
+
+pub struct Rst(Bus);
+
+impl PinErrorType for Rst {
+    type Error = Infallible;
+}
+
+impl OutputPin for Rst {
+    fn set_low(&mut self) -> Result<(), Infallible> {
+        self.0.push(Event::Reset(false));
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Infallible> {
+        self.0.push(Event::Reset(true));
+        Ok(())
+    }
+}
+
+pub struct BusyPin(Busy);
+
+impl BusyPin {
+    async fn reach(&self) -> Result<(), Infallible> {
+        match self.0 {
+            Busy::Ready => Ok(()),
+            Busy::Stuck => core::future::pending().await,
+        }
+    }
+}
+
+impl PinErrorType for BusyPin {
+    type Error = Infallible;
+}
+
+impl InputPin for BusyPin {
+    fn is_high(&mut self) -> Result<bool, Infallible> {
+        Ok(false)
+    }
+
+    fn is_low(&mut self) -> Result<bool, Infallible> {
+        Ok(true)
+    }
+}
+
+impl Wait for BusyPin {
+    async fn wait_for_high(&mut self) -> Result<(), Infallible> {
+        self.reach().await
+    }
+
+    async fn wait_for_low(&mut self) -> Result<(), Infallible> {
+        self.reach().await
+    }
+
+    async fn wait_for_rising_edge(&mut self) -> Result<(), Infallible> {
+        self.reach().await
+    }
+
+    async fn wait_for_falling_edge(&mut self) -> Result<(), Infallible> {
+        self.reach().await
+    }
+
+    async fn wait_for_any_edge(&mut self) -> Result<(), Infallible> {
+        self.reach().await
+    }
+}
+
+pub struct Delay(Bus);
+
+impl DelayNs for Delay {
+    async fn delay_ns(&mut self, ns: u32) {
+        self.0.push(Event::DelayMs(ns / 1_000_000));
+    }
+
+    async fn delay_ms(&mut self, ms: u32) {
+        self.0.push(Event::DelayMs(ms));
+    }
+}
@@ -0,0 +1,396 @@
+//! e-Paper panel drivers behind a common `Display` trait
+//!
+//! Exactly one panel is compiled in, chosen by cargo feature:
+//! - default: Waveshare 5.65" ACeP 7-color (600x448, 4bpp)
+//! - `panel-4in2`: Waveshare 4.2" V2 black/white (400x300, 1bpp)
+//!
+//! Frame sizes, the download size check and the image orientation all follow
+//! the selected panel through the constants re-exported here.
+
+use core::ops::Range;
+
+#[cfg(feature = "panel-4in2")]
+mod epd_4in2;
+#[cfg(not(feature = "panel-4in2"))]
+mod epd_5in65f;
+#[cfg(test)]
+mod mock;
+
+#[cfg(feature = "panel-4in2")]
+pub use epd_4in2::{
+    EPD_4IN2_BITS_PER_PIXEL as BITS_PER_PIXEL, EPD_4IN2_HEIGHT as PANEL_HEIGHT,
+    EPD_4IN2_PALETTE as PALETTE, EPD_4IN2_WIDTH as PANEL_WIDTH, Epd4in2 as Panel,
+};
+#[cfg(not(feature = "panel-4in2"))]
+pub use epd_5in65f::{
+    EPD_5IN65F_BITS_PER_PIXEL as BITS_PER_PIXEL, EPD_5IN65F_HEIGHT as PANEL_HEIGHT,
+    EPD_5IN65F_PALETTE as PALETTE, EPD_5IN65F_WIDTH as PANEL_WIDTH, Epd5in65f as Panel,
+};
+
+/// Color indices used by the firmware (ACeP numbering, see lib/epd_5in65f.h).
+/// Black/white panels show white and clean as white, everything else as black.
+pub const BLACK: u8 = 0x0;
+pub const WHITE: u8 = 0x1;
+pub const RED: u8 = 0x4;
+/// Clean: drives all pigments to their rest position (used for deep-clean passes)
+pub const CLEAN: u8 = 0x7;
+
+/// Pixels packed into one frame byte (most significant bits first)
+pub const PIXELS_PER_BYTE: usize = 8 / BITS_PER_PIXEL;
+
+/// Mask of one pixel value
+const PIXEL_MASK: u8 = ((1u16 << BITS_PER_PIXEL) - 1) as u8;
+
+/// Bytes per row of a `width` pixels wide image (rows start on a byte boundary)
+pub const fn row_bytes(width: u16) -> usize {
+    (width as usize * BITS_PER_PIXEL).div_ceil(8)
+}
+
+/// Bytes per panel row
+pub const PANEL_ROW_BYTES: usize = row_bytes(PANEL_WIDTH);
+
+/// Bytes of one frame in panel scan order
+pub const PANEL_FRAME_BYTES: usize = PANEL_ROW_BYTES * PANEL_HEIGHT as usize;
+
+/// Largest image in any orientation (portrait rows may need a padding byte)
+pub const MAX_FRAME_BYTES: usize = {
+    let portrait = row_bytes(PANEL_HEIGHT) * PANEL_WIDTH as usize;
+    if portrait > PANEL_FRAME_BYTES {
+        portrait
+    } else {
+        PANEL_FRAME_BYTES
+    }
+};
+
+/// Panel pixel value for a color index
+pub const fn native_color(color: u8) -> u8 {
+    if BITS_PER_PIXEL >= 4 {
+        color & PIXEL_MASK
+    } else {
+        (color == WHITE || color == CLEAN) as u8
+    }
+}
+
+/// Frame byte with every pixel set to `color`
+pub const fn fill_byte(color: u8) -> u8 {
+    let value = native_color(color);
+    let mut byte = 0u8;
+    let mut i = 0;
+    while i < PIXELS_PER_BYTE {
+        byte = (byte << BITS_PER_PIXEL) | value;
+        i += 1;
+    }
+    byte
+}
+
+/// Mirror the pixel order within one frame byte
+const fn reverse_pixels(byte: u8) -> u8 {
+    if BITS_PER_PIXEL == 4 {
+        byte.rotate_left(4)
+    } else {
+        byte.reverse_bits()
+    }
+}
+
+/// Read the pixel at (`x`, `y`) from a row-major image with `stride` bytes per row
+fn get_pixel(image: &[u8], stride: usize, (x, y): (u16, u16)) -> u8 {
+    let byte = image
+        .get(x as usize / PIXELS_PER_BYTE + stride * y as usize)
+        .copied()
+        .unwrap_or(fill_byte(WHITE));
+    let shift = (PIXELS_PER_BYTE - 1 - x as usize % PIXELS_PER_BYTE) * BITS_PER_PIXEL;
+    (byte >> shift) & PIXEL_MASK
+}
+
+/// Write the panel pixel `value` at (`x`, `y`) of a row-major image with `stride` bytes per row
+pub fn put_pixel(image: &mut [u8], stride: usize, (x, y): (u16, u16), value: u8) {
+    // IMPORTANT: compute in usize to avoid u16 overflow on row offset.
+    let index = x as usize / PIXELS_PER_BYTE + stride * y as usize;
+    let shift = (PIXELS_PER_BYTE - 1 - x as usize % PIXELS_PER_BYTE) * BITS_PER_PIXEL;
+    if let Some(byte) = image.get_mut(index) {
+        *byte = (*byte & !(PIXEL_MASK << shift)) | ((value & PIXEL_MASK) << shift);
+    }
+}
+
+/// Errors reported by the e-Paper driver
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum EpdError {
+    /// SPI transfer failed
+    Spi,
+    /// Reading or driving a control pin (DC/RST/BUSY) failed
+    Pin,
+    /// Partial window is empty or out of bounds
+    InvalidWindow,
+    /// The panel has no partial update
+    Unsupported,
+    /// BUSY did not change within the allowed time during an update
+    BusyTimeout,
+    /// BUSY never reported ready after reset (panel missing or cable loose)
+    NotResponding,
+}
+
+impl EpdError {
+    /// Short name for logs and status reports
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::Spi => "spi",
+            Self::Pin => "pin",
+            Self::InvalidWindow => "invalid-window",
+            Self::Unsupported => "unsupported",
+            Self::BusyTimeout => "busy-timeout",
+            Self::NotResponding => "not-responding",
+        }
+    }
+}
+
+/// Pixels of a full frame, produced one panel row at a time while it is sent
+pub trait FrameSource {
+    /// Fill `row` with panel row `py`
+    fn panel_row(&mut self, orientation: &Orientation, py: u16, row: &mut [u8]);
+}
+
+/// A plain row-major image in image coordinates
+impl FrameSource for &[u8] {
+    fn panel_row(&mut self, orientation: &Orientation, py: u16, row: &mut [u8]) {
+        orientation.panel_row(self, py, row);
+    }
+}
+
+impl<F: FrameSource> FrameSource for &mut F {
+    fn panel_row(&mut self, orientation: &Orientation, py: u16, row: &mut [u8]) {
+        (**self).panel_row(orientation, py, row);
+    }
+}
+
+/// Operations the firmware needs from a panel driver
+// Only awaited on the single-threaded executor, the futures need not be `Send`
+#[allow(async_fn_in_trait)]
+pub trait Display {
+    /// Orientation used by `display` and `display_window`
+    fn orientation(&self) -> Orientation;
+    /// Set how image coordinates map onto the panel for the following transfers
+    fn set_orientation(&mut self, orientation: Orientation);
+
+    /// Reset and configure the panel
+    async fn init(&mut self) -> Result<(), EpdError>;
+    /// Fill the whole panel with color index `color` and refresh
+    async fn clear(&mut self, color: u8) -> Result<(), EpdError>;
+    /// Show a full frame (an image of `orientation().size()` pixels, possibly with overlays)
+    async fn display(&mut self, frame: impl FrameSource) -> Result<(), EpdError>;
+    /// Update only the part of the panel showing `window` (image coordinates) of
+    /// a full frame, or fail with `EpdError::Unsupported` on panels without a
+    /// partial waveform
+    async fn display_window(
+        &mut self,
+        frame: impl FrameSource,
+        window: Window,
+    ) -> Result<(), EpdError>;
+    /// Start a full-frame transfer; follow with `write_frame` and `finish_frame`
+    async fn begin_frame(&mut self) -> Result<(), EpdError>;
+    /// Send frame bytes that are already in panel scan order
+    async fn write_frame(&mut self, data: &[u8]) -> Result<(), EpdError>;
+    /// Refresh the panel with the frame sent since `begin_frame`
+    async fn finish_frame(&mut self) -> Result<(), EpdError>;
+    /// Enter deep sleep
+    async fn sleep(&mut self) -> Result<(), EpdError>;
+}
+
+/// Clockwise rotation applied to the image on its way to the panel
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Rotation {
+    Deg0,
+    Deg90,
+    /// The Pico-ePaper board mounted upright in the original frame
+    #[default]
+    Deg180,
+    Deg270,
+}
+
+impl Rotation {
+    pub const fn from_degrees(degrees: u16) -> Option<Self> {
+        match degrees {
+            0 => Some(Self::Deg0),
+            90 => Some(Self::Deg90),
+            180 => Some(Self::Deg180),
+            270 => Some(Self::Deg270),
+            _ => None,
+        }
+    }
+
+    pub const fn degrees(self) -> u16 {
+        match self {
+            Self::Deg0 => 0,
+            Self::Deg90 => 90,
+            Self::Deg180 => 180,
+            Self::Deg270 => 270,
+        }
+    }
+}
+
+/// How image (and overlay) coordinates map onto the panel.
+///
+/// The image is mirrored horizontally first (if enabled), then rotated. With
+/// 90°/270° the image is portrait (panel height wide, panel width high).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct Orientation {
+    pub rotation: Rotation,
+    pub mirror: bool,
+}
+
+impl Orientation {
+    pub const fn new(rotation: Rotation, mirror: bool) -> Self {
+        Self { rotation, mirror }
+    }
+
+    /// Image width and height in pixels
+    pub const fn size(&self) -> (u16, u16) {
+        match self.rotation {
+            Rotation::Deg0 | Rotation::Deg180 => (PANEL_WIDTH, PANEL_HEIGHT),
+            Rotation::Deg90 | Rotation::Deg270 => (PANEL_HEIGHT, PANEL_WIDTH),
+        }
+    }
+
+    /// Bytes of an image in this orientation
+    pub const fn frame_bytes(&self) -> usize {
+        let (width, height) = self.size();
+        row_bytes(width) * height as usize
+    }
+
+    /// Image pixel shown at panel pixel (`px`, `py`)
+    pub fn image_pixel(&self, px: u16, py: u16) -> (u16, u16) {
+        let (x, y) = match self.rotation {
+            Rotation::Deg0 => (px, py),
+            Rotation::Deg90 => (py, PANEL_WIDTH - 1 - px),
+            Rotation::Deg180 => (PANEL_WIDTH - 1 - px, PANEL_HEIGHT - 1 - py),
+            Rotation::Deg270 => (PANEL_HEIGHT - 1 - py, px),
+        };
+        if self.mirror {
+            (self.size().0 - 1 - x, y)
+        } else {
+            (x, y)
+        }
+    }
+
+    /// Panel pixel showing image pixel (`x`, `y`)
+    pub fn panel_pixel(&self, x: u16, y: u16) -> (u16, u16) {
+        let x = if self.mirror {
+            self.size().0 - 1 - x
+        } else {
+            x
+        };
+        match self.rotation {
+            Rotation::Deg0 => (x, y),
+            Rotation::Deg90 => (PANEL_WIDTH - 1 - y, x),
+            Rotation::Deg180 => (PANEL_WIDTH - 1 - x, PANEL_HEIGHT - 1 - y),
+            Rotation::Deg270 => (y, PANEL_HEIGHT - 1 - x),
+        }
+    }
+
+    /// Fill `row` with panel row `py`, reading pixels from `image` (row-major,
+    /// `size()` dimensions). Pixels beyond the end of `image` are white.
+    pub fn panel_row(&self, image: &[u8], py: u16, row: &mut [u8]) {
+        let stride = row_bytes(self.size().0);
+        match (self.rotation, self.mirror) {
+            (Rotation::Deg0, false) => {
+                let start = py as usize * stride;
+                for (k, out) in row.iter_mut().enumerate() {
+                    *out = image.get(start + k).copied().unwrap_or(fill_byte(WHITE));
+                }
+            }
+            (Rotation::Deg180, false) => {
+                // Reverse the byte order and the pixel order within each byte
+                let i = (PANEL_HEIGHT - 1 - py) as usize;
+                for (k, out) in row.iter_mut().enumerate() {
+                    let j = PANEL_ROW_BYTES - 1 - k;
+                    let b = image
+                        .get(j + stride * i)
+                        .copied()
+                        .unwrap_or(fill_byte(WHITE));
+                    *out = reverse_pixels(b);
+                }
+            }
+            _ => {
+                for (k, out) in row.iter_mut().enumerate() {
+                    let mut byte = 0u8;
+                    for p in 0..PIXELS_PER_BYTE {
+                        let px = (k * PIXELS_PER_BYTE + p) as u16;
+                        let value = get_pixel(image, stride, self.image_pixel(px, py));
+                        byte = (byte << BITS_PER_PIXEL) | value;
+                    }
+                    *out = byte;
+                }
+            }
+        }
+    }
+
+    /// Store frame bytes given in panel scan order (starting at byte `offset`)
+    /// into `image`, so that `display` shows them unchanged.
+    pub fn write_panel_bytes(&self, image: &mut [u8], offset: usize, data: &[u8]) {
+        match (self.rotation, self.mirror) {
+            (Rotation::Deg0, false) => {
+                let end = (offset + data.len()).min(image.len());
+                if offset < end {
+                    image[offset..end].copy_from_slice(&data[..end - offset]);
+                }
+            }
+            (Rotation::Deg180, false) => {
+                for (i, &b) in data.iter().enumerate() {
+                    if let Some(index) = PANEL_FRAME_BYTES.checked_sub(1 + offset + i) {
+                        image[index] = reverse_pixels(b);
+                    }
+                }
+            }
+            _ => {
+                let stride = row_bytes(self.size().0);
+                for (i, &b) in data.iter().enumerate() {
+                    let py = ((offset + i) / PANEL_ROW_BYTES) as u16;
+                    let first_px = ((offset + i) % PANEL_ROW_BYTES * PIXELS_PER_BYTE) as u16;
+                    for p in 0..PIXELS_PER_BYTE {
+                        let shift = (PIXELS_PER_BYTE - 1 - p) * BITS_PER_PIXEL;
+                        let pixel = self.image_pixel(first_px + p as u16, py);
+                        put_pixel(image, stride, pixel, b >> shift);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Panel rows showing any pixel of `window` (image coordinates)
+    pub fn panel_rows(&self, window: Window) -> Result<Range<u16>, EpdError> {
+        let (width, height) = self.size();
+        if !window.fits(width, height) {
+            return Err(EpdError::InvalidWindow);
+        }
+        let (_, ay) = self.panel_pixel(window.x, window.y);
+        let (_, by) = self.panel_pixel(window.x + window.width - 1, window.y + window.height - 1);
+        Ok(ay.min(by)..ay.max(by) + 1)
+    }
+}
+
+/// Rectangle for partial refresh
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct Window {
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+}
+
+impl Window {
+    pub const fn new(x: u16, y: u16, width: u16, height: u16) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// Non-empty and inside a `width` x `height` area
+    fn fits(&self, width: u16, height: u16) -> bool {
+        self.width > 0
+            && self.height > 0
+            && u32::from(self.x) + u32::from(self.width) <= u32::from(width)
+            && u32::from(self.y) + u32::from(self.height) <= u32::from(height)
+    }
+}
@@ -1,541 +0,0 @@
-//! Driver for 5.65 inch e-Paper display (600x448 pixels)
-//! Bit-banged SPI over GPIO, aligned with Waveshare C reference.
-
-use embassy_time::{Duration, Timer};
-
-use crate::config::EpdPins;
-
-/// Display dimensions
-pub const EPD_5IN65F_WIDTH: u16 = 600;
-pub const EPD_5IN65F_HEIGHT: u16 = 448;
-
-/// Colors: 3-bit indices matching lib/epd_5in65f.h
-pub const EPD_5IN65F_BLACK: u8 = 0x0;
-pub const EPD_5IN65F_WHITE: u8 = 0x1;
-pub const EPD_5IN65F_RED: u8 = 0x4;
-/*
-pub const EPD_5IN65F_GREEN: u8 = 0x2;
-pub const EPD_5IN65F_BLUE: u8 = 0x3;
-pub const EPD_5IN65F_YELLOW: u8 = 0x5;
-pub const EPD_5IN65F_ORANGE: u8 = 0x6;
-pub const EPD_5IN65F_CLEAN: u8 = 0x7;
-*/
-
-/// e-Paper driver structure
-pub struct Epd5in65f<'d> {
-    pins: EpdPins<'d>,
-}
-
-impl<'d> Epd5in65f<'d> {
-    /// Create new driver instance
-    pub fn new(pins: EpdPins<'d>) -> Self {
-        Self { pins }
-    }
-
-    /// Software reset (EPD_RST high->low->high with delays)
-    async fn reset(&mut self) {
-        self.pins.rst.set_high();
-        Timer::after(Duration::from_millis(200)).await;
-        self.pins.rst.set_low();
-        Timer::after(Duration::from_millis(2)).await;
-        self.pins.rst.set_high();
-        Timer::after(Duration::from_millis(200)).await;
-    }
-
-    /// Bit-banged SPI: write single byte, MSB first
-    fn spi_write_byte(&mut self, mut value: u8) {
-        for _ in 0..8 {
-            self.pins.clk.set_low();
-            if (value & 0x80) != 0 {
-                self.pins.mosi.set_high();
-            } else {
-                self.pins.mosi.set_low();
-            }
-            self.pins.clk.set_high();
-            value <<= 1;
-        }
-        self.pins.clk.set_low();
-    }
-
-    /// Send command
-    fn send_command(&mut self, reg: u8) {
-        self.pins.dc.set_low();
-        self.pins.cs.set_low();
-        self.spi_write_byte(reg);
-        self.pins.cs.set_high();
-    }
-
-    /// Send data byte
-    fn send_data(&mut self, data: u8) {
-        self.pins.dc.set_high();
-        self.pins.cs.set_low();
-        self.spi_write_byte(data);
-        self.pins.cs.set_high();
-    }
-
-    /*
-    /// Send data buffer
-    fn send_data_buffer(&mut self, data: &[u8]) {
-        for &b in data {
-            self.send_data(b);
-        }
-    }
-    */
-
-    /// Wait until BUSY becomes high
-    async fn wait_busy_high(&mut self) {
-        defmt::debug!(
-            "wait_busy_high: starting, current state={}",
-            self.pins.busy.is_high()
-        );
-        let mut iterations = 0u32;
-        while !self.pins.busy.is_high() {
-            Timer::after(Duration::from_millis(1)).await;
-            iterations += 1;
-            if iterations & 127 == 0 {
-                defmt::debug!(
-                    "wait_busy_high: still waiting, iterations={}, state={}",
-                    iterations,
-                    self.pins.busy.is_high()
-                );
-            }
-        }
-        defmt::debug!(
-            "wait_busy_high: done after {} iterations, final state={}",
-            iterations,
-            self.pins.busy.is_high()
-        );
-    }
-
-    /// Wait until BUSY becomes low
-    async fn wait_busy_low(&mut self) {
-        defmt::debug!(
-            "wait_busy_low: starting, current state={}",
-            self.pins.busy.is_high()
-        );
-        let mut iterations = 0u32;
-        while self.pins.busy.is_high() {
-            Timer::after(Duration::from_millis(1)).await;
-            iterations += 1;
-            if iterations & 127 == 0 {
-                defmt::debug!(
-                    "wait_busy_low: still waiting, iterations={}, state={}",
-                    iterations,
-                    self.pins.busy.is_high()
-                );
-            }
-        }
-        defmt::debug!(
-            "wait_busy_low: done after {} iterations, final state={}",
-            iterations,
-            self.pins.busy.is_high()
-        );
-    }
-
-    /// Initialize display (sequence mirrors C)
-    pub async fn init(&mut self) {
-        self.reset().await;
-        self.wait_busy_high().await;
-
-        self.send_command(0x00);
-        self.send_data(0xEF);
-        self.send_data(0x08);
-
-        self.send_command(0x01);
-        self.send_data(0x37);
-        self.send_data(0x00);
-        self.send_data(0x23);
-        self.send_data(0x23);
-
-        self.send_command(0x03);
-        self.send_data(0x00);
-
-        self.send_command(0x06);
-        self.send_data(0xC7);
-        self.send_data(0xC7);
-        self.send_data(0x1D);
-
-        self.send_command(0x30);
-        self.send_data(0x3C);
-
-        self.send_command(0x41);
-        self.send_data(0x00);
-
-        self.send_command(0x50);
-        self.send_data(0x37);
-
-        self.send_command(0x60);
-        self.send_data(0x22);
-
-        self.send_command(0x61);
-        self.send_data(0x02);
-        self.send_data(0x58);
-        self.send_data(0x01);
-        self.send_data(0xC0);
-
-        self.send_command(0xE3);
-        self.send_data(0xAA);
-
-        Timer::after(Duration::from_millis(100)).await;
-
-        self.send_command(0x50);
-        self.send_data(0x37);
-    }
-
-    /// Clear screen to given 3-bit color index
-    pub async fn clear(&mut self, color: u8) {
-        self.send_command(0x61); // Set Resolution
-        self.send_data(0x02);
-        self.send_data(0x58);
-        self.send_data(0x01);
-        self.send_data(0xC0);
-
-        self.send_command(0x10);
-
-        // Each byte is two pixels: high nibble and low nibble
-        let width_half = EPD_5IN65F_WIDTH / 2;
-        let byte = ((color & 0x0F) << 4) | (color & 0x0F);
-
-        for _y in 0..EPD_5IN65F_HEIGHT {
-            for _x in 0..width_half {
-                self.send_data(byte);
-            }
-        }
-
-        self.send_command(0x04);
-        self.wait_busy_high().await;
-        self.send_command(0x12);
-        self.wait_busy_high().await;
-        self.send_command(0x02);
-        self.wait_busy_low().await;
-        Timer::after(Duration::from_millis(500)).await;
-    }
-
-    /// Display image buffer, 4bpp packed (two pixels per byte), row-major
-    /// Image is rotated 180 degrees for proper orientation
-    pub async fn display(&mut self, image: &[u8]) {
-        self.send_command(0x61); // Set Resolution
-        self.send_data(0x02);
-        self.send_data(0x58);
-        self.send_data(0x01);
-        self.send_data(0xC0);
-
-        self.send_command(0x10);
-
-        let width_half = EPD_5IN65F_WIDTH / 2;
-        // Send data in reverse order for 180° rotation
-        for i in (0..EPD_5IN65F_HEIGHT as usize).rev() {
-            for j in (0..width_half as usize).rev() {
-                let idx = j + (width_half as usize * i);
-                let b = image.get(idx).copied().unwrap_or(0x11);
-                // Swap nibbles within the byte to maintain proper pixel order
-                let b_rotated = ((b & 0x0F) << 4) | ((b & 0xF0) >> 4);
-                self.send_data(b_rotated);
-            }
-        }
-
-        self.send_command(0x04);
-        self.wait_busy_high().await;
-        self.send_command(0x12);
-        self.wait_busy_high().await;
-        self.send_command(0x02);
-        self.wait_busy_low().await;
-        Timer::after(Duration::from_millis(200)).await;
-    }
-
-    /*
-    /// Display sub-rectangle from image buffer at (xstart, ystart)
-    pub async fn display_part(
-        &mut self,
-        image: &[u8],
-        xstart: u16,
-        ystart: u16,
-        image_width: u16,
-        image_height: u16,
-    ) {
-        self.send_command(0x61); // Set Resolution
-        self.send_data(0x02);
-        self.send_data(0x58);
-        self.send_data(0x01);
-        self.send_data(0xC0);
-
-        self.send_command(0x10);
-
-        let width_half = EPD_5IN65F_WIDTH / 2;
-        for i in 0..EPD_5IN65F_HEIGHT {
-            for j in 0..width_half {
-                if i < image_height + ystart
-                    && i >= ystart
-                    && j < (image_width + xstart) / 2
-                    && j >= xstart / 2
-                {
-                    let idx = ((j - xstart / 2) + (image_width / 2 * (i - ystart))) as usize;
-                    let b = image.get(idx).copied().unwrap_or(0x11);
-                    self.send_data(b);
-                } else {
-                    self.send_data(0x11);
-                }
-            }
-        }
-
-        self.send_command(0x04);
-        self.wait_busy_high().await;
-        self.send_command(0x12);
-        self.wait_busy_high().await;
-        self.send_command(0x02);
-        self.wait_busy_low().await;
-        Timer::after(Duration::from_millis(200)).await;
-    }
-    */
-
-    /// Enter sleep mode
-    pub async fn sleep(&mut self) {
-        Timer::after(Duration::from_millis(100)).await;
-        self.send_command(0x07);
-        self.send_data(0xA5);
-        Timer::after(Duration::from_millis(100)).await;
-        self.pins.rst.set_low(); // Reset
-    }
-}
-
-/// Simple 5x7 bitmap font for digits 0-9
-/// Each digit is 5 bytes (5 columns), each byte represents 7 pixels (bits 0-6)
-const FONT_5X7: [[u8; 5]; 10] = [
-    [0b0111110, 0b1000001, 0b1000001, 0b1000001, 0b0111110], // 0
-    [0b0000000, 0b0100001, 0b1111111, 0b0000001, 0b0000000], // 1
-    [0b0100011, 0b1000101, 0b1001001, 0b1010001, 0b0100001], // 2
-    [0b0100010, 0b1000001, 0b1001001, 0b1001001, 0b0110110], // 3
-    [0b0001100, 0b0010100, 0b0100100, 0b1111111, 0b0000100], // 4
-    [0b1110010, 0b1010001, 0b1010001, 0b1010001, 0b1001110], // 5
-    [0b0111110, 0b1001001, 0b1001001, 0b1001001, 0b0000110], // 6
-    [0b1000000, 0b1000111, 0b1001000, 0b1010000, 0b1100000], // 7
-    [0b0110110, 0b1001001, 0b1001001, 0b1001001, 0b0110110], // 8
-    [0b0110000, 0b1001001, 0b1001001, 0b1001001, 0b0111110], // 9
-];
-
-/// Draw a single digit at position (x, y) in the image buffer
-/// Scale factor determines the size (1 = 5x7, 2 = 10x14, etc.)
-fn draw_digit(image: &mut [u8], x: u16, y: u16, digit: u8, color: u8, scale: u16) {
-    if digit > 9 {
-        return;
-    }
-
-    let glyph = &FONT_5X7[digit as usize];
-
-    for col in 0..5 {
-        let column_data = glyph[col];
-        for row in 0..7 {
-            if (column_data & (1 << (6 - row))) != 0 {
-                // Draw scaled pixel
-                for sy in 0..scale {
-                    for sx in 0..scale {
-                        let px = x + (col as u16 * scale) + sx;
-                        let py = y + (row as u16 * scale) + sy;
-
-                        if px < EPD_5IN65F_WIDTH && py < EPD_5IN65F_HEIGHT {
-                            set_pixel(image, px, py, color);
-                        }
-                    }
-                }
-            }
-        }
-    }
-}
-
-/// Set a single pixel in the image buffer.
-/// Image format: 4bpp packed (two pixels per byte), row-major.
-fn set_pixel(image: &mut [u8], x: u16, y: u16, color: u8) {
-    if x >= EPD_5IN65F_WIDTH || y >= EPD_5IN65F_HEIGHT {
-        return;
-    }
-
-    // IMPORTANT: compute in usize to avoid u16 overflow on row offset.
-    // 300 * 447 = 134100, which overflows u16 and causes wrapped writes.
-    let width_half = (EPD_5IN65F_WIDTH / 2) as usize;
-    let byte_index = (x as usize / 2) + (width_half * y as usize);
-
-    if byte_index < image.len() {
-        if x.is_multiple_of(2) {
-            // High nibble (left pixel)
-            image[byte_index] = (image[byte_index] & 0x0F) | ((color & 0x0F) << 4);
-        } else {
-            // Low nibble (right pixel)
-            image[byte_index] = (image[byte_index] & 0xF0) | (color & 0x0F);
-        }
-    }
-}
-
-/// Draw a number (up to 3 digits) at position (x, y)
-/// Returns the width of the drawn text in pixels
-pub fn draw_number(image: &mut [u8], x: u16, y: u16, number: u8, color: u8, scale: u16) -> u16 {
-    let mut current_x = x;
-    let char_width = 5 * scale;
-    let char_spacing = 2 * scale;
-
-    if number >= 100 {
-        let hundreds = number / 100;
-        draw_digit(image, current_x, y, hundreds, color, scale);
-        current_x += char_width + char_spacing;
-    }
-
-    if number >= 10 {
-        let tens = (number / 10) % 10;
-        draw_digit(image, current_x, y, tens, color, scale);
-        current_x += char_width + char_spacing;
-    }
-
-    let ones = number % 10;
-    draw_digit(image, current_x, y, ones, color, scale);
-    current_x += char_width;
-
-    current_x - x
-}
-
-/// Draw low-battery warning in the bottom-right quarter of the screen.
-pub fn draw_low_battery_warning(image: &mut [u8]) {
-    let overlay_width = 336;
-    let overlay_height = 180;
-    let overlay_x = EPD_5IN65F_WIDTH - overlay_width - 25;
-    let overlay_y = EPD_5IN65F_HEIGHT - overlay_height - 14;
-
-    let icon_w = 284;
-    let icon_h = 142;
-    let cap_w = 26;
-    let icon_x = overlay_x;
-    let icon_y = overlay_y;
-    let border = 10;
-    let background_padding = 10;
-
-    // Clear destination area with padding so the icon has whitespace around it.
-    fill_rect(
-        image,
-        icon_x.saturating_sub(background_padding),
-        icon_y.saturating_sub(background_padding),
-        icon_w + cap_w + (background_padding * 2),
-        icon_h + (background_padding * 2),
-        EPD_5IN65F_WHITE,
-    );
-
-    // Battery body border.
-    fill_rect(image, icon_x, icon_y, icon_w, icon_h, EPD_5IN65F_BLACK);
-    fill_rect(
-        image,
-        icon_x + border,
-        icon_y + border,
-        icon_w - (border * 2),
-        icon_h - (border * 2),
-        EPD_5IN65F_WHITE,
-    );
-
-    // Battery cap on the right side (horizontal battery orientation).
-    fill_rect(image, icon_x + icon_w, icon_y + 42, cap_w, 58, EPD_5IN65F_BLACK);
-    fill_rect(image, icon_x + icon_w + 6, icon_y + 48, 14, 46, EPD_5IN65F_WHITE);
-
-    // "Almost empty" red segment at the left side.
-    fill_rect(
-        image,
-        icon_x + border + 20,
-        icon_y + border + 20,
-        38,
-        icon_h - (border * 2) - 40,
-        EPD_5IN65F_RED,
-    );
-}
-
-/// Draw a simple "broken WiFi" warning icon in the same area as low-battery icon.
-pub fn draw_broken_wifi_warning(image: &mut [u8]) {
-    let overlay_width = 336;
-    let overlay_height = 180;
-    let overlay_x = EPD_5IN65F_WIDTH - overlay_width - 25;
-    let overlay_y = EPD_5IN65F_HEIGHT - overlay_height - 14;
-    let background_padding = 10;
-
-    fill_rect(
-        image,
-        overlay_x.saturating_sub(background_padding),
-        overlay_y.saturating_sub(background_padding),
-        overlay_width + (background_padding * 2),
-        overlay_height + (background_padding * 2),
-        EPD_5IN65F_WHITE,
-    );
-
-    let center_x = overlay_x + overlay_width / 2;
-    // Move icon a bit higher inside the block.
-    let center_y = overlay_y + overlay_height - 42;
-
-    // Real WiFi arcs: three 90-degree ring segments with decreasing radii.
-    draw_wifi_arc(image, center_x, center_y, 108, 18);
-    draw_wifi_arc(image, center_x, center_y, 76, 16);
-    draw_wifi_arc(image, center_x, center_y, 46, 14);
-
-    // WiFi dot.
-    draw_filled_circle(image, center_x, center_y, 12, EPD_5IN65F_BLACK);
-
-    // Red slash to indicate "broken".
-    for i in 0..190u16 {
-        let x = overlay_x + 72 + i;
-        let y = overlay_y + 18 + i / 2;
-        fill_rect(image, x, y, 12, 8, EPD_5IN65F_RED);
-    }
-}
-
-fn draw_wifi_arc(
-    image: &mut [u8],
-    center_x: u16,
-    center_y: u16,
-    radius_outer: u16,
-    thickness: u16,
-) {
-    let radius_inner = radius_outer.saturating_sub(thickness);
-    let ro2 = (radius_outer as i32) * (radius_outer as i32);
-    let ri2 = (radius_inner as i32) * (radius_inner as i32);
-    let cx = center_x as i32;
-    let cy = center_y as i32;
-    let ro = radius_outer as i32;
-
-    for dy in -ro..=0 {
-        for dx in -ro..=ro {
-            let d2 = dx * dx + dy * dy;
-            // Keep only the upper 90-degree sector (between -45° and +45° around vertical).
-            if d2 <= ro2 && d2 >= ri2 && dx.abs() <= -dy {
-                plot_pixel_i32(image, cx + dx, cy + dy, EPD_5IN65F_BLACK);
-            }
-        }
-    }
-}
-
-fn plot_pixel_i32(image: &mut [u8], x: i32, y: i32, color: u8) {
-    if x < 0 || y < 0 {
-        return;
-    }
-    if x >= EPD_5IN65F_WIDTH as i32 || y >= EPD_5IN65F_HEIGHT as i32 {
-        return;
-    }
-    set_pixel(image, x as u16, y as u16, color);
-}
-
-fn draw_filled_circle(image: &mut [u8], center_x: u16, center_y: u16, radius: u16, color: u8) {
-    let cx = center_x as i32;
-    let cy = center_y as i32;
-    let r = radius as i32;
-    let r2 = r * r;
-
-    for dy in -r..=r {
-        for dx in -r..=r {
-            if dx * dx + dy * dy <= r2 {
-                plot_pixel_i32(image, cx + dx, cy + dy, color);
-            }
-        }
-    }
-}
-
-fn fill_rect(image: &mut [u8], x: u16, y: u16, width: u16, height: u16, color: u8) {
-    let x_end = x.saturating_add(width).min(EPD_5IN65F_WIDTH);
-    let y_end = y.saturating_add(height).min(EPD_5IN65F_HEIGHT);
-
-    for py in y..y_end {
-        for px in x..x_end {
-            set_pixel(image, px, py, color);
-        }
-    }
-}
@@ -4,6 +4,9 @@
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 use embassy_sync::channel::Channel;
 
+use crate::epd::EpdError;
+use crate::network::NetworkError;
+
 /// Maximum number of events that can be queued
 const EVENT_QUEUE_SIZE: usize = 10;
 
@@ -24,10 +27,18 @@ pub enum Event {
     NetworkDisconnected,
     /// Image downloaded successfully
     ImageDownloaded,
-    /// Image download failed
-    ImageDownloadFailed,
+    /// The server answered `304 Not Modified`, the image on display is current
+    ImageNotModified,
+    /// Update cycle ended without a new image
+    ImageDownloadFailed(NetworkError),
     /// Scheduler update requested - notifies scheduler that next_update_delay_secs has changed
     SchedulerUpdateRequested,
+    /// Panel refreshed successfully
+    DisplayUpdated,
+    /// Panel update failed (e.g. BUSY stuck or panel not responding)
+    DisplayFailed(EpdError),
+    /// Temperature back inside the refresh window after a refresh was postponed
+    TemperatureInRange,
 }
 
 /// Global event channel for inter-task communication
@@ -0,0 +1,569 @@
+//! Image response headers
+//! Collects the headers of the image server's response that decide how the body
+//! is decoded and shown, applied over the device settings, and the validators
+//! that make the next request conditional.
+
+use defmt::{error, info, warn};
+
+use crate::epd::{Orientation, PANEL_FRAME_BYTES, PANEL_HEIGHT, PANEL_WIDTH, Rotation};
+use crate::image::dither::{DitherMethod, Ditherer, PixelFormat};
+use crate::image::{Container, ContainerDecoder};
+use crate::layout::StatusLayout;
+
+/// Transfer encoding of the image body, from the `Content-Encoding` header
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum ContentEncoding {
+    /// Raw frame in the panel's pixel format (default)
+    Identity,
+    /// PackBits run-length compressed frame (`x-packbits`)
+    PackBits,
+}
+
+/// Pixel order of the downloaded image, from the `X-Image-Orientation` header
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum ImageOrientation {
+    /// Upright image, the device applies the configured rotation/mirroring (default)
+    Upright,
+    /// Already in panel scan order (`X-Image-Orientation: panel`)
+    Panel,
+}
+
+/// A header asks for something the firmware cannot handle
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum HeaderError {
+    /// Unknown `X-Image-Format`
+    Format,
+    /// Unknown `Content-Encoding`
+    Encoding,
+}
+
+impl HeaderError {
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::Format => "Unsupported image format",
+            Self::Encoding => "Unsupported content encoding",
+        }
+    }
+}
+
+/// Device settings the headers override
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeaderDefaults {
+    /// `epaper.rotation` / `epaper.mirror`
+    pub orientation: Orientation,
+    /// `image.dither`
+    pub dither: DitherMethod,
+    /// `display.utc_offset_minutes`
+    pub utc_offset_minutes: i16,
+}
+
+/// Response headers relevant to the image
+#[derive(Debug, Clone, Copy, defmt::Format)]
+pub struct ImageHeaders {
+    /// `X-Next-Delay` in seconds
+    pub next_delay: Option<u64>,
+    /// `X-Image-Orientation`
+    pub orientation: ImageOrientation,
+    /// `Content-Length`, if the server sent one (size on the wire)
+    pub content_length: Option<usize>,
+    /// `Content-Encoding`
+    pub encoding: ContentEncoding,
+    /// `X-Image-Format`: panel pixels, or RGB/gray to be dithered on the device
+    pub format: PixelFormat,
+    /// `Content-Type` of a BMP/PNG file to decode on the device
+    pub container: Option<Container>,
+    /// `X-Dither`, or `image.dither`
+    pub dither: DitherMethod,
+    /// Display orientation for this image: `epaper.rotation`/`epaper.mirror`
+    /// with the `X-Image-Rotation`/`X-Image-Mirror` overrides
+    pub display_orientation: Orientation,
+    /// `X-Deep-Clean`: clear the panel before showing this image
+    pub deep_clean: bool,
+    /// `X-Status-Bar`, replaces the configured status bar for this image
+    pub status_bar: Option<StatusLayout>,
+    /// `Date` as minutes since local midnight (`display.utc_offset_minutes`)
+    pub date_minute: Option<u16>,
+    /// `X-Content-CRC32` of the body
+    pub crc32: Option<u32>,
+    /// `crc32c` entry of the `Digest` header
+    pub crc32c: Option<u32>,
+}
+
+impl ImageHeaders {
+    /// Collect the headers the firmware cares about
+    pub fn parse<'h>(
+        headers: impl Iterator<Item = (&'h str, &'h [u8])>,
+        content_length: Option<usize>,
+        defaults: &HeaderDefaults,
+    ) -> Result<Self, HeaderError> {
+        let mut parsed = Self {
+            next_delay: None,
+            orientation: ImageOrientation::Upright,
+            content_length,
+            encoding: ContentEncoding::Identity,
+            format: PixelFormat::Native,
+            container: None,
+            dither: defaults.dither,
+            display_orientation: defaults.orientation,
+            deep_clean: false,
+            status_bar: None,
+            date_minute: None,
+            crc32: None,
+            crc32c: None,
+        };
+        let mut rotation = defaults.orientation.rotation;
+        let mut mirror = defaults.orientation.mirror;
+
+        for (name, value) in headers {
+            let Ok(value_str) = core::str::from_utf8(value) else {
+                continue;
+            };
+            if name.eq_ignore_ascii_case("x-next-delay") {
+                if let Ok(delay) = value_str.trim().parse::<u64>() {
+                    parsed.next_delay = Some(delay);
+                    info!("X-Next-Delay header found: {} seconds", delay);
+                } else {
+                    warn!("Failed to parse X-Next-Delay value: {}", value_str);
+                }
+            } else if name.eq_ignore_ascii_case("x-image-orientation") {
+                if value_str.trim().eq_ignore_ascii_case("panel") {
+                    parsed.orientation = ImageOrientation::Panel;
+                } else if !value_str.trim().eq_ignore_ascii_case("upright") {
+                    warn!("Unknown X-Image-Orientation value: {}", value_str);
+                }
+            } else if name.eq_ignore_ascii_case("x-image-rotation") {
+                match value_str
+                    .trim()
+                    .parse::<u16>()
+                    .ok()
+                    .and_then(Rotation::from_degrees)
+                {
+                    Some(value) => rotation = value,
+                    None => warn!("Invalid X-Image-Rotation value: {}", value_str),
+                }
+            } else if name.eq_ignore_ascii_case("x-image-mirror") {
+                match value_str.trim() {
+                    "1" | "true" => mirror = true,
+                    "0" | "false" => mirror = false,
+                    _ => warn!("Invalid X-Image-Mirror value: {}", value_str),
+                }
+            } else if name.eq_ignore_ascii_case("x-deep-clean") {
+                match value_str.trim() {
+                    "1" | "true" => parsed.deep_clean = true,
+                    "0" | "false" => {}
+                    _ => warn!("Invalid X-Deep-Clean value: {}", value_str),
+                }
+            } else if name.eq_ignore_ascii_case("x-image-format") {
+                match PixelFormat::from_name(value_str.trim()) {
+                    Some(format) => parsed.format = format,
+                    None => {
+                        error!("Unsupported X-Image-Format: {}", value_str);
+                        return Err(HeaderError::Format);
+                    }
+                }
+            } else if name.eq_ignore_ascii_case("content-type") {
+                parsed.container = Container::from_content_type(value_str);
+            } else if name.eq_ignore_ascii_case("x-dither") {
+                match DitherMethod::from_name(value_str.trim()) {
+                    Some(method) => parsed.dither = method,
+                    None => warn!("Invalid X-Dither value: {}", value_str),
+                }
+            } else if name.eq_ignore_ascii_case("x-status-bar") {
+                match StatusLayout::parse(value_str) {
+                    Some(layout) => parsed.status_bar = Some(layout),
+                    None => warn!("Invalid X-Status-Bar value: {}", value_str),
+                }
+            } else if name.eq_ignore_ascii_case("date") {
+                parsed.date_minute = parse_http_date_minute(value_str, defaults.utc_offset_minutes);
+                if parsed.date_minute.is_none() {
+                    warn!("Failed to parse Date value: {}", value_str);
+                }
+            } else if name.eq_ignore_ascii_case("x-content-crc32") {
+                match u32::from_str_radix(value_str.trim(), 16) {
+                    Ok(crc) => parsed.crc32 = Some(crc),
+                    Err(_) => warn!("Invalid X-Content-CRC32 value: {}", value_str),
+                }
+            } else if name.eq_ignore_ascii_case("digest") {
+                parsed.crc32c = parse_digest_crc32c(value_str);
+                if parsed.crc32c.is_none() {
+                    info!("No usable checksum in Digest: {}", value_str);
+                }
+            } else if name.eq_ignore_ascii_case("content-encoding") {
+                let encoding = value_str.trim();
+                if encoding.eq_ignore_ascii_case("x-packbits")
+                    || encoding.eq_ignore_ascii_case("packbits")
+                {
+                    parsed.encoding = ContentEncoding::PackBits;
+                } else if !encoding.eq_ignore_ascii_case("identity") {
+                    error!("Unsupported Content-Encoding: {}", encoding);
+                    return Err(HeaderError::Encoding);
+                }
+            }
+        }
+        parsed.display_orientation = Orientation::new(rotation, mirror);
+
+        if parsed.next_delay.is_none() {
+            info!("X-Next-Delay header not found, will use default interval");
+        }
+        info!("Image encoding: {}", parsed.encoding);
+
+        Ok(parsed)
+    }
+
+    /// Pixels per row of the body
+    pub fn image_width(&self) -> u16 {
+        match self.orientation {
+            ImageOrientation::Upright => self.display_orientation.size().0,
+            ImageOrientation::Panel => PANEL_WIDTH,
+        }
+    }
+
+    /// Rows of the body
+    pub fn image_height(&self) -> u16 {
+        match self.orientation {
+            ImageOrientation::Upright => self.display_orientation.size().1,
+            ImageOrientation::Panel => PANEL_HEIGHT,
+        }
+    }
+
+    /// Image bytes once decoded: one image in the display orientation, or one panel frame
+    pub fn frame_bytes(&self) -> usize {
+        match self.orientation {
+            ImageOrientation::Upright => self.display_orientation.frame_bytes(),
+            ImageOrientation::Panel => PANEL_FRAME_BYTES,
+        }
+    }
+
+    /// Bytes of one decoded image row, padding included
+    pub fn row_bytes(&self) -> usize {
+        self.frame_bytes() / usize::from(self.image_height())
+    }
+
+    /// Converter to panel pixels for RGB/gray bodies and `container` files,
+    /// `None` for native frames
+    pub fn ditherer(&self, container: Option<Container>) -> Option<Ditherer> {
+        let format = match container {
+            Some(container) => {
+                info!(
+                    "Decoding {} image with {} dithering",
+                    container, self.dither
+                );
+                // Unused, the decoder hands over palette colors
+                PixelFormat::Rgb888
+            }
+            None if self.format == PixelFormat::Native => return None,
+            None => {
+                info!(
+                    "Converting {} image with {} dithering",
+                    self.format, self.dither
+                );
+                self.format
+            }
+        };
+        Some(Ditherer::new(format, self.dither, self.image_width()))
+    }
+
+    /// Decoder for a BMP/PNG body of the expected size
+    pub fn container_decoder(&self, container: Container) -> ContainerDecoder {
+        ContainerDecoder::new(container, self.image_width(), self.image_height())
+    }
+}
+
+/// Longest `ETag` that is remembered; a longer one is not sent back
+const ETAG_CAPACITY: usize = 80;
+
+/// `ETag` and `Last-Modified` of the image on display, sent back as
+/// `If-None-Match` / `If-Modified-Since` so an unchanged image is not downloaded again
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImageValidators {
+    pub etag: Option<heapless::String<ETAG_CAPACITY>>,
+    /// An HTTP date, 29 characters
+    pub last_modified: Option<heapless::String<32>>,
+}
+
+impl ImageValidators {
+    /// No validators: the next request downloads the image unconditionally
+    pub const fn new() -> Self {
+        Self {
+            etag: None,
+            last_modified: None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+
+    /// Validators of a response, values that do not fit are dropped
+    pub fn from_headers<'h>(headers: impl Iterator<Item = (&'h str, &'h [u8])>) -> Self {
+        let mut validators = Self::new();
+        for (name, value) in headers {
+            let Ok(value) = core::str::from_utf8(value) else {
+                continue;
+            };
+            let value = value.trim();
+            if value.is_empty() {
+                continue;
+            }
+            if name.eq_ignore_ascii_case("etag") {
+                validators.etag = heapless::String::try_from(value).ok();
+                if validators.etag.is_none() {
+                    warn!("ETag longer than {} bytes, not remembered", ETAG_CAPACITY);
+                }
+            } else if name.eq_ignore_ascii_case("last-modified") {
+                validators.last_modified = heapless::String::try_from(value).ok();
+            }
+        }
+        validators
+    }
+}
+
+/// Start and total length of a `Content-Range: bytes <start>-<end>/<total>` header
+pub fn parse_content_range(value: &str) -> Option<(usize, usize)> {
+    let (span, total) = value.trim().strip_prefix("bytes ")?.split_once('/')?;
+    let (start, _) = span.split_once('-')?;
+    Some((start.trim().parse().ok()?, total.trim().parse().ok()?))
+}
+
+/// Hex `crc32c` value of a `Digest` header ("sha-256=..., crc32c=1a2b3c4d"),
+/// the only algorithm of the list the firmware checks
+fn parse_digest_crc32c(value: &str) -> Option<u32> {
+    value.split(',').find_map(|entry| {
+        let (algorithm, digest) = entry.split_once('=')?;
+        if !algorithm.trim().eq_ignore_ascii_case("crc32c") {
+            return None;
+        }
+        u32::from_str_radix(digest.trim(), 16).ok()
+    })
+}
+
+/// Local time of day of an HTTP `Date` ("Sun, 06 Nov 1994 08:49:37 GMT")
+/// in minutes since midnight
+fn parse_http_date_minute(value: &str, utc_offset_minutes: i16) -> Option<u16> {
+    let time = value.split_whitespace().nth(4)?;
+    let mut fields = time.split(':');
+    let hours: u8 = fields.next()?.parse().ok()?;
+    let minutes: u8 = fields.next()?.parse().ok()?;
+    if hours > 23 || minutes > 59 {
+        return None;
+    }
+    let utc = i32::from(hours) * 60 + i32::from(minutes);
+    let minute = (utc + i32::from(utc_offset_minutes)).rem_euclid(24 * 60);
+    Some(minute as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEFAULTS: HeaderDefaults = HeaderDefaults {
+        orientation: Orientation::new(Rotation::Deg180, false),
+        dither: DitherMethod::FloydSteinberg,
+        utc_offset_minutes: 0,
+    };
+
+    fn parse(headers: &[(&'static str, &'static str)]) -> Result<ImageHeaders, HeaderError> {
+        let headers = headers
+            .iter()
+            .map(|&(name, value)| (name, value.as_bytes()));
+        ImageHeaders::parse(headers, Some(1234), &DEFAULTS)
+    }
+
+    #[test]
+    fn without_headers_the_device_settings_apply() {
+        let headers = parse(&[]).unwrap();
+
+        assert_eq!(headers.next_delay, None);
+        assert_eq!(headers.orientation, ImageOrientation::Upright);
+        assert_eq!(headers.content_length, Some(1234));
+        assert_eq!(headers.encoding, ContentEncoding::Identity);
+        assert_eq!(headers.format, PixelFormat::Native);
+        assert_eq!(headers.container, None);
+        assert_eq!(headers.dither, DitherMethod::FloydSteinberg);
+        assert_eq!(headers.display_orientation, DEFAULTS.orientation);
+        assert!(!headers.deep_clean);
+        assert_eq!(headers.date_minute, None);
+        assert_eq!((headers.crc32, headers.crc32c), (None, None));
+    }
+
+    #[test]
+    fn headers_override_the_device_settings() {
+        let headers = parse(&[
+            ("X-Next-Delay", " 600 "),
+            ("x-image-rotation", "90"),
+            ("X-IMAGE-MIRROR", "true"),
+            ("X-Dither", "ordered"),
+            ("X-Image-Format", "rgb888"),
+            ("Content-Encoding", "x-packbits"),
+            ("Content-Type", "image/png; charset=binary"),
+            ("X-Deep-Clean", "1"),
+            ("X-Content-CRC32", "CBF43926"),
+        ])
+        .unwrap();
+
+        assert_eq!(headers.next_delay, Some(600));
+        assert_eq!(
+            headers.display_orientation,
+            Orientation::new(Rotation::Deg90, true)
+        );
+        assert_eq!(headers.dither, DitherMethod::Ordered);
+        assert_eq!(headers.format, PixelFormat::Rgb888);
+        assert_eq!(headers.encoding, ContentEncoding::PackBits);
+        assert_eq!(headers.container, Some(Container::Png));
+        assert!(headers.deep_clean);
+        assert_eq!(headers.crc32, Some(0xCBF4_3926));
+    }
+
+    #[test]
+    fn invalid_values_keep_the_device_settings() {
+        let headers = parse(&[
+            ("X-Next-Delay", "soon"),
+            ("X-Image-Rotation", "45"),
+            ("X-Image-Mirror", "maybe"),
+            ("X-Dither", "random"),
+            ("X-Deep-Clean", "yes"),
+            ("X-Content-CRC32", "not-hex"),
+        ])
+        .unwrap();
+
+        assert_eq!(headers.next_delay, None);
+        assert_eq!(headers.display_orientation, DEFAULTS.orientation);
+        assert_eq!(headers.dither, DitherMethod::FloydSteinberg);
+        assert!(!headers.deep_clean);
+        assert_eq!(headers.crc32, None);
+    }
+
+    #[test]
+    fn unknown_format_and_encoding_are_refused() {
+        assert_eq!(
+            parse(&[("X-Image-Format", "cmyk")]).unwrap_err(),
+            HeaderError::Format
+        );
+        assert_eq!(
+            parse(&[("Content-Encoding", "gzip")]).unwrap_err(),
+            HeaderError::Encoding
+        );
+        assert!(parse(&[("Content-Encoding", "identity")]).is_ok());
+    }
+
+    #[test]
+    fn panel_order_images_have_the_panel_size() {
+        let upright = parse(&[("X-Image-Rotation", "90")]).unwrap();
+        assert_eq!(
+            (upright.image_width(), upright.image_height()),
+            (PANEL_HEIGHT, PANEL_WIDTH)
+        );
+
+        let panel = parse(&[("X-Image-Rotation", "90"), ("X-Image-Orientation", "panel")]).unwrap();
+        assert_eq!(panel.orientation, ImageOrientation::Panel);
+        assert_eq!(
+            (panel.image_width(), panel.image_height()),
+            (PANEL_WIDTH, PANEL_HEIGHT)
+        );
+        assert_eq!(panel.frame_bytes(), PANEL_FRAME_BYTES);
+        assert_eq!(
+            panel.row_bytes(),
+            PANEL_FRAME_BYTES / usize::from(PANEL_HEIGHT)
+        );
+    }
+
+    #[test]
+    fn validators_are_taken_from_the_response() {
+        let headers = [
+            ("etag", " \"v42\" ".as_bytes()),
+            ("Last-Modified", b"Sun, 06 Nov 1994 08:49:37 GMT"),
+            ("Content-Length", b"1234"),
+        ];
+        let validators = ImageValidators::from_headers(headers.into_iter());
+
+        assert_eq!(validators.etag.as_deref(), Some("\"v42\""));
+        assert_eq!(
+            validators.last_modified.as_deref(),
+            Some("Sun, 06 Nov 1994 08:49:37 GMT")
+        );
+        assert!(!validators.is_empty());
+    }
+
+    #[test]
+    fn validators_that_do_not_fit_are_dropped() {
+        let long = [b'a'; ETAG_CAPACITY + 1];
+        let headers = [
+            ("ETag", long.as_slice()),
+            ("Last-Modified", b"   "),
+            ("X-Other", b"1"),
+        ];
+        let validators = ImageValidators::from_headers(headers.into_iter());
+
+        assert_eq!(validators, ImageValidators::new());
+        assert!(validators.is_empty());
+
+        let fits = [b'a'; ETAG_CAPACITY];
+        let validators = ImageValidators::from_headers([("ETag", fits.as_slice())].into_iter());
+        assert_eq!(validators.etag.map(|etag| etag.len()), Some(ETAG_CAPACITY));
+    }
+
+    #[test]
+    fn content_range_yields_start_and_total() {
+        assert_eq!(
+            parse_content_range("bytes 4096-134399/134400"),
+            Some((4096, 134_400))
+        );
+        assert_eq!(parse_content_range(" bytes 0-9/10 "), Some((0, 10)));
+    }
+
+    #[test]
+    fn malformed_content_ranges_are_ignored() {
+        // Unknown total, unsatisfiable range, other unit
+        assert_eq!(parse_content_range("bytes 0-9/*"), None);
+        assert_eq!(parse_content_range("bytes */134400"), None);
+        assert_eq!(parse_content_range("items 0-9/10"), None);
+        assert_eq!(parse_content_range("bytes 0-9"), None);
+        assert_eq!(parse_content_range(""), None);
+    }
+
+    #[test]
+    fn digest_yields_the_crc32c_entry() {
+        assert_eq!(
+            parse_digest_crc32c(
+                "sha-256=X48E9qOokqqrvdts8nOJRJN3OWDUoyWxBf7kbu9DBPE=, CRC32C=e3069283"
+            ),
+            Some(0xE306_9283)
+        );
+        assert_eq!(parse_digest_crc32c("crc32c=e3069283"), Some(0xE306_9283));
+        assert_eq!(parse_digest_crc32c("sha-256=abc"), None);
+        assert_eq!(parse_digest_crc32c("crc32c=xyz"), None);
+        assert_eq!(parse_digest_crc32c(""), None);
+    }
+
+    #[test]
+    fn date_is_converted_to_the_local_minute() {
+        let date = "Sun, 06 Nov 1994 08:49:37 GMT";
+        assert_eq!(parse_http_date_minute(date, 0), Some(8 * 60 + 49));
+        assert_eq!(parse_http_date_minute(date, 120), Some(10 * 60 + 49));
+        // Wraps around midnight in both directions
+        assert_eq!(parse_http_date_minute(date, -600), Some(22 * 60 + 49));
+        assert_eq!(
+            parse_http_date_minute("Sun, 06 Nov 1994 23:30:00 GMT", 60),
+            Some(30)
+        );
+    }
+
+    #[test]
+    fn malformed_dates_are_ignored() {
+        assert_eq!(parse_http_date_minute("Sun, 06 Nov 1994", 0), None);
+        assert_eq!(
+            parse_http_date_minute("Sun, 06 Nov 1994 24:00:00 GMT", 0),
+            None
+        );
+        assert_eq!(
+            parse_http_date_minute("Sun, 06 Nov 1994 08:60:00 GMT", 0),
+            None
+        );
+        assert_eq!(parse_http_date_minute("Sunday 08:49", 0), None);
+
+        let headers = parse(&[("Date", "yesterday")]).unwrap();
+        assert_eq!(headers.date_minute, None);
+        let headers = parse(&[("date", "Sun, 06 Nov 1994 08:49:37 GMT")]).unwrap();
+        assert_eq!(headers.date_minute, Some(8 * 60 + 49));
+    }
+}
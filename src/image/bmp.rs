@@ -0,0 +1,399 @@
+//! Streaming decoder for indexed Windows bitmaps
+//!
+//! Layout: 14-byte file header ("BM", size, pixel data offset), an info header
+//! of at least 40 bytes (width, height, bits per pixel, compression, palette
+//! size), the palette as blue-green-red-reserved entries, then the pixel rows
+//! padded to 4 bytes. Rows are stored bottom-up unless the height is negative.
+//!
+//! Only uncompressed 1, 4 and 8 bits per pixel are supported.
+
+use defmt::error;
+
+use super::{MAX_ROW_PIXELS, Row, unpack_indices};
+
+/// File header and the fields of the info header the decoder reads
+const HEADER_BYTES: usize = 14 + 40;
+
+/// Info header sizes written by common encoders (v1, v2, v3, v4, v5)
+const INFO_HEADER_SIZES: [u32; 5] = [40, 52, 56, 108, 124];
+
+/// Padded row of an 8-bit image
+const MAX_STRIDE: usize = MAX_ROW_PIXELS.next_multiple_of(4);
+
+/// True if `data` starts like a supported bitmap file
+pub fn has_signature(data: &[u8]) -> bool {
+    data.len() >= 18
+        && data.starts_with(b"BM")
+        && INFO_HEADER_SIZES.contains(&u32::from_le_bytes([
+            data[14], data[15], data[16], data[17],
+        ]))
+}
+
+/// Part of the file the next byte belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Header,
+    /// Rest of the info header, palette and gap up to the pixel data
+    BeforePixels,
+    Rows,
+    Done,
+}
+
+/// Incremental bitmap decoder
+pub struct BmpDecoder {
+    width: u16,
+    height: u16,
+    state: State,
+    /// Bytes of the file consumed so far
+    position: u32,
+    header: [u8; HEADER_BYTES],
+    bits: u8,
+    bottom_up: bool,
+    palette_start: u32,
+    palette_end: u32,
+    pixel_offset: u32,
+    palette: [[u8; 3]; 256],
+    /// Padded row being collected and how much of it has arrived
+    row: [u8; MAX_STRIDE],
+    row_len: usize,
+    /// Rows decoded so far, in file order
+    rows: u16,
+    indices: [u8; MAX_ROW_PIXELS],
+}
+
+impl BmpDecoder {
+    /// Decoder for a bitmap of exactly `width` x `height` pixels
+    pub fn new(width: u16, height: u16) -> Self {
+        Self {
+            width: width.min(MAX_ROW_PIXELS as u16),
+            height,
+            state: State::Header,
+            position: 0,
+            header: [0; HEADER_BYTES],
+            bits: 8,
+            bottom_up: true,
+            palette_start: 0,
+            palette_end: 0,
+            pixel_offset: 0,
+            palette: [[0; 3]; 256],
+            row: [0; MAX_STRIDE],
+            row_len: 0,
+            rows: 0,
+            indices: [0; MAX_ROW_PIXELS],
+        }
+    }
+
+    /// True once every row has been decoded
+    pub fn is_complete(&self) -> bool {
+        self.state == State::Done
+    }
+
+    /// Decode from `input` until the next row is complete, see `ContainerDecoder::next_row`
+    pub fn next_row(&mut self, input: &mut &[u8]) -> Result<Option<Row<'_>>, &'static str> {
+        if !self.advance(input)? {
+            return Ok(None);
+        }
+        let y = if self.bottom_up {
+            self.height - self.rows
+        } else {
+            self.rows - 1
+        };
+        Ok(Some(Row {
+            y,
+            indices: &self.indices[..usize::from(self.width)],
+            palette: &self.palette,
+        }))
+    }
+
+    /// Bytes per stored row, including the padding
+    fn stride(&self) -> usize {
+        (usize::from(self.width) * usize::from(self.bits)).div_ceil(32) * 4
+    }
+
+    /// Consume `input` up to the end of the next row. Returns true if a row is ready.
+    fn advance(&mut self, input: &mut &[u8]) -> Result<bool, &'static str> {
+        while !input.is_empty() {
+            match self.state {
+                State::Header => {
+                    let start = self.position as usize;
+                    let n = (HEADER_BYTES - start).min(input.len());
+                    self.header[start..start + n].copy_from_slice(&input[..n]);
+                    self.consume(input, n);
+                    if self.position as usize == HEADER_BYTES {
+                        self.parse_header()?;
+                        self.state = State::BeforePixels;
+                    }
+                }
+                State::BeforePixels => {
+                    if self.position >= self.pixel_offset {
+                        self.state = State::Rows;
+                        continue;
+                    }
+                    if (self.palette_start..self.palette_end).contains(&self.position) {
+                        // Entries are stored blue, green, red, reserved
+                        let offset = (self.position - self.palette_start) as usize;
+                        if let Some(channel) = 2usize.checked_sub(offset % 4) {
+                            self.palette[offset / 4][channel] = input[0];
+                        }
+                    }
+                    self.consume(input, 1);
+                }
+                State::Rows => {
+                    let stride = self.stride();
+                    let n = (stride - self.row_len).min(input.len());
+                    self.row[self.row_len..self.row_len + n].copy_from_slice(&input[..n]);
+                    self.row_len += n;
+                    self.consume(input, n);
+                    if self.row_len == stride {
+                        self.row_len = 0;
+                        let width = usize::from(self.width);
+                        unpack_indices(&self.row, self.bits, &mut self.indices[..width]);
+                        self.rows += 1;
+                        if self.rows == self.height {
+                            self.state = State::Done;
+                        }
+                        return Ok(true);
+                    }
+                }
+                // Trailing bytes after the last row are ignored
+                State::Done => *input = &[],
+            }
+        }
+        Ok(false)
+    }
+
+    fn consume(&mut self, input: &mut &[u8], n: usize) {
+        *input = &input[n..];
+        self.position += n as u32;
+    }
+
+    /// Check the file and info headers against what the decoder supports
+    fn parse_header(&mut self) -> Result<(), &'static str> {
+        let h = &self.header;
+        let u16_at = |at: usize| u16::from_le_bytes([h[at], h[at + 1]]);
+        let u32_at = |at: usize| u32::from_le_bytes([h[at], h[at + 1], h[at + 2], h[at + 3]]);
+
+        let info_size = u32_at(14);
+        if !h.starts_with(b"BM") || !INFO_HEADER_SIZES.contains(&info_size) {
+            error!("Not a Windows bitmap (info header of {} bytes)", info_size);
+            return Err("Unsupported BMP header");
+        }
+        let width = u32_at(18) as i32;
+        let height = u32_at(22) as i32;
+        let bits = u16_at(28);
+        let compression = u32_at(30);
+        let colors_used = u32_at(46);
+
+        if !matches!(bits, 1 | 4 | 8) {
+            error!("BMP with {} bits per pixel, expected 1, 4 or 8", bits);
+            return Err("Unsupported BMP bit depth");
+        }
+        if compression != 0 {
+            error!("BMP compression {} not supported", compression);
+            return Err("Compressed BMP not supported");
+        }
+        if width != i32::from(self.width) || height.unsigned_abs() != u32::from(self.height) {
+            error!(
+                "BMP is {}x{} pixels, expected {}x{}",
+                width,
+                height.unsigned_abs(),
+                self.width,
+                self.height
+            );
+            return Err("Image size does not match the display");
+        }
+        let max_colors = 1u32 << bits;
+        let colors = if colors_used == 0 {
+            max_colors
+        } else {
+            colors_used
+        };
+        if colors > max_colors {
+            error!(
+                "BMP palette of {} colors for {} bits per pixel",
+                colors, bits
+            );
+            return Err("Invalid BMP palette");
+        }
+
+        self.bits = bits as u8;
+        self.bottom_up = height > 0;
+        self.palette_start = 14 + info_size;
+        self.palette_end = self.palette_start + colors * 4;
+        self.pixel_offset = u32_at(10);
+        if self.pixel_offset < self.palette_end {
+            error!(
+                "BMP pixel data at {} overlaps the palette",
+                self.pixel_offset
+            );
+            return Err("Invalid BMP header");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec;
+    use std::vec::Vec;
+
+    use super::*;
+
+    /// Bitmap file of `width` x `height` (negative: top-down) pixels with the
+    /// given palette and rows, each row already packed and padded as stored
+    fn bitmap(width: i32, height: i32, bits: u16, palette: &[[u8; 3]], rows: &[&[u8]]) -> Vec<u8> {
+        let pixel_offset = (HEADER_BYTES + palette.len() * 4) as u32;
+        let mut file = Vec::new();
+        file.extend_from_slice(b"BM");
+        file.extend_from_slice(&0u32.to_le_bytes());
+        file.extend_from_slice(&0u32.to_le_bytes());
+        file.extend_from_slice(&pixel_offset.to_le_bytes());
+        file.extend_from_slice(&40u32.to_le_bytes());
+        file.extend_from_slice(&width.to_le_bytes());
+        file.extend_from_slice(&height.to_le_bytes());
+        file.extend_from_slice(&1u16.to_le_bytes());
+        file.extend_from_slice(&bits.to_le_bytes());
+        // Compression, image size and resolution
+        file.extend_from_slice(&[0; 16]);
+        file.extend_from_slice(&(palette.len() as u32).to_le_bytes());
+        file.extend_from_slice(&0u32.to_le_bytes());
+        for &[r, g, b] in palette {
+            file.extend_from_slice(&[b, g, r, 0]);
+        }
+        for row in rows {
+            file.extend_from_slice(row);
+        }
+        file
+    }
+
+    /// Rows (y, indices, RGB of each pixel) decoded from `file` fed in `chunk` sized pieces
+    #[allow(clippy::type_complexity)]
+    fn decode(
+        decoder: &mut BmpDecoder,
+        file: &[u8],
+        chunk: usize,
+    ) -> Result<Vec<(u16, Vec<u8>, Vec<[u8; 3]>)>, &'static str> {
+        let mut rows = Vec::new();
+        for mut piece in file.chunks(chunk) {
+            while let Some(row) = decoder.next_row(&mut piece)? {
+                let colors = row
+                    .indices
+                    .iter()
+                    .map(|&i| row.palette[usize::from(i)])
+                    .collect();
+                rows.push((row.y, row.indices.to_vec(), colors));
+            }
+            assert!(piece.is_empty());
+        }
+        Ok(rows)
+    }
+
+    const RED: [u8; 3] = [255, 0, 0];
+    const GREEN: [u8; 3] = [0, 255, 0];
+    const BLUE: [u8; 3] = [0, 0, 255];
+
+    #[test]
+    fn bottom_up_rows_arrive_last_row_first() {
+        let file = bitmap(
+            3,
+            2,
+            8,
+            &[RED, GREEN, BLUE],
+            &[&[2, 1, 0, 0], &[0, 1, 2, 0]],
+        );
+        for chunk in 1..=file.len() {
+            let mut decoder = BmpDecoder::new(3, 2);
+            let rows = decode(&mut decoder, &file, chunk).unwrap();
+            assert_eq!(
+                rows,
+                [
+                    (1, vec![2, 1, 0], vec![BLUE, GREEN, RED]),
+                    (0, vec![0, 1, 2], vec![RED, GREEN, BLUE]),
+                ],
+                "chunk {chunk}"
+            );
+            assert!(decoder.is_complete());
+        }
+    }
+
+    #[test]
+    fn top_down_rows_arrive_first_row_first() {
+        // 10 pixels at 1 bit: two bytes of pixels padded to four
+        let file = bitmap(
+            10,
+            -2,
+            1,
+            &[[0; 3], [255; 3]],
+            &[
+                &[0b1010_0000, 0b0100_0000, 0, 0],
+                &[0b0000_1111, 0b1100_0000, 0, 0],
+            ],
+        );
+        let mut decoder = BmpDecoder::new(10, 2);
+        let rows = decode(&mut decoder, &file, 5).unwrap();
+        let indices: Vec<_> = rows
+            .iter()
+            .map(|(y, indices, _)| (*y, indices.clone()))
+            .collect();
+        assert_eq!(
+            indices,
+            [
+                (0, vec![1, 0, 1, 0, 0, 0, 0, 0, 0, 1]),
+                (1, vec![0, 0, 0, 0, 1, 1, 1, 1, 1, 1]),
+            ]
+        );
+        assert!(decoder.is_complete());
+    }
+
+    #[test]
+    fn index_outside_the_palette_is_black() {
+        // Two palette entries, but 4-bit pixels can name sixteen
+        let file = bitmap(2, 1, 4, &[RED, GREEN], &[&[0x19, 0, 0, 0]]);
+        let mut decoder = BmpDecoder::new(2, 1);
+        let rows = decode(&mut decoder, &file, 64).unwrap();
+        assert_eq!(rows, [(0, vec![1, 9], vec![GREEN, [0; 3]])]);
+    }
+
+    #[test]
+    fn rejects_a_palette_larger_than_the_bit_depth() {
+        let file = bitmap(2, 1, 1, &[RED, GREEN, BLUE], &[&[0; 4]]);
+        let mut decoder = BmpDecoder::new(2, 1);
+        assert_eq!(decode(&mut decoder, &file, 64), Err("Invalid BMP palette"));
+    }
+
+    #[test]
+    fn rejects_other_sizes() {
+        let size_mismatch = Err("Image size does not match the display");
+        for (width, height) in [
+            (4, 2),
+            (3, 3),
+            (3, -3),
+            (-3, 2),
+            (i32::MAX, 2),
+            (3, i32::MIN),
+        ] {
+            let file = bitmap(width, height, 8, &[RED], &[]);
+            let mut decoder = BmpDecoder::new(3, 2);
+            assert_eq!(
+                decode(&mut decoder, &file, 64),
+                size_mismatch,
+                "{width}x{height}"
+            );
+        }
+
+        // Rows wider than the decoder can hold are refused even when expected
+        let width = MAX_ROW_PIXELS as u16 + 4;
+        let file = bitmap(i32::from(width), 1, 8, &[RED], &[]);
+        let mut decoder = BmpDecoder::new(width, 1);
+        assert_eq!(decode(&mut decoder, &file, 64), size_mismatch);
+    }
+
+    #[test]
+    fn truncated_file_is_incomplete() {
+        let file = bitmap(3, 2, 8, &[RED], &[&[0; 4], &[0; 4]]);
+        let mut decoder = BmpDecoder::new(3, 2);
+        let rows = decode(&mut decoder, &file[..file.len() - 1], 64).unwrap();
+        assert_eq!(rows.len(), 1);
+        assert!(!decoder.is_complete());
+    }
+}
@@ -0,0 +1,474 @@
+//! Streaming RGB to panel palette conversion
+//!
+//! Pixels arrive row by row (top to bottom, left to right) as 24-bit RGB,
+//! 8-bit RGB (3-3-2) or 8-bit gray and are mapped to the nearest panel color
+//! with one of three methods:
+//! - `None`: plain nearest color
+//! - `Ordered`: 4x4 Bayer pattern mixing the nearest color with the one partner
+//!   that best completes it; stable patterns, but neutral mid-grays may pick up
+//!   a tint on the 7-color palette (a full pair search is too slow at 5 MHz)
+//! - `FloydSteinberg`: error diffusion; the error for the next row is kept in
+//!   a single row buffer, so memory does not depend on the image height
+//!
+//! The output is packed frame bytes in the panel's pixel format, one padded
+//! row per input row, ready for an `ImageSink`.
+
+use super::MAX_ROW_PIXELS;
+use crate::epd::{BITS_PER_PIXEL, CLEAN, PALETTE};
+
+/// Dither candidates: palette entries below the clean color
+const COLORS: usize = if PALETTE.len() < CLEAN as usize {
+    PALETTE.len()
+} else {
+    CLEAN as usize
+};
+
+/// 4x4 Bayer matrix, thresholds 0..16
+const BAYER_4X4: [[i16; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// Pixel format of the incoming stream, from the `X-Image-Format` header
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum PixelFormat {
+    /// Frame already in the panel's pixel format, no conversion
+    Native,
+    /// Three bytes per pixel: red, green, blue
+    Rgb888,
+    /// One byte per pixel: red in bits 7-5, green in 4-2, blue in 1-0
+    Rgb332,
+    /// One byte per pixel, 0 = black, 255 = white
+    Gray8,
+}
+
+impl PixelFormat {
+    /// Format for an `X-Image-Format` value
+    pub fn from_name(name: &str) -> Option<Self> {
+        let format = match name {
+            "native" => Self::Native,
+            "rgb888" | "rgb24" => Self::Rgb888,
+            "rgb332" => Self::Rgb332,
+            "gray8" | "grey8" => Self::Gray8,
+            _ => return None,
+        };
+        Some(format)
+    }
+
+    /// Bytes per input pixel
+    const fn bytes_per_pixel(self) -> usize {
+        match self {
+            Self::Rgb888 => 3,
+            Self::Native | Self::Rgb332 | Self::Gray8 => 1,
+        }
+    }
+
+    /// RGB of one input pixel
+    fn rgb(self, bytes: &[u8]) -> [u8; 3] {
+        match self {
+            Self::Rgb888 => [bytes[0], bytes[1], bytes[2]],
+            Self::Rgb332 => {
+                let b = bytes[0];
+                [
+                    expand(b >> 5, 3),
+                    expand((b >> 2) & 0x7, 3),
+                    expand(b & 0x3, 2),
+                ]
+            }
+            Self::Native | Self::Gray8 => [bytes[0]; 3],
+        }
+    }
+}
+
+/// Scale a `bits` wide channel value to 0..=255
+fn expand(value: u8, bits: u32) -> u8 {
+    let max = (1u16 << bits) - 1;
+    (u16::from(value) * 255 / max) as u8
+}
+
+/// How RGB pixels are reduced to the panel palette
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum DitherMethod {
+    /// Nearest color, no dithering
+    None,
+    /// 4x4 Bayer ordered dithering
+    Ordered,
+    /// Floyd-Steinberg error diffusion
+    FloydSteinberg,
+}
+
+impl DitherMethod {
+    /// Method for its config/`X-Dither` name
+    pub fn from_name(name: &str) -> Option<Self> {
+        let method = match name {
+            "none" => Self::None,
+            "ordered" | "bayer" => Self::Ordered,
+            "floyd-steinberg" => Self::FloydSteinberg,
+            _ => return None,
+        };
+        Some(method)
+    }
+}
+
+/// Squared RGB distance between `rgb` and palette entry `color`
+fn distance(rgb: [i16; 3], color: [u8; 3]) -> i32 {
+    (0..3)
+        .map(|c| {
+            let d = i32::from(rgb[c]) - i32::from(color[c]);
+            d * d
+        })
+        .sum()
+}
+
+/// Nearest palette entry to `rgb`, as a panel pixel value and its RGB
+fn nearest_color(rgb: [i16; 3]) -> (u8, [i16; 3]) {
+    let mut best = (0, i32::MAX);
+    for (index, &color) in PALETTE[..COLORS].iter().enumerate() {
+        let distance = distance(rgb, color);
+        if distance < best.1 {
+            best = (index, distance);
+        }
+    }
+    (best.0 as u8, PALETTE[best.0].map(i16::from))
+}
+
+/// Two palette entries to mix for `rgb`: the nearest one, and the partner
+/// whose mix with it comes closest to `rgb`. Returns both as panel pixel values
+/// and the share of the partner in sixteenths (0..=16).
+fn nearest_pair(rgb: [i16; 3]) -> (u8, u8, i16) {
+    let (first, _) = nearest_color(rgb);
+    let a = PALETTE[usize::from(first)];
+    let mut best = (first, 0, distance(rgb, a));
+    for (index, &b) in PALETTE[..COLORS].iter().enumerate() {
+        // Project `rgb` onto the line between both colors
+        let (mut along, mut length) = (0i32, 0i32);
+        for c in 0..3 {
+            let step = i32::from(b[c]) - i32::from(a[c]);
+            along += (i32::from(rgb[c]) - i32::from(a[c])) * step;
+            length += step * step;
+        }
+        if length == 0 {
+            continue;
+        }
+        let mix = ((along * 16 + length / 2) / length).clamp(0, 16);
+        let mixed: [i16; 3] = core::array::from_fn(|c| {
+            let step = i32::from(b[c]) - i32::from(a[c]);
+            (i32::from(a[c]) + step * mix / 16) as i16
+        });
+        let error = mixed
+            .iter()
+            .zip(rgb)
+            .map(|(&m, r)| (i32::from(m) - i32::from(r)).pow(2))
+            .sum();
+        if error < best.2 {
+            best = (index as u8, mix as i16, error);
+        }
+    }
+    (first, best.0, best.1)
+}
+
+/// Incremental converter from an RGB/gray stream to packed frame bytes
+pub struct Ditherer {
+    format: PixelFormat,
+    method: DitherMethod,
+    width: u16,
+    /// Position of the next pixel
+    x: u16,
+    y: u16,
+    /// Bytes of a pixel split across input chunks
+    pixel: [u8; 3],
+    pixel_len: u8,
+    /// Error diffused into the next row, offset by one (index `x + 1`)
+    errors: [[i16; 3]; MAX_ROW_PIXELS + 2],
+    /// Error carried to the right neighbour
+    carry: [i16; 3],
+    /// Error for the next row's pixel below-right, written once that slot is read
+    pending: [i16; 3],
+    /// Output byte being filled and the number of pixels in it
+    byte: u8,
+    byte_pixels: u8,
+}
+
+impl Ditherer {
+    /// Converter for `width` pixels wide rows (at most the longer panel side)
+    pub fn new(format: PixelFormat, method: DitherMethod, width: u16) -> Self {
+        Self {
+            format,
+            method,
+            width: width.min(MAX_ROW_PIXELS as u16),
+            x: 0,
+            y: 0,
+            pixel: [0; 3],
+            pixel_len: 0,
+            errors: [[0; 3]; MAX_ROW_PIXELS + 2],
+            carry: [0; 3],
+            pending: [0; 3],
+            byte: 0,
+            byte_pixels: 0,
+        }
+    }
+
+    /// True when the input ended after a complete row
+    pub fn is_complete(&self) -> bool {
+        self.x == 0 && self.pixel_len == 0
+    }
+
+    /// Convert as many pixels from `input` as fit into `output`, advancing `input`.
+    /// Returns the number of frame bytes written; 0 only once `input` is empty.
+    pub fn convert(&mut self, input: &mut &[u8], output: &mut [u8]) -> usize {
+        let pixel_bytes = self.format.bytes_per_pixel();
+        let mut written = 0;
+        // A pixel can complete a byte and the row padding at once, so keep room for two
+        while !input.is_empty() && written + 2 <= output.len() {
+            let take = (pixel_bytes - usize::from(self.pixel_len)).min(input.len());
+            let start = usize::from(self.pixel_len);
+            self.pixel[start..start + take].copy_from_slice(&input[..take]);
+            *input = &input[take..];
+            self.pixel_len += take as u8;
+            if usize::from(self.pixel_len) < pixel_bytes {
+                break;
+            }
+            self.pixel_len = 0;
+
+            let rgb = self.format.rgb(&self.pixel[..pixel_bytes]);
+            written += self.convert_pixel(rgb, &mut output[written..]);
+        }
+        written
+    }
+
+    /// Convert one already decoded pixel (BMP/PNG palette entry), ignoring `format`.
+    /// `output` needs room for two bytes; returns the number of bytes written.
+    pub fn convert_pixel(&mut self, rgb: [u8; 3], output: &mut [u8]) -> usize {
+        let value = self.quantize(rgb);
+        self.push(value, output)
+    }
+
+    /// Pick the panel value for the pixel at (`x`, `y`) and update the error state
+    fn quantize(&mut self, rgb: [u8; 3]) -> u8 {
+        let x = usize::from(self.x);
+        let mut wanted = rgb.map(i16::from);
+
+        match self.method {
+            DitherMethod::None => nearest_color(wanted).0,
+            DitherMethod::Ordered => {
+                let threshold = BAYER_4X4[usize::from(self.y) % 4][x % 4];
+                let (first, second, mix) = nearest_pair(wanted);
+                if mix > threshold { second } else { first }
+            }
+            DitherMethod::FloydSteinberg => {
+                for (c, channel) in wanted.iter_mut().enumerate() {
+                    let incoming = self.errors[x + 1][c] + self.carry[c];
+                    *channel = (*channel + incoming).clamp(0, 255);
+                }
+                let (value, shown) = nearest_color(wanted);
+                for c in 0..3 {
+                    let error = wanted[c] - shown[c];
+                    self.errors[x][c] += error * 3 / 16;
+                    self.errors[x + 1][c] = error * 5 / 16 + self.pending[c];
+                    self.pending[c] = error / 16;
+                    self.carry[c] = error * 7 / 16;
+                }
+                value
+            }
+        }
+    }
+
+    /// Pack one pixel value, finishing the row after `width` pixels.
+    /// Returns the number of bytes completed.
+    fn push(&mut self, value: u8, output: &mut [u8]) -> usize {
+        let pixels_per_byte = (8 / BITS_PER_PIXEL) as u8;
+        let mut written = 0;
+        self.byte = (self.byte << BITS_PER_PIXEL) | value;
+        self.byte_pixels += 1;
+        if self.byte_pixels == pixels_per_byte {
+            output[written] = self.byte;
+            written += 1;
+            self.byte = 0;
+            self.byte_pixels = 0;
+        }
+
+        self.x += 1;
+        if self.x == self.width {
+            // Rows start on a byte boundary, pad the last byte
+            if self.byte_pixels > 0 {
+                let missing = pixels_per_byte - self.byte_pixels;
+                output[written] = self.byte << (u32::from(missing) * BITS_PER_PIXEL as u32);
+                written += 1;
+                self.byte = 0;
+                self.byte_pixels = 0;
+            }
+            self.x = 0;
+            self.y += 1;
+            self.carry = [0; 3];
+            self.pending = [0; 3];
+            // Left of the first pixel, never read
+            self.errors[0] = [0; 3];
+        }
+        written
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec;
+    use std::vec::Vec;
+
+    use super::*;
+    use crate::epd::{BLACK, WHITE, row_bytes};
+
+    const B: u8 = BLACK;
+    const W: u8 = WHITE;
+
+    /// Convert `input` in `chunk` sized pieces through a `out_len` byte output buffer
+    fn convert(
+        format: PixelFormat,
+        method: DitherMethod,
+        width: u16,
+        input: &[u8],
+        chunk: usize,
+        out_len: usize,
+    ) -> Vec<u8> {
+        let mut ditherer = Ditherer::new(format, method, width);
+        let mut frame = Vec::new();
+        let mut out = vec![0; out_len];
+        for mut piece in input.chunks(chunk) {
+            while !piece.is_empty() {
+                let n = ditherer.convert(&mut piece, &mut out);
+                frame.extend_from_slice(&out[..n]);
+            }
+        }
+        assert!(ditherer.is_complete());
+        frame
+    }
+
+    /// Panel pixel values of a packed frame, row by row
+    fn pixels(frame: &[u8], width: u16) -> Vec<Vec<u8>> {
+        let mask = ((1u16 << BITS_PER_PIXEL) - 1) as u8;
+        frame
+            .chunks(row_bytes(width))
+            .map(|row| {
+                (0..usize::from(width))
+                    .map(|x| {
+                        let bit = x * BITS_PER_PIXEL;
+                        (row[bit / 8] >> (8 - BITS_PER_PIXEL - bit % 8)) & mask
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn nearest_color_without_dithering() {
+        let input = [
+            0, 0, 0, 255, 255, 255, 40, 30, 20, 220, 230, 240, 10, 10, 10,
+        ];
+        let frame = convert(PixelFormat::Rgb888, DitherMethod::None, 5, &input, 64, 64);
+        assert_eq!(frame.len(), row_bytes(5));
+        assert_eq!(pixels(&frame, 5), [[B, W, B, W, B]]);
+    }
+
+    #[test]
+    fn ordered_dither_follows_the_bayer_pattern() {
+        // A quarter of the way to white: the 4 of 16 thresholds below 4 turn white
+        let input = [64; 8 * 4];
+        let frame = convert(PixelFormat::Gray8, DitherMethod::Ordered, 8, &input, 64, 64);
+        assert_eq!(
+            pixels(&frame, 8),
+            [
+                [W, B, W, B, W, B, W, B],
+                [B, B, B, B, B, B, B, B],
+                [W, B, W, B, W, B, W, B],
+                [B, B, B, B, B, B, B, B],
+            ]
+        );
+    }
+
+    /// Brightest gray level still shown as black; any error added to it changes the color
+    fn darkest_gray_limit() -> u8 {
+        (0..=255)
+            .take_while(|&level| nearest_color([i16::from(level); 3]).0 == BLACK)
+            .last()
+            .unwrap()
+    }
+
+    /// Floyd-Steinberg over gray `rows`, as panel pixel values
+    fn floyd_steinberg(rows: &[&[u8]]) -> Vec<Vec<u8>> {
+        let width = rows[0].len() as u16;
+        let input = rows.concat();
+        let frame = convert(
+            PixelFormat::Gray8,
+            DitherMethod::FloydSteinberg,
+            width,
+            &input,
+            64,
+            64,
+        );
+        pixels(&frame, width)
+    }
+
+    #[test]
+    fn floyd_steinberg_carries_the_error_right() {
+        let limit = darkest_gray_limit();
+        let shown = floyd_steinberg(&[&[limit, limit]]);
+        assert_eq!(shown[0][0], B);
+        // 7/16 of the first pixel's error lifts its neighbour out of black
+        assert_ne!(shown[0][1], B);
+
+        // Without dithering both stay black
+        let frame = convert(
+            PixelFormat::Gray8,
+            DitherMethod::None,
+            2,
+            &[limit; 2],
+            64,
+            64,
+        );
+        assert_eq!(pixels(&frame, 2), [[B, B]]);
+    }
+
+    #[test]
+    fn floyd_steinberg_passes_the_error_down() {
+        let limit = darkest_gray_limit();
+        // Straight down (5/16), in a single column
+        let shown = floyd_steinberg(&[&[limit], &[limit]]);
+        assert_eq!(shown[0][0], B);
+        assert_ne!(shown[1][0], B);
+
+        // Down and left (3/16)
+        let shown = floyd_steinberg(&[&[0, limit], &[limit, 0]]);
+        assert_eq!(shown[0], [B, B]);
+        assert_ne!(shown[1][0], B);
+    }
+
+    #[test]
+    fn floyd_steinberg_keeps_errors_within_their_row() {
+        let limit = darkest_gray_limit();
+        // The error right of the last pixel is dropped, not carried to the next row's first
+        let shown = floyd_steinberg(&[&[0, 0, limit], &[limit, 0, 0]]);
+        assert_eq!(shown[0], [B, B, B]);
+        assert_eq!(shown[1][0], B);
+    }
+
+    #[test]
+    fn output_does_not_depend_on_chunking() {
+        let (width, height) = (13, 5);
+        let input: Vec<u8> = (0..width * height * 3)
+            .map(|i| (i * 37 % 256) as u8)
+            .collect();
+        for method in [
+            DitherMethod::None,
+            DitherMethod::Ordered,
+            DitherMethod::FloydSteinberg,
+        ] {
+            let whole = convert(
+                PixelFormat::Rgb888,
+                method,
+                width as u16,
+                &input,
+                input.len(),
+                256,
+            );
+            assert_eq!(whole.len(), row_bytes(width as u16) * height);
+            // Pixels split across chunks and an output buffer with room for a single pixel
+            let split = convert(PixelFormat::Rgb888, method, width as u16, &input, 2, 2);
+            assert_eq!(whole, split, "{method:?}");
+        }
+    }
+}
@@ -0,0 +1,114 @@
+//! Image transport decoders and pixel conversion
+//! Turn the HTTP body into panel frame bytes before they reach an `ImageSink`.
+
+pub mod bmp;
+pub mod dither;
+pub mod packbits;
+pub mod png;
+
+use crate::epd::{PANEL_HEIGHT, PANEL_WIDTH};
+use bmp::BmpDecoder;
+use png::PngDecoder;
+
+/// Widest image row the decoders hold (portrait images are `PANEL_HEIGHT` wide)
+pub const MAX_ROW_PIXELS: usize = if PANEL_WIDTH > PANEL_HEIGHT {
+    PANEL_WIDTH as usize
+} else {
+    PANEL_HEIGHT as usize
+};
+
+/// Standard image file format of the body, decoded on the device
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Container {
+    /// Uncompressed 1/4/8-bit indexed Windows bitmap
+    Bmp,
+    /// Paletted or gray PNG, 1 to 8 bits per pixel, not interlaced
+    Png,
+}
+
+impl Container {
+    /// Container for a `Content-Type` value (parameters are ignored)
+    pub fn from_content_type(value: &str) -> Option<Self> {
+        let mime = value.split(';').next()?.trim();
+        if mime.eq_ignore_ascii_case("image/png") {
+            Some(Self::Png)
+        } else if ["image/bmp", "image/x-bmp", "image/x-ms-bmp"]
+            .iter()
+            .any(|bmp| mime.eq_ignore_ascii_case(bmp))
+        {
+            Some(Self::Bmp)
+        } else {
+            None
+        }
+    }
+
+    /// Container recognized by the first bytes of the body
+    pub fn sniff(data: &[u8]) -> Option<Self> {
+        if data.starts_with(&png::SIGNATURE) {
+            Some(Self::Png)
+        } else if bmp::has_signature(data) {
+            Some(Self::Bmp)
+        } else {
+            None
+        }
+    }
+}
+
+/// One decoded image row
+pub struct Row<'a> {
+    /// Row number from the top of the image
+    pub y: u16,
+    /// One palette index per pixel
+    pub indices: &'a [u8],
+    /// RGB of every index (256 entries, unused ones are black)
+    pub palette: &'a [[u8; 3]],
+}
+
+/// Incremental decoder for a BMP or PNG body
+// Only one decoder exists per download, the PNG inflate window dominates
+#[allow(clippy::large_enum_variant)]
+pub enum ContainerDecoder {
+    Bmp(BmpDecoder),
+    Png(PngDecoder),
+}
+
+impl ContainerDecoder {
+    /// Decoder for an image that must be exactly `width` x `height` pixels
+    pub fn new(container: Container, width: u16, height: u16) -> Self {
+        match container {
+            Container::Bmp => Self::Bmp(BmpDecoder::new(width, height)),
+            Container::Png => Self::Png(PngDecoder::new(width, height)),
+        }
+    }
+
+    /// Decode from `input` until the next row is complete, advancing `input`.
+    /// Returns `None` once `input` is used up without completing a row.
+    pub fn next_row(&mut self, input: &mut &[u8]) -> Result<Option<Row<'_>>, &'static str> {
+        match self {
+            Self::Bmp(decoder) => decoder.next_row(input),
+            Self::Png(decoder) => decoder.next_row(input),
+        }
+    }
+
+    /// True once every row of the image has been decoded
+    pub fn is_complete(&self) -> bool {
+        match self {
+            Self::Bmp(decoder) => decoder.is_complete(),
+            Self::Png(decoder) => decoder.is_complete(),
+        }
+    }
+}
+
+/// Split MSB-first packed `bits` wide pixels into one index per byte
+fn unpack_indices(packed: &[u8], bits: u8, indices: &mut [u8]) {
+    if bits == 8 {
+        indices.copy_from_slice(&packed[..indices.len()]);
+        return;
+    }
+    let per_byte = usize::from(8 / bits);
+    let mask = (1u8 << bits) - 1;
+    for (x, index) in indices.iter_mut().enumerate() {
+        let shift = 8 - bits * (1 + (x % per_byte) as u8);
+        *index = (packed[x / per_byte] >> shift) & mask;
+    }
+}
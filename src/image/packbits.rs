@@ -0,0 +1,196 @@
+//! Streaming PackBits (Apple/TIFF run-length) decoder
+//!
+//! Each run starts with a signed header byte `n`:
+//! - `0..=127`: copy the next `n + 1` bytes literally
+//! - `-127..=-1`: repeat the next byte `1 - n` times
+//! - `-128`: no-op
+//!
+//! The decoder keeps its state between calls, so runs may be split across
+//! HTTP chunks in any way.
+
+/// Decoder state between input chunks
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Expecting a run header
+    #[default]
+    Header,
+    /// Copying this many more literal bytes
+    Literal(u8),
+    /// Waiting for the byte to repeat this many times
+    RepeatByte(u8),
+    /// Emitting `byte` this many more times
+    Repeat { byte: u8, remaining: u8 },
+}
+
+/// Incremental PackBits decoder
+#[derive(Default)]
+pub struct PackBitsDecoder {
+    state: State,
+}
+
+impl PackBitsDecoder {
+    pub const fn new() -> Self {
+        Self {
+            state: State::Header,
+        }
+    }
+
+    /// True when the input ended on a run boundary
+    pub fn is_complete(&self) -> bool {
+        self.state == State::Header
+    }
+
+    /// Decode from `input` into `out`, advancing `input` past the consumed bytes.
+    /// Returns the number of bytes written to `out`; call again while `input`
+    /// is not empty (the output buffer may have filled up first).
+    pub fn decode(&mut self, input: &mut &[u8], out: &mut [u8]) -> usize {
+        let mut written = 0;
+
+        while written < out.len() {
+            match self.state {
+                State::Header => {
+                    let Some((&header, rest)) = input.split_first() else {
+                        break;
+                    };
+                    *input = rest;
+                    self.state = match header {
+                        0..=127 => State::Literal(header + 1),
+                        128 => State::Header,
+                        _ => State::RepeatByte((257 - u16::from(header)) as u8),
+                    };
+                }
+                State::Literal(remaining) => {
+                    let n = usize::from(remaining)
+                        .min(input.len())
+                        .min(out.len() - written);
+                    if n == 0 {
+                        break;
+                    }
+                    out[written..written + n].copy_from_slice(&input[..n]);
+                    *input = &input[n..];
+                    written += n;
+                    let remaining = remaining - n as u8;
+                    self.state = if remaining == 0 {
+                        State::Header
+                    } else {
+                        State::Literal(remaining)
+                    };
+                }
+                State::RepeatByte(count) => {
+                    let Some((&byte, rest)) = input.split_first() else {
+                        break;
+                    };
+                    *input = rest;
+                    self.state = State::Repeat {
+                        byte,
+                        remaining: count,
+                    };
+                }
+                State::Repeat { byte, remaining } => {
+                    let n = usize::from(remaining).min(out.len() - written);
+                    out[written..written + n].fill(byte);
+                    written += n;
+                    let remaining = remaining - n as u8;
+                    self.state = if remaining == 0 {
+                        State::Header
+                    } else {
+                        State::Repeat { byte, remaining }
+                    };
+                }
+            }
+        }
+
+        written
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec;
+    use std::vec::Vec;
+
+    use super::*;
+
+    /// Literal "abc", "x" repeated 3 times, a no-op, literal "z", "y" repeated 128 times
+    const ENCODED: &[u8] = &[2, b'a', b'b', b'c', 0xFE, b'x', 0x80, 0, b'z', 0x81, b'y'];
+
+    fn expected() -> Vec<u8> {
+        let mut bytes = b"abcxxxz".to_vec();
+        bytes.extend([b'y'; 128]);
+        bytes
+    }
+
+    /// Feeds `chunks` one after the other through an output buffer of `out_len` bytes
+    fn decode_chunks(decoder: &mut PackBitsDecoder, chunks: &[&[u8]], out_len: usize) -> Vec<u8> {
+        let mut decoded = Vec::new();
+        let mut out = vec![0; out_len];
+        for chunk in chunks {
+            let mut input = *chunk;
+            loop {
+                let n = decoder.decode(&mut input, &mut out);
+                decoded.extend_from_slice(&out[..n]);
+                if input.is_empty() && n < out.len() {
+                    break;
+                }
+            }
+        }
+        decoded
+    }
+
+    #[test]
+    fn decodes_in_one_chunk() {
+        let mut decoder = PackBitsDecoder::new();
+        assert_eq!(decode_chunks(&mut decoder, &[ENCODED], 256), expected());
+        assert!(decoder.is_complete());
+    }
+
+    #[test]
+    fn decodes_split_at_every_byte() {
+        for split in 0..=ENCODED.len() {
+            let (head, tail) = ENCODED.split_at(split);
+            let mut decoder = PackBitsDecoder::new();
+            assert_eq!(
+                decode_chunks(&mut decoder, &[head, tail], 256),
+                expected(),
+                "split at {split}"
+            );
+            assert!(decoder.is_complete(), "split at {split}");
+        }
+    }
+
+    #[test]
+    fn decodes_byte_by_byte_into_a_small_buffer() {
+        let chunks: Vec<&[u8]> = ENCODED.chunks(1).collect();
+        let mut decoder = PackBitsDecoder::new();
+        assert_eq!(decode_chunks(&mut decoder, &chunks, 2), expected());
+        assert!(decoder.is_complete());
+    }
+
+    #[test]
+    fn stops_when_the_output_is_full() {
+        let mut decoder = PackBitsDecoder::new();
+        let mut input = ENCODED;
+        let mut out = [0; 5];
+        assert_eq!(decoder.decode(&mut input, &mut out), 5);
+        assert_eq!(&out, b"abcxx");
+        assert_eq!(input, &ENCODED[6..]);
+        assert!(!decoder.is_complete());
+    }
+
+    #[test]
+    fn truncated_final_run_is_incomplete() {
+        // Repeat header without its byte
+        let mut decoder = PackBitsDecoder::new();
+        assert_eq!(decode_chunks(&mut decoder, &[&[0xFD]], 16), b"");
+        assert!(!decoder.is_complete());
+
+        // Literal run one byte short
+        let mut decoder = PackBitsDecoder::new();
+        assert_eq!(decode_chunks(&mut decoder, &[&[2, b'a', b'b']], 16), b"ab");
+        assert!(!decoder.is_complete());
+
+        // The missing byte in the next chunk completes it
+        assert_eq!(decode_chunks(&mut decoder, &[b"c"], 16), b"c");
+        assert!(decoder.is_complete());
+    }
+}
@@ -0,0 +1,677 @@
+//! Streaming decoder for paletted and gray PNG images
+//!
+//! The file is an 8-byte signature followed by chunks (length, type, data,
+//! CRC-32). `IHDR` gives the size and pixel format, `PLTE` the palette and the
+//! `IDAT` chunks together hold one zlib stream of filtered rows: each row is a
+//! filter type byte followed by the packed pixels. Other chunks are skipped.
+//!
+//! Inflating needs the last 32 KB of output as dictionary, so the decoder holds
+//! a 32 KB window plus the inflate state (about 44 KB in total) while a PNG is
+//! being downloaded. Only non-interlaced images with 1, 2, 4 or 8 bits per
+//! pixel (palette or gray) are supported.
+
+use crc::{CRC_32_ISO_HDLC, Crc, Digest};
+use defmt::error;
+use miniz_oxide::inflate::TINFLStatus;
+use miniz_oxide::inflate::core::inflate_flags::{
+    TINFL_FLAG_HAS_MORE_INPUT, TINFL_FLAG_PARSE_ZLIB_HEADER,
+};
+use miniz_oxide::inflate::core::{DecompressorOxide, decompress};
+
+use super::{MAX_ROW_PIXELS, Row, unpack_indices};
+
+/// First bytes of every PNG file
+pub const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+/// Deflate dictionary size, the inflate output is kept in a ring of this size
+const WINDOW_SIZE: usize = 32 * 1024;
+
+/// Data bytes of the `IHDR` chunk
+const IHDR_BYTES: usize = 13;
+
+/// Color types from `IHDR`
+const COLOR_GRAY: u8 = 0;
+const COLOR_PALETTE: u8 = 3;
+
+static CHUNK_CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+/// Part of the file the next byte belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Signature,
+    /// Length and type of the next chunk
+    ChunkHeader,
+    /// This many data bytes of the current chunk left
+    ChunkData(u32),
+    ChunkCrc,
+    Done,
+}
+
+/// Incremental PNG decoder
+pub struct PngDecoder {
+    width: u16,
+    height: u16,
+    state: State,
+    /// Signature, chunk header or CRC being collected
+    field: [u8; 8],
+    field_len: usize,
+    header: [u8; IHDR_BYTES],
+    chunk_type: [u8; 4],
+    chunk_len: u32,
+    crc: Digest<'static, u32>,
+    seen_header: bool,
+    seen_palette: bool,
+    color_type: u8,
+    bits: u8,
+    palette: [[u8; 3]; 256],
+
+    inflater: DecompressorOxide,
+    window: [u8; WINDOW_SIZE],
+    /// Inflated bytes not yet taken into rows: `window[read..write]`
+    read: usize,
+    write: usize,
+    /// The inflater stopped with output left over
+    more_output: bool,
+    inflate_done: bool,
+
+    /// Filter type and bytes of the row being collected (`None` before its filter byte)
+    filter: Option<u8>,
+    row: [u8; MAX_ROW_PIXELS],
+    row_len: usize,
+    /// Previous unfiltered row, zero before the first
+    previous: [u8; MAX_ROW_PIXELS],
+    /// Rows decoded so far
+    rows: u16,
+    indices: [u8; MAX_ROW_PIXELS],
+}
+
+impl PngDecoder {
+    /// Decoder for an image of exactly `width` x `height` pixels
+    pub fn new(width: u16, height: u16) -> Self {
+        Self {
+            width: width.min(MAX_ROW_PIXELS as u16),
+            height,
+            state: State::Signature,
+            field: [0; 8],
+            field_len: 0,
+            header: [0; IHDR_BYTES],
+            chunk_type: [0; 4],
+            chunk_len: 0,
+            crc: CHUNK_CRC.digest(),
+            seen_header: false,
+            seen_palette: false,
+            color_type: COLOR_PALETTE,
+            bits: 8,
+            palette: [[0; 3]; 256],
+            inflater: DecompressorOxide::new(),
+            window: [0; WINDOW_SIZE],
+            read: 0,
+            write: 0,
+            more_output: false,
+            inflate_done: false,
+            filter: None,
+            row: [0; MAX_ROW_PIXELS],
+            row_len: 0,
+            previous: [0; MAX_ROW_PIXELS],
+            rows: 0,
+            indices: [0; MAX_ROW_PIXELS],
+        }
+    }
+
+    /// True once every row has been decoded
+    pub fn is_complete(&self) -> bool {
+        self.rows == self.height
+    }
+
+    /// Decode from `input` until the next row is complete, see `ContainerDecoder::next_row`
+    pub fn next_row(&mut self, input: &mut &[u8]) -> Result<Option<Row<'_>>, &'static str> {
+        if !self.advance(input)? {
+            return Ok(None);
+        }
+        Ok(Some(Row {
+            y: self.rows - 1,
+            indices: &self.indices[..usize::from(self.width)],
+            palette: &self.palette,
+        }))
+    }
+
+    /// Packed pixel bytes per row, without the filter byte
+    fn row_bytes(&self) -> usize {
+        (usize::from(self.width) * usize::from(self.bits)).div_ceil(8)
+    }
+
+    /// Consume `input` up to the end of the next row. Returns true if a row is ready.
+    fn advance(&mut self, input: &mut &[u8]) -> Result<bool, &'static str> {
+        loop {
+            // Rows from data inflated earlier come first
+            if self.take_row()? {
+                return Ok(true);
+            }
+            if self.more_output {
+                self.inflate(&[])?;
+                continue;
+            }
+            if input.is_empty() {
+                return Ok(false);
+            }
+
+            match self.state {
+                State::Signature => {
+                    if self.collect(input, SIGNATURE.len()) {
+                        if self.field[..SIGNATURE.len()] != SIGNATURE {
+                            return Err("Not a PNG file");
+                        }
+                        self.state = State::ChunkHeader;
+                    }
+                }
+                State::ChunkHeader => {
+                    if self.collect(input, 8) {
+                        let [l0, l1, l2, l3, t0, t1, t2, t3, ..] = self.field;
+                        self.chunk_len = u32::from_be_bytes([l0, l1, l2, l3]);
+                        self.chunk_type = [t0, t1, t2, t3];
+                        self.crc = CHUNK_CRC.digest();
+                        self.crc.update(&self.chunk_type);
+                        self.start_chunk()?;
+                        self.state = State::ChunkData(self.chunk_len);
+                    }
+                }
+                State::ChunkData(0) => self.state = State::ChunkCrc,
+                State::ChunkData(remaining) => {
+                    let n = (remaining as usize).min(input.len());
+                    let data = &input[..n];
+                    let consumed = self.chunk_data(data, self.chunk_len - remaining)?;
+                    self.crc.update(&data[..consumed]);
+                    *input = &input[consumed..];
+                    self.state = State::ChunkData(remaining - consumed as u32);
+                }
+                State::ChunkCrc => {
+                    if self.collect(input, 4) {
+                        let [c0, c1, c2, c3, ..] = self.field;
+                        let digest = core::mem::replace(&mut self.crc, CHUNK_CRC.digest());
+                        if digest.finalize() != u32::from_be_bytes([c0, c1, c2, c3]) {
+                            error!("PNG chunk {=[u8]:a} has a bad CRC", self.chunk_type);
+                            return Err("PNG checksum mismatch");
+                        }
+                        self.end_chunk()?;
+                    }
+                }
+                // Trailing bytes after `IEND` are ignored
+                State::Done => *input = &[],
+            }
+        }
+    }
+
+    /// Collect bytes into `field` until it holds `len`. Returns true once complete.
+    fn collect(&mut self, input: &mut &[u8], len: usize) -> bool {
+        let n = (len - self.field_len).min(input.len());
+        self.field[self.field_len..self.field_len + n].copy_from_slice(&input[..n]);
+        *input = &input[n..];
+        self.field_len += n;
+        if self.field_len < len {
+            return false;
+        }
+        self.field_len = 0;
+        true
+    }
+
+    /// Check the order and size of a chunk whose header just arrived
+    fn start_chunk(&mut self) -> Result<(), &'static str> {
+        let valid = match &self.chunk_type {
+            b"IHDR" => !self.seen_header && self.chunk_len == IHDR_BYTES as u32,
+            // Grayscale images have no palette, the gray levels are the palette
+            b"PLTE" => {
+                self.seen_header
+                    && self.color_type == COLOR_PALETTE
+                    && self.chunk_len.is_multiple_of(3)
+                    && self.chunk_len <= 3 * 256
+            }
+            _ => self.seen_header,
+        };
+        if !valid {
+            error!(
+                "Unexpected PNG chunk {=[u8]:a} of {} bytes",
+                self.chunk_type, self.chunk_len
+            );
+            return Err("Invalid PNG structure");
+        }
+        Ok(())
+    }
+
+    /// Handle `data` found at `offset` of the current chunk's data.
+    /// Returns how many bytes were consumed (less than `data` when a row completed).
+    fn chunk_data(&mut self, data: &[u8], offset: u32) -> Result<usize, &'static str> {
+        match &self.chunk_type {
+            b"IHDR" => {
+                let start = offset as usize;
+                self.header[start..start + data.len()].copy_from_slice(data);
+            }
+            b"PLTE" => {
+                for (i, &byte) in data.iter().enumerate() {
+                    let at = offset as usize + i;
+                    self.palette[at / 3][at % 3] = byte;
+                }
+            }
+            b"IDAT" => {
+                if self.color_type == COLOR_PALETTE && !self.seen_palette {
+                    return Err("PNG palette missing");
+                }
+                return self.inflate(data);
+            }
+            _ => {}
+        }
+        Ok(data.len())
+    }
+
+    /// A chunk ended with a valid CRC
+    fn end_chunk(&mut self) -> Result<(), &'static str> {
+        self.state = State::ChunkHeader;
+        match &self.chunk_type {
+            b"IHDR" => self.parse_header()?,
+            b"PLTE" => self.seen_palette = true,
+            b"IEND" => {
+                if !self.is_complete() {
+                    error!("PNG ended after {} of {} rows", self.rows, self.height);
+                    return Err("Truncated PNG image");
+                }
+                self.state = State::Done;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Check `IHDR` against what the decoder supports
+    fn parse_header(&mut self) -> Result<(), &'static str> {
+        let f = &self.header;
+        let width = u32::from_be_bytes([f[0], f[1], f[2], f[3]]);
+        let height = u32::from_be_bytes([f[4], f[5], f[6], f[7]]);
+        let [bits, color_type, compression, filter, interlace] = [f[8], f[9], f[10], f[11], f[12]];
+
+        if !matches!(color_type, COLOR_GRAY | COLOR_PALETTE) || !matches!(bits, 1 | 2 | 4 | 8) {
+            error!(
+                "PNG color type {} with {} bits, expected palette or gray",
+                color_type, bits
+            );
+            return Err("Unsupported PNG pixel format");
+        }
+        if compression != 0 || filter != 0 {
+            error!(
+                "PNG compression method {} and filter method {}, expected 0 and 0",
+                compression, filter
+            );
+            return Err("Unknown PNG compression or filter method");
+        }
+        if interlace != 0 {
+            error!("PNG interlace method {} not supported", interlace);
+            return Err("Interlaced PNG not supported");
+        }
+        if width != u32::from(self.width) || height != u32::from(self.height) {
+            error!(
+                "PNG is {}x{} pixels, expected {}x{}",
+                width, height, self.width, self.height
+            );
+            return Err("Image size does not match the display");
+        }
+
+        self.seen_header = true;
+        self.color_type = color_type;
+        self.bits = bits;
+        if color_type == COLOR_GRAY {
+            let max = (1u16 << bits) - 1;
+            for (level, entry) in self
+                .palette
+                .iter_mut()
+                .enumerate()
+                .take(usize::from(max) + 1)
+            {
+                *entry = [(level as u16 * 255 / max) as u8; 3];
+            }
+        }
+        Ok(())
+    }
+
+    /// Inflate compressed bytes into the window (with `data` empty: flush output
+    /// left over from the last call). Returns how many bytes of `data` were consumed.
+    fn inflate(&mut self, data: &[u8]) -> Result<usize, &'static str> {
+        if self.inflate_done {
+            // Padding after the end of the zlib stream
+            return Ok(data.len());
+        }
+        if self.write == WINDOW_SIZE {
+            self.read = 0;
+            self.write = 0;
+        }
+        let flags = TINFL_FLAG_PARSE_ZLIB_HEADER | TINFL_FLAG_HAS_MORE_INPUT;
+        let (status, consumed, produced) = decompress(
+            &mut self.inflater,
+            data,
+            &mut self.window,
+            self.write,
+            flags,
+        );
+        self.write += produced;
+        self.more_output = status == TINFLStatus::HasMoreOutput;
+        match status {
+            TINFLStatus::Done => self.inflate_done = true,
+            TINFLStatus::NeedsMoreInput | TINFLStatus::HasMoreOutput => {}
+            _ => {
+                error!("PNG inflate failed: {}", status as i8);
+                return Err("Corrupt PNG data");
+            }
+        }
+        Ok(consumed)
+    }
+
+    /// Move inflated bytes into the current row. Returns true once a row is complete.
+    fn take_row(&mut self) -> Result<bool, &'static str> {
+        let row_bytes = self.row_bytes();
+        while self.read < self.write {
+            if self.is_complete() {
+                // Extra data after the last row is ignored
+                self.read = self.write;
+                break;
+            }
+            let Some(filter) = self.filter else {
+                self.filter = Some(self.window[self.read]);
+                self.read += 1;
+                continue;
+            };
+            let n = (row_bytes - self.row_len).min(self.write - self.read);
+            self.row[self.row_len..self.row_len + n]
+                .copy_from_slice(&self.window[self.read..self.read + n]);
+            self.read += n;
+            self.row_len += n;
+            if self.row_len == row_bytes {
+                self.unfilter(filter)?;
+                let width = usize::from(self.width);
+                unpack_indices(&self.row, self.bits, &mut self.indices[..width]);
+                self.previous[..row_bytes].copy_from_slice(&self.row[..row_bytes]);
+                self.filter = None;
+                self.row_len = 0;
+                self.rows += 1;
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Undo the row filter; pixels are at most one byte, so "left" is the previous byte
+    fn unfilter(&mut self, filter: u8) -> Result<(), &'static str> {
+        let row_bytes = self.row_bytes();
+        let (row, up) = (&mut self.row[..row_bytes], &self.previous[..row_bytes]);
+        match filter {
+            0 => {}
+            1 => {
+                for x in 1..row_bytes {
+                    row[x] = row[x].wrapping_add(row[x - 1]);
+                }
+            }
+            2 => {
+                for (byte, &up) in row.iter_mut().zip(up) {
+                    *byte = byte.wrapping_add(up);
+                }
+            }
+            3 => {
+                for x in 0..row_bytes {
+                    let left = if x > 0 { u16::from(row[x - 1]) } else { 0 };
+                    row[x] = row[x].wrapping_add(((left + u16::from(up[x])) / 2) as u8);
+                }
+            }
+            4 => {
+                for x in 0..row_bytes {
+                    let (left, up_left) = if x > 0 {
+                        (row[x - 1], up[x - 1])
+                    } else {
+                        (0, 0)
+                    };
+                    row[x] = row[x].wrapping_add(paeth(left, up[x], up_left));
+                }
+            }
+            _ => {
+                error!("Invalid PNG filter type {}", filter);
+                return Err("Corrupt PNG data");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Paeth predictor: whichever neighbour is closest to `left + up - up_left`
+fn paeth(left: u8, up: u8, up_left: u8) -> u8 {
+    let estimate = i16::from(left) + i16::from(up) - i16::from(up_left);
+    let distance = |value: u8| (estimate - i16::from(value)).abs();
+    if distance(left) <= distance(up) && distance(left) <= distance(up_left) {
+        left
+    } else if distance(up) <= distance(up_left) {
+        up
+    } else {
+        up_left
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec;
+    use std::vec::Vec;
+
+    use super::*;
+
+    /// One chunk with its length and CRC
+    fn chunk(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut crc = CHUNK_CRC.digest();
+        crc.update(kind);
+        crc.update(data);
+        let mut bytes = (data.len() as u32).to_be_bytes().to_vec();
+        bytes.extend_from_slice(kind);
+        bytes.extend_from_slice(data);
+        bytes.extend_from_slice(&crc.finalize().to_be_bytes());
+        bytes
+    }
+
+    /// zlib stream holding `data` in a single stored (uncompressed) block
+    fn zlib_stored(data: &[u8]) -> Vec<u8> {
+        let len = data.len() as u16;
+        let mut stream = vec![0x78, 0x01, 0x01];
+        stream.extend_from_slice(&len.to_le_bytes());
+        stream.extend_from_slice(&(!len).to_le_bytes());
+        stream.extend_from_slice(data);
+        let (mut a, mut b) = (1u32, 0u32);
+        for &byte in data {
+            a = (a + u32::from(byte)) % 65521;
+            b = (b + a) % 65521;
+        }
+        stream.extend_from_slice(&((b << 16) | a).to_be_bytes());
+        stream
+    }
+
+    fn ihdr(width: u32, height: u32, bits: u8, color_type: u8) -> Vec<u8> {
+        ihdr_methods(width, height, bits, color_type, [0, 0, 0])
+    }
+
+    /// `IHDR` with the given compression, filter and interlace methods
+    fn ihdr_methods(
+        width: u32,
+        height: u32,
+        bits: u8,
+        color_type: u8,
+        methods: [u8; 3],
+    ) -> Vec<u8> {
+        let mut data = width.to_be_bytes().to_vec();
+        data.extend_from_slice(&height.to_be_bytes());
+        data.extend_from_slice(&[bits, color_type]);
+        data.extend_from_slice(&methods);
+        chunk(b"IHDR", &data)
+    }
+
+    /// PNG file from a header, an optional palette and filtered rows split over `idats` chunks
+    fn png(header: Vec<u8>, palette: &[[u8; 3]], rows: &[u8], idats: usize) -> Vec<u8> {
+        let mut file = SIGNATURE.to_vec();
+        file.extend(header);
+        if !palette.is_empty() {
+            file.extend(chunk(b"PLTE", palette.as_flattened()));
+        }
+        let stream = zlib_stored(rows);
+        for part in stream.chunks(stream.len().div_ceil(idats)) {
+            file.extend(chunk(b"IDAT", part));
+        }
+        file.extend(chunk(b"IEND", &[]));
+        file
+    }
+
+    /// Rows (y, indices, RGB of each pixel) decoded from `file` fed in `chunk` sized pieces
+    #[allow(clippy::type_complexity)]
+    fn decode(
+        decoder: &mut PngDecoder,
+        file: &[u8],
+        chunk: usize,
+    ) -> Result<Vec<(u16, Vec<u8>, Vec<[u8; 3]>)>, &'static str> {
+        let mut rows = Vec::new();
+        for mut piece in file.chunks(chunk) {
+            while let Some(row) = decoder.next_row(&mut piece)? {
+                let colors = row
+                    .indices
+                    .iter()
+                    .map(|&i| row.palette[usize::from(i)])
+                    .collect();
+                rows.push((row.y, row.indices.to_vec(), colors));
+            }
+            assert!(piece.is_empty());
+        }
+        Ok(rows)
+    }
+
+    const RED: [u8; 3] = [255, 0, 0];
+    const GREEN: [u8; 3] = [0, 255, 0];
+    const BLUE: [u8; 3] = [0, 0, 255];
+
+    #[test]
+    fn decodes_filtered_palette_rows() {
+        // Rows 0 1 2 / 2 1 0 / 1 1 1 with filters none, sub and up
+        let rows = [0, 0, 1, 2, 1, 2, 255, 255, 2, 255, 0, 1];
+        let file = png(ihdr(3, 3, 8, COLOR_PALETTE), &[RED, GREEN, BLUE], &rows, 2);
+        for chunk in 1..=file.len() {
+            let mut decoder = PngDecoder::new(3, 3);
+            let rows = decode(&mut decoder, &file, chunk).unwrap();
+            assert_eq!(
+                rows,
+                [
+                    (0, vec![0, 1, 2], vec![RED, GREEN, BLUE]),
+                    (1, vec![2, 1, 0], vec![BLUE, GREEN, RED]),
+                    (2, vec![1, 1, 1], vec![GREEN; 3]),
+                ],
+                "chunk {chunk}"
+            );
+            assert!(decoder.is_complete());
+        }
+    }
+
+    #[test]
+    fn gray_levels_span_black_to_white() {
+        let file = png(ihdr(4, 1, 2, COLOR_GRAY), &[], &[0, 0b00_01_10_11], 1);
+        let mut decoder = PngDecoder::new(4, 1);
+        let rows = decode(&mut decoder, &file, 64).unwrap();
+        assert_eq!(
+            rows,
+            [(
+                0,
+                vec![0, 1, 2, 3],
+                vec![[0; 3], [85; 3], [170; 3], [255; 3]]
+            )]
+        );
+    }
+
+    #[test]
+    fn index_outside_the_palette_is_black() {
+        let file = png(ihdr(2, 1, 8, COLOR_PALETTE), &[RED, GREEN], &[0, 1, 200], 1);
+        let mut decoder = PngDecoder::new(2, 1);
+        let rows = decode(&mut decoder, &file, 64).unwrap();
+        assert_eq!(rows, [(0, vec![1, 200], vec![GREEN, [0; 3]])]);
+    }
+
+    #[test]
+    fn rejects_a_missing_or_oversized_palette() {
+        let file = png(ihdr(2, 1, 8, COLOR_PALETTE), &[], &[0, 0, 0], 1);
+        let mut decoder = PngDecoder::new(2, 1);
+        assert_eq!(decode(&mut decoder, &file, 64), Err("PNG palette missing"));
+
+        let file = png(ihdr(2, 1, 8, COLOR_PALETTE), &[RED; 257], &[0, 0, 0], 1);
+        let mut decoder = PngDecoder::new(2, 1);
+        assert_eq!(
+            decode(&mut decoder, &file, 64),
+            Err("Invalid PNG structure")
+        );
+    }
+
+    #[test]
+    fn rejects_a_palette_in_a_gray_image() {
+        let file = png(ihdr(2, 1, 8, COLOR_GRAY), &[RED, GREEN], &[0, 0, 1], 1);
+        let mut decoder = PngDecoder::new(2, 1);
+        assert_eq!(
+            decode(&mut decoder, &file, 64),
+            Err("Invalid PNG structure")
+        );
+    }
+
+    #[test]
+    fn reports_the_unsupported_method() {
+        for (methods, error) in [
+            ([1, 0, 0], "Unknown PNG compression or filter method"),
+            ([0, 1, 0], "Unknown PNG compression or filter method"),
+            ([0, 0, 1], "Interlaced PNG not supported"),
+        ] {
+            let file = png(ihdr_methods(3, 1, 8, COLOR_GRAY, methods), &[], &[], 1);
+            let mut decoder = PngDecoder::new(3, 1);
+            assert_eq!(decode(&mut decoder, &file, 64), Err(error), "{methods:?}");
+        }
+    }
+
+    #[test]
+    fn rejects_other_sizes() {
+        let size_mismatch = Err("Image size does not match the display");
+        for (width, height) in [(4, 2), (3, 3), (u32::MAX, 2), (3, 65538)] {
+            let file = png(ihdr(width, height, 8, COLOR_GRAY), &[], &[], 1);
+            let mut decoder = PngDecoder::new(3, 2);
+            assert_eq!(
+                decode(&mut decoder, &file, 64),
+                size_mismatch,
+                "{width}x{height}"
+            );
+        }
+
+        // Rows wider than the decoder can hold are refused even when expected
+        let width = MAX_ROW_PIXELS as u16 + 4;
+        let file = png(ihdr(u32::from(width), 1, 8, COLOR_GRAY), &[], &[], 1);
+        let mut decoder = PngDecoder::new(width, 1);
+        assert_eq!(decode(&mut decoder, &file, 64), size_mismatch);
+    }
+
+    #[test]
+    fn truncated_idat_is_incomplete() {
+        let file = png(ihdr(3, 2, 8, COLOR_GRAY), &[], &[0, 1, 2, 3, 0, 4, 5, 6], 1);
+        let idat_end = file.len() - chunk(b"IEND", &[]).len() - 4;
+        // Cut inside the second row
+        let mut decoder = PngDecoder::new(3, 2);
+        let rows = decode(&mut decoder, &file[..idat_end - 6], 64).unwrap();
+        assert_eq!(rows.len(), 1);
+        assert!(!decoder.is_complete());
+    }
+
+    #[test]
+    fn rejects_iend_before_the_last_row() {
+        let file = png(ihdr(3, 2, 8, COLOR_GRAY), &[], &[0, 1, 2, 3], 1);
+        let mut decoder = PngDecoder::new(3, 2);
+        assert_eq!(decode(&mut decoder, &file, 64), Err("Truncated PNG image"));
+    }
+
+    #[test]
+    fn rejects_a_bad_crc() {
+        let mut file = png(ihdr(3, 1, 8, COLOR_GRAY), &[], &[0, 1, 2, 3], 1);
+        let ihdr_crc = SIGNATURE.len() + 8 + IHDR_BYTES;
+        file[ihdr_crc] ^= 1;
+        let mut decoder = PngDecoder::new(3, 1);
+        assert_eq!(
+            decode(&mut decoder, &file, 64),
+            Err("PNG checksum mismatch")
+        );
+    }
+}
@@ -0,0 +1,265 @@
+//! Status bar layout
+//! Which widgets the status bar shows and where, from the `[display]` config or
+//! the `X-Status-Bar` header. The firmware draws them over each image.
+
+use embedded_graphics::mono_font::{MonoFont, iso_8859_5::FONT_6X10};
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+
+use crate::FIRMWARE_VERSION;
+use crate::epd::Window;
+
+/// Most widgets a layout can hold
+pub const MAX_WIDGETS: usize = 8;
+
+/// Largest text scale of a widget
+pub const MAX_WIDGET_SCALE: u8 = 8;
+
+/// Font used for all overlay text; characters it lacks are drawn as `?`
+pub const FONT: &MonoFont<'static> = &FONT_6X10;
+
+/// What a status bar widget shows
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Widget {
+    /// Battery level, "87%"
+    Battery,
+    /// Chip temperature, "21°C"
+    Temperature,
+    /// WiFi signal strength of the last connection, "-67dBm"
+    Rssi,
+    /// Server time of the last successful download, "12:34"
+    Updated,
+    /// Time since the last successful download, "5m ago"
+    Age,
+    /// Firmware version, "v0.1.0"
+    Version,
+    /// Time until the next scheduled download, "next 30m"
+    Countdown,
+}
+
+impl Widget {
+    /// Widget for its config/header name
+    pub fn from_name(name: &str) -> Option<Self> {
+        let widget = match name {
+            "battery" => Self::Battery,
+            "temperature" => Self::Temperature,
+            "rssi" => Self::Rssi,
+            "updated" => Self::Updated,
+            "age" => Self::Age,
+            "version" => Self::Version,
+            "countdown" => Self::Countdown,
+            _ => return None,
+        };
+        Some(widget)
+    }
+
+    /// Characters of the longest text the widget draws
+    fn max_chars(self) -> usize {
+        match self {
+            Self::Battery => "100%".len(),
+            // "-127", degree mark and "C"
+            Self::Temperature => 6,
+            Self::Rssi => "-128dBm".len(),
+            Self::Updated => "23:59".len(),
+            Self::Age => "999d ago".len(),
+            Self::Version => 1 + FIRMWARE_VERSION.len(),
+            Self::Countdown => "next 99m".len(),
+        }
+    }
+}
+
+/// One widget and where it goes, in image coordinates
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct WidgetSlot {
+    pub widget: Widget,
+    pub x: u16,
+    pub y: u16,
+    /// Text scale (1 = 6x10 pixel characters)
+    pub scale: u8,
+}
+
+impl WidgetSlot {
+    pub const fn new(widget: Widget, x: u16, y: u16, scale: u8) -> Self {
+        Self {
+            widget,
+            x,
+            y,
+            scale,
+        }
+    }
+
+    /// Area cleared for the widget: its longest text at `scale`
+    pub fn area(&self) -> Rectangle {
+        let scale = u32::from(self.scale);
+        let width = self.widget.max_chars() as u32 * FONT.character_size.width * scale;
+        Rectangle::new(
+            Point::new(i32::from(self.x), i32::from(self.y)),
+            Size::new(width, FONT.character_size.height * scale),
+        )
+    }
+}
+
+/// Widgets of the status bar, drawn in order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct StatusLayout {
+    slots: [WidgetSlot; MAX_WIDGETS],
+    len: u8,
+}
+
+impl StatusLayout {
+    /// No status bar
+    pub const EMPTY: Self = Self {
+        slots: [WidgetSlot::new(Widget::Battery, 0, 0, 1); MAX_WIDGETS],
+        len: 0,
+    };
+
+    /// Layout from a list of at most `MAX_WIDGETS` slots
+    pub const fn new(slots: &[WidgetSlot]) -> Self {
+        assert!(slots.len() <= MAX_WIDGETS, "too many status bar widgets");
+        let mut layout = Self::EMPTY;
+        let mut i = 0;
+        while i < slots.len() {
+            layout.slots[i] = slots[i];
+            i += 1;
+        }
+        layout.len = slots.len() as u8;
+        layout
+    }
+
+    pub fn slots(&self) -> &[WidgetSlot] {
+        &self.slots[..usize::from(self.len)]
+    }
+
+    /// Parse an `X-Status-Bar` value: `none`, or `;`-separated `name x y [scale]`
+    /// entries such as `battery 0 0 2; rssi 0 24`
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut layout = Self::EMPTY;
+        if value.trim().eq_ignore_ascii_case("none") {
+            return Some(layout);
+        }
+        for entry in value.split(';') {
+            let mut fields = entry.split_whitespace();
+            let widget = Widget::from_name(fields.next()?)?;
+            let x = fields.next()?.parse().ok()?;
+            let y = fields.next()?.parse().ok()?;
+            let scale = match fields.next() {
+                Some(scale) => scale.parse().ok()?,
+                None => 1,
+            };
+            if fields.next().is_some() || !(1..=MAX_WIDGET_SCALE).contains(&scale) {
+                return None;
+            }
+            if usize::from(layout.len) == MAX_WIDGETS {
+                return None;
+            }
+            layout.slots[usize::from(layout.len)] = WidgetSlot::new(widget, x, y, scale);
+            layout.len += 1;
+        }
+        Some(layout)
+    }
+
+    /// Smallest window covering every widget on a `width` x `height` image,
+    /// `None` if no widget is visible
+    pub fn window(&self, width: u16, height: u16) -> Option<Window> {
+        let image = Rectangle::new(Point::zero(), Size::new(width.into(), height.into()));
+        let mut covered: Option<Rectangle> = None;
+        for slot in self.slots() {
+            let area = slot.area().intersection(&image);
+            if area.is_zero_sized() {
+                continue;
+            }
+            covered = Some(match covered {
+                Some(covered) => envelope(covered, area),
+                None => area,
+            });
+        }
+        let covered = covered?;
+        Some(Window::new(
+            covered.top_left.x as u16,
+            covered.top_left.y as u16,
+            covered.size.width as u16,
+            covered.size.height as u16,
+        ))
+    }
+}
+
+/// Smallest rectangle containing `a` and `b` (both non-empty)
+pub fn envelope(a: Rectangle, b: Rectangle) -> Rectangle {
+    let top_left = a.top_left.component_min(b.top_left);
+    let bottom_right = (a.top_left + a.size).component_max(b.top_left + b.size);
+    Rectangle::with_corners(top_left, bottom_right - Point::new(1, 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::format;
+    use std::vec::Vec;
+
+    use super::*;
+
+    #[test]
+    fn parse_places_the_widgets_in_order() {
+        let layout = StatusLayout::parse("battery 0 0 2; rssi 0 24").unwrap();
+
+        assert_eq!(
+            layout.slots(),
+            [
+                WidgetSlot::new(Widget::Battery, 0, 0, 2),
+                WidgetSlot::new(Widget::Rssi, 0, 24, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_none_is_an_empty_layout() {
+        assert_eq!(StatusLayout::parse(" None "), Some(StatusLayout::EMPTY));
+    }
+
+    #[test]
+    fn parse_rejects_malformed_entries() {
+        for value in [
+            "clock 0 0",
+            "battery 0",
+            "battery -1 0",
+            "battery 0 0 0",
+            "battery 0 0 9",
+            "battery 0 0 2 1",
+            "battery 0 0; ; age 0 10",
+        ] {
+            assert_eq!(StatusLayout::parse(value), None, "{value}");
+        }
+    }
+
+    #[test]
+    fn parse_rejects_more_than_max_widgets() {
+        let entry = |i: usize| format!("age 0 {}", i * 10);
+        let full: Vec<_> = (0..MAX_WIDGETS).map(entry).collect();
+        assert!(StatusLayout::parse(&full.join(";")).is_some());
+
+        let over: Vec<_> = (0..=MAX_WIDGETS).map(entry).collect();
+        assert_eq!(StatusLayout::parse(&over.join(";")), None);
+    }
+
+    #[test]
+    fn window_covers_every_visible_widget() {
+        // "100%" at scale 2 is 48x20, "-128dBm" at scale 1 is 42x10
+        let layout = StatusLayout::new(&[
+            WidgetSlot::new(Widget::Battery, 0, 0, 2),
+            WidgetSlot::new(Widget::Rssi, 100, 50, 1),
+        ]);
+
+        assert_eq!(layout.window(600, 448), Some(Window::new(0, 0, 142, 60)));
+    }
+
+    #[test]
+    fn window_is_clipped_to_the_image() {
+        let layout = StatusLayout::new(&[
+            WidgetSlot::new(Widget::Battery, 590, 440, 1),
+            WidgetSlot::new(Widget::Age, 700, 0, 1),
+        ]);
+
+        assert_eq!(layout.window(600, 448), Some(Window::new(590, 440, 10, 8)));
+        assert_eq!(layout.window(400, 300), None);
+        assert_eq!(StatusLayout::EMPTY.window(600, 448), None);
+    }
+}
@@ -0,0 +1,32 @@
+//! # E-Paper Weather Display, hardware-independent parts
+//! Panel drivers (generic over embedded-hal 1.0), image decoders, the image
+//! response headers, the status bar layout and the retry backoff. The firmware
+//! in `main.rs` wires them to the RP2040; the unit tests run on the host with
+//! `cargo test-host`.
+
+#![cfg_attr(not(test), no_std)]
+
+pub mod epd;
+pub mod headers;
+pub mod image;
+pub mod layout;
+pub mod retry;
+
+/// Firmware version - automatically populated from Cargo.toml
+pub static FIRMWARE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// defmt needs a logger to link the host test binary; log output is dropped
+#[cfg(test)]
+mod test_logger {
+    #[defmt::global_logger]
+    struct NullLogger;
+
+    unsafe impl defmt::Logger for NullLogger {
+        fn acquire() {}
+        unsafe fn flush() {}
+        unsafe fn release() {}
+        unsafe fn write(_bytes: &[u8]) {}
+    }
+
+    defmt::timestamp!("{=u32}", 0);
+}
@@ -7,34 +7,49 @@
 use core::panic::PanicInfo;
 use cortex_m::peripheral::SCB;
 use defmt::info;
+use defmt_rtt as _;
 use embassy_executor::Spawner;
 use embassy_rp::adc::{Adc, Config as AdcConfig, InterruptHandler as AdcInterruptHandler};
 use embassy_rp::bind_interrupts;
 use embassy_rp::clocks::{ClockConfig, CoreVoltage};
 use embassy_rp::config::Config;
-use defmt_rtt as _;
+use embassy_rp::dma::InterruptHandler as DmaInterruptHandler;
+use embassy_rp::peripherals::{DMA_CH0, DMA_CH1, FLASH, PIO0};
+use embassy_rp::pio::InterruptHandler as PioInterruptHandler;
 
 mod config;
-mod epd_5in65f;
+mod draw;
 mod event;
 mod network;
 mod state;
+mod store;
+#[cfg(feature = "streaming")]
+mod stream;
 mod task;
+mod tls;
 
+use config::EpdPins;
+#[cfg(not(feature = "streaming"))]
 use network::IMAGE_BUFFER_SIZE;
+use pico_epaper::{FIRMWARE_VERSION, epd, headers, image, layout, retry};
+#[cfg(not(feature = "streaming"))]
+use static_cell::ConstStaticCell;
 use task::{
-    WifiPeripherals, battery_monitor, button_handler, display_handler, network_manager,
-    orchestrator, scheduler, wait_battery_ready,
+    ImageTarget, WifiPeripherals, battery_monitor, button_handler, display_handler,
+    network_manager, orchestrator, scheduler, wait_battery_ready,
 };
 
-/// Firmware version - automatically populated from Cargo.toml
-pub static FIRMWARE_VERSION: &str = env!("CARGO_PKG_VERSION");
-
-// Static buffer for image data
-static mut IMAGE_BUFFER: [u8; IMAGE_BUFFER_SIZE] = [0u8; IMAGE_BUFFER_SIZE];
+// Staging buffer for downloads (not needed when streaming straight to the panel)
+#[cfg(not(feature = "streaming"))]
+static STAGING_BUFFER: ConstStaticCell<[u8; IMAGE_BUFFER_SIZE]> =
+    ConstStaticCell::new([0u8; IMAGE_BUFFER_SIZE]);
 
+// DMA_IRQ_0 is shared by the CYW43 (CH0) and e-Paper SPI (CH1) channels,
+// so all bindings live here rather than next to each driver.
 bind_interrupts!(struct Irqs {
     ADC_IRQ_FIFO => AdcInterruptHandler;
+    PIO0_IRQ_0 => PioInterruptHandler<PIO0>;
+    DMA_IRQ_0 => DmaInterruptHandler<DMA_CH0>, DmaInterruptHandler<DMA_CH1>;
 });
 
 #[panic_handler]
@@ -53,6 +68,31 @@ fn spawn_unwrap<S>(
     spawner.spawn(token.unwrap());
 }
 
+/// Spawn the display task and return where the network task should put images.
+/// The network task owns the RAM staging buffer and the flash image store;
+/// the display task reads the promoted image from flash (see `store`).
+#[cfg(not(feature = "streaming"))]
+fn spawn_display(
+    spawner: &Spawner,
+    epd_pins: EpdPins,
+    flash: embassy_rp::Peri<'static, FLASH>,
+) -> ImageTarget {
+    spawn_unwrap(spawner, display_handler(epd_pins));
+    network::FrameSink::new(STAGING_BUFFER.take(), store::ImageStore::new(flash))
+}
+
+/// Spawn the display task and return where the network task should put images.
+/// Body chunks are handed to the display task directly, nothing is stored.
+#[cfg(feature = "streaming")]
+fn spawn_display(
+    spawner: &Spawner,
+    epd_pins: EpdPins,
+    _flash: embassy_rp::Peri<'static, FLASH>,
+) -> ImageTarget {
+    spawn_unwrap(spawner, display_handler(epd_pins));
+    stream::PanelStream::new()
+}
+
 #[embassy_executor::main]
 async fn main(spawner: Spawner) {
     info!("Starting e-Paper Weather Display v{}", FIRMWARE_VERSION);
@@ -66,13 +106,13 @@ async fn main(spawner: Spawner) {
     let config = Config::new(clock_config);
     let p = embassy_rp::init(config);
 
-    // Setup ADC for battery voltage measurement
+    // Setup ADC for battery voltage and chip temperature measurement
     let adc = Adc::new(p.ADC, Irqs, AdcConfig::default());
 
     // Spawn battery monitor task
     // Note: Uses GPIO28 (ADC2) with voltage divider (220Ω + 100Ω)
     // This does not conflict with WiFi pins
-    spawn_unwrap(&spawner, battery_monitor(adc));
+    spawn_unwrap(&spawner, battery_monitor(adc, p.ADC_TEMP_SENSOR));
 
     // Wait for first battery measurement to complete
     info!("Waiting for initial battery measurement...");
@@ -81,26 +121,19 @@ async fn main(spawner: Spawner) {
 
     // Initialize GPIO pins for e-paper display and buttons
     let (epd_pins, keys) = config::init_all(
-        p.PIN_12, p.PIN_8, p.PIN_9, p.PIN_13, p.PIN_10, p.PIN_11, p.PIN_15, p.PIN_17, p.PIN_2,
+        p.PIN_12, p.PIN_8, p.PIN_9, p.PIN_13, p.PIN_10, p.PIN_11, p.SPI1, p.DMA_CH1, p.PIN_15,
+        p.PIN_17, p.PIN_2,
     );
 
     // Spawn button handler task
     spawn_unwrap(&spawner, button_handler(keys));
 
-    // Get static reference to image buffer
-    // SAFETY: We're in single-threaded executor, buffer is only accessed by display and network tasks
-    let image_buffer: &'static mut [u8; IMAGE_BUFFER_SIZE] =
-        unsafe { &mut *core::ptr::addr_of_mut!(IMAGE_BUFFER) };
-
-    // Split image buffer reference for display and network tasks
-    // SAFETY: Display task only reads after network task writes, coordinated via events
-    let display_buffer: &'static mut [u8; IMAGE_BUFFER_SIZE] =
-        unsafe { &mut *(image_buffer as *mut _) };
-    let network_buffer: &'static mut [u8; IMAGE_BUFFER_SIZE] =
-        unsafe { &mut *(image_buffer as *mut _) };
+    // Show the image of the previous boot until the first download replaces it
+    #[cfg(not(feature = "streaming"))]
+    store::restore_stored_image().await;
 
     // Spawn display handler task
-    spawn_unwrap(&spawner, display_handler(epd_pins, display_buffer));
+    let image_target = spawn_display(&spawner, epd_pins, p.FLASH);
 
     // Setup WiFi peripherals
     let wifi_peripherals = WifiPeripherals {
@@ -115,7 +148,7 @@ async fn main(spawner: Spawner) {
     // Spawn network manager task
     spawn_unwrap(
         &spawner,
-        network_manager(spawner, wifi_peripherals, network_buffer),
+        network_manager(spawner, wifi_peripherals, image_target),
     );
 
     // Spawn orchestrator tasks
@@ -5,90 +5,855 @@
 
 include!(concat!(env!("OUT_DIR"), "/config_generated.rs"));
 
+use core::fmt::Write as _;
+
+use crc::{CRC_32_ISCSI, CRC_32_ISO_HDLC, Crc, Digest};
 use defmt::*;
 use embassy_net::Stack;
-use embassy_net::dns::DnsSocket;
-use embassy_net::tcp::client::{TcpClient, TcpClientState};
-use reqwless::client::HttpClient;
-use reqwless::request::Method;
-
-/// Image buffer size: 600x448 pixels, 4 bits per pixel = 134_400 bytes
-pub const IMAGE_BUFFER_SIZE: usize = 134_400;
-
-/// Download raw 4bpp image from HTTP server using reqwless
-/// Returns tuple: (image_data, next_delay_seconds)
-/// Buffer must be provided by caller (allocated in heap in main)
-pub async fn download_image<'a>(
-    stack: &Stack<'_>,
-    image_buffer: &'a mut [u8],
-) -> Result<(&'a mut [u8], Option<u64>), &'static str> {
-    if image_buffer.len() < IMAGE_BUFFER_SIZE {
-        return Err("Buffer too small");
+use embassy_net::dns::DnsQueryType;
+use embassy_net::tcp::TcpSocket;
+use embedded_io_async::{Error as _, ErrorKind, ErrorType, Read, Write};
+use embedded_tls::{Aes128GcmSha256, TlsConfig, TlsConnection, TlsContext};
+use reqwless::client::HttpConnection;
+use reqwless::request::{Request, RequestBuilder};
+
+use crate::config::{EPD_ORIENTATION, STATUS_LAYOUT};
+use crate::epd::{self, Orientation, PANEL_FRAME_BYTES};
+use crate::headers::{
+    ContentEncoding, HeaderDefaults, HeaderError, ImageHeaders, ImageOrientation, ImageValidators,
+    parse_content_range,
+};
+use crate::image::dither::{Ditherer, PixelFormat};
+use crate::image::packbits::PackBitsDecoder;
+use crate::image::{Container, ContainerDecoder};
+use crate::layout::StatusLayout;
+use crate::state::get_state;
+use crate::store::{ImageStore, stored_image};
+use crate::tls::{self, PinnedProvider, TlsFailure};
+
+/// Image buffer size: one frame of the selected panel in any orientation
+/// (600x448 pixels at 4 bits per pixel = 134_400 bytes on the 5.65")
+pub const IMAGE_BUFFER_SIZE: usize = epd::MAX_FRAME_BYTES;
+
+/// Buffer for the response status line and headers
+const HTTP_RX_BUFFER_SIZE: usize = 4096;
+
+/// Socket buffers for each direction of the connection to the image server
+const TCP_BUFFER_SIZE: usize = 4096;
+
+/// TLS record buffers, only reserved when the image URL is https://
+const TLS_READ_BYTES: usize = if IMAGE_HTTPS {
+    tls::TLS_READ_BUFFER_SIZE
+} else {
+    0
+};
+const TLS_WRITE_BYTES: usize = if IMAGE_HTTPS {
+    tls::TLS_WRITE_BUFFER_SIZE
+} else {
+    0
+};
+
+/// Body bytes pulled from the socket per read
+const BODY_CHUNK_SIZE: usize = 1024;
+
+/// Decoded bytes handed to the sink per write
+const DECODE_CHUNK_SIZE: usize = 1024;
+
+/// Body encodings the firmware can decode, advertised via `Accept-Encoding`
+const ACCEPT_ENCODING: &str = "x-packbits, identity";
+
+/// Device settings the response headers override
+const HEADER_DEFAULTS: HeaderDefaults = HeaderDefaults {
+    orientation: EPD_ORIENTATION,
+    dither: IMAGE_DITHER,
+    utc_offset_minutes: UTC_OFFSET_MINUTES,
+};
+
+/// Checksum of the body for `X-Content-CRC32` (the zlib/PNG CRC-32)
+static BODY_CRC32: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+/// Checksum of the body for `Digest: crc32c=...` (Castagnoli)
+static BODY_CRC32C: Crc<u32> = Crc::<u32>::new(&CRC_32_ISCSI);
+
+/// Most `[[wifi.networks]]` entries, one bit each in a scan result
+pub const MAX_WIFI_NETWORKS: usize = 8;
+
+/// A WiFi network from `[[wifi.networks]]`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct WifiNetwork {
+    pub ssid: &'static str,
+    /// WPA2 passphrase, empty for an open network
+    pub password: &'static str,
+    /// Networks in range are joined highest priority first (`WIFI_NETWORKS` is sorted)
+    pub priority: u8,
+}
+
+/// Why a downloaded body was not accepted as the new image.
+/// The previous image stays on display.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum ValidationError {
+    /// The body ended before (or ran past) its `Content-Length`
+    BodyLength,
+    /// The body does not match its `X-Content-CRC32` or `Digest` checksum
+    Checksum,
+    /// Compressed data, a pixel row or an image file ended early
+    Truncated,
+    /// The decoded image is not exactly one frame for the active panel
+    ImageSize,
+}
+
+impl ValidationError {
+    /// Short name for logs and status reports
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::BodyLength => "body-length",
+            Self::Checksum => "checksum",
+            Self::Truncated => "truncated",
+            Self::ImageSize => "image-size",
+        }
     }
+}
 
-    info!("Downloading image from: {}", IMAGE_URL);
+/// Why an update cycle ended without a new image
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum NetworkError {
+    /// Joining the WiFi network failed on every retry
+    JoinFailed,
+    /// The WiFi link did not come up after joining
+    LinkTimeout,
+    /// No address from DHCP
+    DhcpTimeout,
+    /// The image server name could not be resolved
+    Dns,
+    /// No TCP connection to the image server
+    Connect,
+    /// The TLS handshake with the image server failed
+    Tls(TlsFailure),
+    /// Sending the request or reading the response headers failed
+    Request,
+    /// The server answered with a status other than 200
+    HttpStatus(u16),
+    /// The connection failed while reading the body
+    BodyRead,
+    /// The download did not finish in time
+    Timeout,
+    /// The response asks for a format, encoding or pixel order the firmware cannot handle
+    Unsupported(&'static str),
+    /// The BMP/PNG file is corrupt
+    Decode(&'static str),
+    /// The body failed validation
+    Validation(ValidationError),
+    /// The panel or the image store could not take the image
+    Device(&'static str),
+}
+
+/// Who is to blame for a `NetworkError`, decides the warning and the retries
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum ErrorClass {
+    /// WiFi, DHCP, DNS or the connection to the server
+    Network,
+    /// The server is reachable but returned an error
+    Server,
+    /// The server sent an image the firmware refuses
+    Content,
+    /// The device itself failed
+    Device,
+}
 
-    // Create HTTP client with reqwless
-    let client_state = TcpClientState::<1, 4096, 4096>::new();
-    let tcp_client = TcpClient::new(*stack, &client_state);
-    let dns_client = DnsSocket::new(*stack);
-    let mut http_client = HttpClient::new(&tcp_client, &dns_client);
+impl NetworkError {
+    pub const fn class(&self) -> ErrorClass {
+        match self {
+            Self::JoinFailed
+            | Self::LinkTimeout
+            | Self::DhcpTimeout
+            | Self::Dns
+            | Self::Connect
+            | Self::Request
+            | Self::BodyRead
+            | Self::Timeout => ErrorClass::Network,
+            Self::Tls(_) | Self::HttpStatus(_) => ErrorClass::Server,
+            Self::Unsupported(_) | Self::Decode(_) | Self::Validation(_) => ErrorClass::Content,
+            Self::Device(_) => ErrorClass::Device,
+        }
+    }
+
+    /// Short name for logs and status captions
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::JoinFailed => "join-failed",
+            Self::LinkTimeout => "link-timeout",
+            Self::DhcpTimeout => "dhcp-timeout",
+            Self::Dns => "dns",
+            Self::Connect => "connect",
+            Self::Tls(_) => "tls",
+            Self::Request => "request",
+            Self::HttpStatus(_) => "http-status",
+            Self::BodyRead => "body-read",
+            Self::Timeout => "timeout",
+            Self::Unsupported(_) => "unsupported",
+            Self::Decode(_) => "decode",
+            Self::Validation(error) => error.as_str(),
+            Self::Device(_) => "device",
+        }
+    }
+}
+
+impl From<ValidationError> for NetworkError {
+    fn from(error: ValidationError) -> Self {
+        Self::Validation(error)
+    }
+}
+
+impl From<HeaderError> for NetworkError {
+    fn from(error: HeaderError) -> Self {
+        Self::Unsupported(error.as_str())
+    }
+}
+
+/// Connection to the image server, plain TCP or TLS for https:// URLs
+// One per download on the stack, there is no allocator to box the TLS state into
+#[allow(clippy::large_enum_variant)]
+enum ServerConnection<'a> {
+    Plain(TcpSocket<'a>),
+    Tls(TlsConnection<'a, TcpSocket<'a>, Aes128GcmSha256>),
+}
 
-    // Make HTTP GET request
-    let mut request = http_client
-        .request(Method::GET, IMAGE_URL)
+impl ErrorType for ServerConnection<'_> {
+    type Error = ErrorKind;
+}
+
+impl Read for ServerConnection<'_> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, ErrorKind> {
+        match self {
+            Self::Plain(socket) => socket.read(buf).await.map_err(|e| e.kind()),
+            Self::Tls(connection) => connection.read(buf).await.map_err(|e| e.kind()),
+        }
+    }
+}
+
+impl Write for ServerConnection<'_> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, ErrorKind> {
+        match self {
+            Self::Plain(socket) => socket.write(buf).await.map_err(|e| e.kind()),
+            Self::Tls(connection) => connection.write(buf).await.map_err(|e| e.kind()),
+        }
+    }
+
+    async fn flush(&mut self) -> Result<(), ErrorKind> {
+        match self {
+            Self::Plain(socket) => socket.flush().await.map_err(|e| e.kind()),
+            Self::Tls(connection) => connection.flush().await.map_err(|e| e.kind()),
+        }
+    }
+}
+
+/// Connect to the image server, with TLS for https:// URLs
+async fn connect<'a>(
+    stack: &Stack<'a>,
+    tcp_rx: &'a mut [u8],
+    tcp_tx: &'a mut [u8],
+    tls_read: &'a mut [u8],
+    tls_write: &'a mut [u8],
+) -> Result<HttpConnection<'a, ServerConnection<'a>>, NetworkError> {
+    // Also accepts an IP address literal
+    let addresses = stack
+        .dns_query(IMAGE_HOST, DnsQueryType::A)
         .await
-        .map_err(|_| "Failed to create HTTP request")?;
+        .map_err(|e| {
+            error!("Resolving {} failed: {:?}", IMAGE_HOST, e);
+            NetworkError::Dns
+        })?;
+    let Some(&address) = addresses.first() else {
+        error!("No address for {}", IMAGE_HOST);
+        return Err(NetworkError::Dns);
+    };
 
-    // Send request and get response
-    let response = request
-        .send(image_buffer)
+    let mut socket = TcpSocket::new(*stack, tcp_rx, tcp_tx);
+    socket.connect((address, IMAGE_PORT)).await.map_err(|e| {
+        error!("Connecting to {}:{} failed: {:?}", address, IMAGE_PORT, e);
+        NetworkError::Connect
+    })?;
+
+    // build.rs requires a pin for https://
+    let Some(pin) = TLS_PIN.filter(|_| IMAGE_HTTPS) else {
+        return Ok(HttpConnection::Plain(ServerConnection::Plain(socket)));
+    };
+    let mut connection = TlsConnection::new(socket, tls_read, tls_write);
+    let config = TlsConfig::new().with_server_name(IMAGE_HOST);
+    connection
+        .open(TlsContext::new(&config, PinnedProvider::new(pin)))
         .await
-        .map_err(|_| "Failed to send HTTP request")?;
+        .map_err(|e| NetworkError::Tls(TlsFailure::from_error(e)))?;
+    info!("TLS session with {} established", IMAGE_HOST);
+    Ok(HttpConnection::Plain(ServerConnection::Tls(connection)))
+}
 
-    info!("Response status: {}", response.status.0);
+/// Destination for the image body while it is being downloaded
+pub trait ImageSink {
+    /// Called once the response headers have been accepted, before any body bytes
+    async fn begin(&mut self, headers: &ImageHeaders) -> Result<(), NetworkError>;
+    /// Store `data` at byte `offset` of the frame
+    async fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), NetworkError>;
+    /// The body ended after `len` bytes
+    async fn finish(&mut self, len: usize) -> Result<(), NetworkError>;
+    /// The transfer failed or was cancelled after `begin`
+    async fn abort(&mut self);
+    /// Whether written bytes survive `abort`, so a retry can continue the body
+    /// where it stopped instead of starting over
+    fn resumable(&self) -> bool {
+        false
+    }
+}
 
-    if response.status.0 != 200 {
-        error!("HTTP error: status {}", response.status.0);
-        return Err("HTTP request failed");
-    }
-
-    // Parse X-Next-Delay header
-    let mut next_delay: Option<u64> = None;
-    for (name, value) in response.headers() {
-        if name.eq_ignore_ascii_case("x-next-delay") {
-            if let Ok(value_str) = core::str::from_utf8(value) {
-                if let Ok(delay) = value_str.parse::<u64>() {
-                    next_delay = Some(delay);
-                    info!("X-Next-Delay header found: {} seconds", delay);
-                } else {
-                    warn!("Failed to parse X-Next-Delay value: {}", value_str);
-                }
+/// Sink writing into the RAM staging buffer, normalized to upright orientation.
+/// A complete image is promoted to the flash image store the display task reads.
+pub struct FrameSink<'a> {
+    frame: &'a mut [u8; IMAGE_BUFFER_SIZE],
+    store: ImageStore,
+    orientation: ImageOrientation,
+    display: Orientation,
+    deep_clean: bool,
+    status_layout: StatusLayout,
+    date_minute: Option<u16>,
+}
+
+impl<'a> FrameSink<'a> {
+    pub fn new(frame: &'a mut [u8; IMAGE_BUFFER_SIZE], store: ImageStore) -> Self {
+        Self {
+            frame,
+            store,
+            orientation: ImageOrientation::Upright,
+            display: EPD_ORIENTATION,
+            deep_clean: false,
+            status_layout: STATUS_LAYOUT,
+            date_minute: None,
+        }
+    }
+}
+
+impl FrameSink<'_> {
+    /// Expected body size: one image in the display orientation, or one panel frame
+    fn frame_bytes(&self) -> usize {
+        match self.orientation {
+            ImageOrientation::Upright => self.display.frame_bytes(),
+            ImageOrientation::Panel => PANEL_FRAME_BYTES,
+        }
+    }
+}
+
+impl ImageSink for FrameSink<'_> {
+    async fn begin(&mut self, headers: &ImageHeaders) -> Result<(), NetworkError> {
+        self.orientation = headers.orientation;
+        self.display = headers.display_orientation;
+        self.deep_clean = headers.deep_clean;
+        self.status_layout = headers.status_bar.unwrap_or(STATUS_LAYOUT);
+        self.date_minute = headers.date_minute;
+        Ok(())
+    }
+
+    async fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), NetworkError> {
+        let end = offset
+            .checked_add(data.len())
+            .filter(|&end| end <= self.frame_bytes())
+            .ok_or(ValidationError::ImageSize)?;
+
+        match self.orientation {
+            ImageOrientation::Upright => self.frame[offset..end].copy_from_slice(data),
+            // Put every panel pixel back where `display` will pick it up
+            ImageOrientation::Panel => {
+                self.display
+                    .write_panel_bytes(&mut self.frame[..], offset, data)
             }
-            break;
         }
+        Ok(())
     }
 
-    if next_delay.is_none() {
-        info!("X-Next-Delay header not found, will use default interval");
+    /// `download_image` has checked the size of the image
+    async fn finish(&mut self, _len: usize) -> Result<(), NetworkError> {
+        // Replace the shown image and its settings together, between display updates
+        let mut stored = stored_image().await;
+        let image = &self.frame[..self.display.frame_bytes()];
+        self.store
+            .promote(&mut stored, image, self.display)
+            .map_err(NetworkError::Device)?;
+        let mut state = get_state().await;
+        state.image_orientation = self.display;
+        state.status_layout = self.status_layout;
+        state.last_download_minute = self.date_minute;
+        if self.deep_clean {
+            info!("Deep clean requested by server");
+            state.deep_clean_requested = true;
+        }
+        Ok(())
     }
 
-    // Read response body
-    let body_bytes = response
-        .body()
-        .read_to_end()
-        .await
-        .map_err(|_| "Failed to read response body")?;
+    /// The staged bytes are simply overwritten by the next download
+    async fn abort(&mut self) {}
+
+    /// The staging buffer keeps the bytes of an interrupted download
+    fn resumable(&self) -> bool {
+        true
+    }
+}
 
-    let body_len = body_bytes.len();
-    info!("Downloaded {} bytes", body_len);
+/// Body of an interrupted download that is staged in the sink, kept by the
+/// network task between the attempts of one update cycle so the next attempt
+/// can ask for the rest with a `Range` request
+#[derive(Default)]
+pub struct PartialDownload {
+    /// Headers of the response the staged bytes belong to, `None` if the body
+    /// cannot be resumed (compressed or converted on the device, no length)
+    headers: Option<ImageHeaders>,
+    /// Validators of that response, its strong `ETag` goes into `If-Range`
+    validators: ImageValidators,
+    /// Body bytes staged so far
+    received: usize,
+    /// Checksums over the staged bytes
+    crc32: Option<Digest<'static, u32>>,
+    crc32c: Option<Digest<'static, u32>>,
+}
 
-    if body_len != IMAGE_BUFFER_SIZE {
+impl PartialDownload {
+    /// Byte to continue the staged body at, if there is one to resume
+    fn resume_at(&self) -> Option<usize> {
+        let resumable = self.headers.is_some() && self.validators.etag.is_some();
+        (resumable && self.received > 0).then_some(self.received)
+    }
+
+    /// Whether a `206 Partial Content` response carries the rest of the staged body
+    fn continued_by<'h>(&self, headers: impl Iterator<Item = (&'h str, &'h [u8])>) -> bool {
+        let (Some(received), Some(total)) = (
+            self.resume_at(),
+            self.headers.and_then(|h| h.content_length),
+        ) else {
+            return false;
+        };
+        let range = headers
+            .filter(|(name, _)| name.eq_ignore_ascii_case("content-range"))
+            .find_map(|(_, value)| {
+                core::str::from_utf8(value)
+                    .ok()
+                    .and_then(parse_content_range)
+            });
+        if range == Some((received, total)) {
+            return true;
+        }
         warn!(
+            "Content-Range {} does not continue byte {} of {}",
+            range, received, total
+        );
+        false
+    }
+}
+
+/// Decode one body chunk and pass the frame bytes to `sink`, starting at `*offset`.
+/// BMP/PNG rows are placed `row_bytes` apart.
+async fn write_decoded(
+    sink: &mut impl ImageSink,
+    decoder: &mut Option<PackBitsDecoder>,
+    container: &mut Option<ContainerDecoder>,
+    ditherer: &mut Option<Ditherer>,
+    chunk: &[u8],
+    row_bytes: usize,
+    offset: &mut usize,
+) -> Result<(), NetworkError> {
+    let Some(decoder) = decoder else {
+        return write_converted(sink, container, ditherer, chunk, row_bytes, offset).await;
+    };
+
+    let mut input = chunk;
+    let mut decoded = [0u8; DECODE_CHUNK_SIZE];
+    loop {
+        let n = decoder.decode(&mut input, &mut decoded);
+        if n == 0 {
+            return Ok(());
+        }
+        write_converted(sink, container, ditherer, &decoded[..n], row_bytes, offset).await?;
+    }
+}
+
+/// Convert decoded RGB/gray bytes or BMP/PNG files to panel pixels (native frames
+/// pass through) and write them to `sink` at `*offset`
+async fn write_converted(
+    sink: &mut impl ImageSink,
+    container: &mut Option<ContainerDecoder>,
+    ditherer: &mut Option<Ditherer>,
+    data: &[u8],
+    row_bytes: usize,
+    offset: &mut usize,
+) -> Result<(), NetworkError> {
+    if let (Some(container), Some(ditherer)) = (container, ditherer.as_mut()) {
+        return write_rows(sink, container, ditherer, data, row_bytes, offset).await;
+    }
+    let Some(ditherer) = ditherer else {
+        return write_frame_bytes(sink, data, offset).await;
+    };
+
+    let mut input = data;
+    let mut converted = [0u8; DECODE_CHUNK_SIZE];
+    loop {
+        let n = ditherer.convert(&mut input, &mut converted);
+        if n == 0 {
+            return Ok(());
+        }
+        write_frame_bytes(sink, &converted[..n], offset).await?;
+    }
+}
+
+/// Decode BMP/PNG rows, dither their palette colors and write each row to `sink`
+/// at its place in the frame, `row_bytes` per row (bitmaps arrive bottom-up).
+/// `*offset` counts the frame bytes written.
+async fn write_rows(
+    sink: &mut impl ImageSink,
+    container: &mut ContainerDecoder,
+    ditherer: &mut Ditherer,
+    data: &[u8],
+    row_bytes: usize,
+    offset: &mut usize,
+) -> Result<(), NetworkError> {
+    let mut input = data;
+    let mut converted = [0u8; DECODE_CHUNK_SIZE];
+    while let Some(row) = container
+        .next_row(&mut input)
+        .map_err(NetworkError::Decode)?
+    {
+        let mut n = 0;
+        for &index in row.indices {
+            let rgb = row.palette[usize::from(index)];
+            n += ditherer.convert_pixel(rgb, &mut converted[n..]);
+        }
+        let mut row_offset = usize::from(row.y) * row_bytes;
+        write_frame_bytes(sink, &converted[..n], &mut row_offset).await?;
+        *offset += n;
+    }
+    Ok(())
+}
+
+/// Pass frame bytes to `sink` at `*offset` and advance it
+async fn write_frame_bytes(
+    sink: &mut impl ImageSink,
+    data: &[u8],
+    offset: &mut usize,
+) -> Result<(), NetworkError> {
+    if *offset + data.len() > IMAGE_BUFFER_SIZE {
+        error!("Decoded image exceeds {} bytes", IMAGE_BUFFER_SIZE);
+        return Err(ValidationError::ImageSize.into());
+    }
+    sink.write(*offset, data).await?;
+    *offset += data.len();
+    Ok(())
+}
+
+/// What a successful request brought
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Download {
+    /// A new image of this many decoded bytes went to the sink
+    Image(usize),
+    /// `304 Not Modified`: the image on display is still current
+    NotModified,
+}
+
+/// Download image (raw or PackBits-compressed, panel pixels or RGB/gray to be
+/// dithered, or an indexed BMP/PNG file) from HTTP server using reqwless
+/// Decoded body chunks are passed to `sink` as they arrive. The request is
+/// conditional on the validators of the image on display, unless KEY0 asked
+/// for a refresh. A body that `partial` has staged in an earlier attempt is
+/// continued with a `Range` request if the image has not changed.
+/// Returns tuple: (download, next_delay_seconds)
+pub async fn download_image(
+    stack: &Stack<'_>,
+    sink: &mut impl ImageSink,
+    partial: &mut PartialDownload,
+) -> Result<(Download, Option<u64>), NetworkError> {
+    info!("Downloading image from: {}", IMAGE_URL);
+
+    let mut tcp_rx = [0u8; TCP_BUFFER_SIZE];
+    let mut tcp_tx = [0u8; TCP_BUFFER_SIZE];
+    let mut tls_read = [0u8; TLS_READ_BYTES];
+    let mut tls_write = [0u8; TLS_WRITE_BYTES];
+    let mut connection = connect(
+        stack,
+        &mut tcp_rx,
+        &mut tcp_tx,
+        &mut tls_read,
+        &mut tls_write,
+    )
+    .await?;
+
+    // Report panel health and rejected images so failures are visible on the server side
+    let (display_error, display_failures, image_error, rejected_images, validators) = {
+        let state = get_state().await;
+        let validators = if state.force_refresh {
+            ImageValidators::new()
+        } else {
+            state.image_validators.clone()
+        };
+        (
+            state.last_display_error,
+            state.display_failure_count,
+            state.last_image_error,
+            state.rejected_image_count,
+            validators,
+        )
+    };
+    let mut failure_count = heapless::String::<10>::new();
+    let _ = core::write!(failure_count, "{}", display_failures);
+    let mut rejected_count = heapless::String::<10>::new();
+    let _ = core::write!(rejected_count, "{}", rejected_images);
+    let mut range = heapless::String::<32>::new();
+    // Owned and declared before the headers that borrow it, `partial` changes while the response is read
+    let if_range = partial.validators.etag.clone();
+
+    // Make HTTP GET request, offering compressed transfer
+    let mut request_headers = heapless::Vec::<(&str, &str), 7>::new();
+    let _ = request_headers.extend_from_slice(&[
+        ("Accept-Encoding", ACCEPT_ENCODING),
+        (
+            "X-Display-Status",
+            display_error.map_or("ok", |e| e.as_str()),
+        ),
+        ("X-Display-Failures", failure_count.as_str()),
+        ("X-Image-Status", image_error.map_or("ok", |e| e.as_str())),
+        ("X-Image-Rejections", rejected_count.as_str()),
+    ]);
+    let conditional = match (partial.resume_at(), &if_range) {
+        // The rest of the staged body, unless the image changed in the meantime
+        (Some(received), Some(etag)) => {
+            info!("Resuming the download at byte {}", received);
+            let _ = core::write!(range, "bytes={}-", received);
+            let _ = request_headers.push(("Range", range.as_str()));
+            let _ = request_headers.push(("If-Range", etag.as_str()));
+            false
+        }
+        _ => {
+            if let Some(etag) = &validators.etag {
+                let _ = request_headers.push(("If-None-Match", etag.as_str()));
+            }
+            if let Some(last_modified) = &validators.last_modified {
+                let _ = request_headers.push(("If-Modified-Since", last_modified.as_str()));
+            }
+            !validators.is_empty()
+        }
+    };
+    let request = Request::get(IMAGE_PATH)
+        .host(IMAGE_HOST)
+        .headers(&request_headers)
+        .build();
+
+    // Send request and get response
+    let mut rx_buffer = [0u8; HTTP_RX_BUFFER_SIZE];
+    let response = connection
+        .send(request, &mut rx_buffer)
+        .await
+        .map_err(|e| {
+            error!("HTTP request failed: {:?}", e);
+            NetworkError::Request
+        })?;
+
+    info!("Response status: {}", response.status.0);
+
+    if response.status.0 == 304 && conditional {
+        // Only the headers matter, such as `X-Next-Delay`
+        let headers = ImageHeaders::parse(response.headers(), None, &HEADER_DEFAULTS)?;
+        info!("Image not modified, keeping the image on display");
+        get_state().await.last_download_minute = headers.date_minute;
+        return Ok((Download::NotModified, headers.next_delay));
+    }
+
+    let resumed = response.status.0 == 206 && partial.continued_by(response.headers());
+    if !resumed && response.status.0 != 200 {
+        error!("HTTP error: status {}", response.status.0);
+        return Err(NetworkError::HttpStatus(response.status.0));
+    }
+
+    let (headers, new_validators, mut body_len, mut crc32, mut crc32c) =
+        match partial.headers.filter(|_| resumed) {
+            Some(headers) => {
+                info!("Continuing the staged body at byte {}", partial.received);
+                (
+                    headers,
+                    partial.validators.clone(),
+                    partial.received,
+                    partial.crc32.clone(),
+                    partial.crc32c.clone(),
+                )
+            }
+            None => {
+                if partial.resume_at().is_some() {
+                    info!("Image changed on the server, downloading it in full");
+                }
+                let headers = ImageHeaders::parse(
+                    response.headers(),
+                    response.content_length,
+                    &HEADER_DEFAULTS,
+                )?;
+                let new_validators = ImageValidators::from_headers(response.headers());
+                sink.begin(&headers).await?;
+                *partial = PartialDownload::default();
+                (
+                    headers,
+                    new_validators,
+                    0,
+                    headers.crc32.map(|_| BODY_CRC32.digest()),
+                    headers.crc32c.map(|_| BODY_CRC32C.digest()),
+                )
+            }
+        };
+
+    let mut decoder = match headers.encoding {
+        ContentEncoding::Identity => None,
+        ContentEncoding::PackBits => Some(PackBitsDecoder::new()),
+    };
+    let mut container = headers.container.map(|c| headers.container_decoder(c));
+    let mut ditherer = headers.ditherer(headers.container);
+
+    // Only bodies stored byte for byte can be continued at a byte offset, and
+    // only with a strong `ETag` to tell that the image has not changed
+    let resumable = sink.resumable()
+        && decoder.is_none()
+        && ditherer.is_none()
+        && headers.content_length.is_some()
+        && new_validators
+            .etag
+            .as_deref()
+            .is_some_and(|etag| !etag.starts_with("W/"));
+    if resumable && !resumed {
+        partial.headers = Some(headers);
+        partial.validators = new_validators.clone();
+    }
+
+    // Read response body chunk by chunk; a continued body is a native frame,
+    // one image byte per body byte
+    let mut reader = response.body().reader();
+    let mut chunk = [0u8; BODY_CHUNK_SIZE];
+    let mut image_len = body_len;
+    loop {
+        let n = match reader.read(&mut chunk).await {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) => {
+                error!("Reading the response body failed: {:?}", e);
+                sink.abort().await;
+                return Err(NetworkError::BodyRead);
+            }
+        };
+        // Without a Content-Type, recognize BMP/PNG files by their first bytes
+        if body_len == 0
+            && container.is_none()
+            && decoder.is_none()
+            && headers.format == PixelFormat::Native
+            && let Some(found) = Container::sniff(&chunk[..n])
+        {
+            container = Some(headers.container_decoder(found));
+            ditherer = headers.ditherer(Some(found));
+        }
+        body_len += n;
+        if let Some(digest) = &mut crc32 {
+            digest.update(&chunk[..n]);
+        }
+        if let Some(digest) = &mut crc32c {
+            digest.update(&chunk[..n]);
+        }
+        let written = write_decoded(
+            sink,
+            &mut decoder,
+            &mut container,
+            &mut ditherer,
+            &chunk[..n],
+            headers.row_bytes(),
+            &mut image_len,
+        );
+        match written.await {
+            Ok(()) => {}
+            Err(NetworkError::Validation(error)) => return Err(reject(sink, error).await),
+            Err(e) => {
+                sink.abort().await;
+                return Err(e);
+            }
+        }
+        // Remember what is staged so a retry can ask for the rest
+        if resumable && container.is_none() {
+            partial.received = body_len;
+            partial.crc32 = crc32.clone();
+            partial.crc32c = crc32c.clone();
+        }
+    }
+
+    info!(
+        "Downloaded {} bytes ({} bytes decoded)",
+        body_len, image_len
+    );
+    if let Some(expected) = headers.content_length
+        && body_len != expected
+    {
+        error!("Body of {} bytes, Content-Length {}", body_len, expected);
+        return Err(reject(sink, ValidationError::BodyLength).await);
+    }
+    let checksums = [
+        (
+            "X-Content-CRC32",
+            headers.crc32,
+            crc32.map(|d| d.finalize()),
+        ),
+        (
+            "Digest crc32c",
+            headers.crc32c,
+            crc32c.map(|d| d.finalize()),
+        ),
+    ];
+    for (name, expected, actual) in checksums {
+        if let (Some(expected), Some(actual)) = (expected, actual)
+            && expected != actual
+        {
+            error!("{} {=u32:#x}, body has {=u32:#x}", name, expected, actual);
+            return Err(reject(sink, ValidationError::Checksum).await);
+        }
+    }
+    if decoder.as_ref().is_some_and(|d| !d.is_complete()) {
+        error!("PackBits stream ended in the middle of a run");
+        return Err(reject(sink, ValidationError::Truncated).await);
+    }
+    if container.as_ref().is_some_and(|c| !c.is_complete()) {
+        error!("Image file ended before its last row");
+        return Err(reject(sink, ValidationError::Truncated).await);
+    }
+    if ditherer.as_ref().is_some_and(|d| !d.is_complete()) {
+        error!("Image data ended in the middle of a row");
+        return Err(reject(sink, ValidationError::Truncated).await);
+    }
+    if image_len != headers.frame_bytes() {
+        error!(
             "Image size mismatch: got {} bytes, expected {}",
-            body_len, IMAGE_BUFFER_SIZE
+            image_len,
+            headers.frame_bytes()
         );
+        return Err(reject(sink, ValidationError::ImageSize).await);
+    }
+    if headers.crc32.is_some() || headers.crc32c.is_some() {
+        info!("Image checksum verified");
     }
 
-    Ok((body_bytes, next_delay))
+    if let Err(e) = sink.finish(image_len).await {
+        sink.abort().await;
+        return Err(e);
+    }
+    let mut state = get_state().await;
+    state.last_image_error = None;
+    state.image_validators = new_validators;
+
+    Ok((Download::Image(image_len), headers.next_delay))
+}
+
+/// Drop a body that failed validation (the previous image stays on display)
+/// and count it. Returns the error for the caller.
+async fn reject(sink: &mut impl ImageSink, error: ValidationError) -> NetworkError {
+    sink.abort().await;
+    let mut state = get_state().await;
+    state.last_image_error = Some(error);
+    state.rejected_image_count = state.rejected_image_count.saturating_add(1);
+    warn!(
+        "Image rejected: {} ({} since boot)",
+        error, state.rejected_image_count
+    );
+    error.into()
 }
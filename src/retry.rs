@@ -0,0 +1,93 @@
+//! Retry backoff
+//! How long the firmware waits before retrying a failed update cycle: exponential
+//! backoff from the `image.retry_*` settings, spread by a random jitter.
+
+/// Backoff settings (`image.retry_initial_secs`, `image.retry_multiplier`,
+/// `image.retry_jitter_percent`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub initial_secs: u32,
+    pub multiplier: u32,
+    pub jitter_percent: u8,
+}
+
+impl RetryPolicy {
+    /// Wait before the retry after `step` earlier failed retries:
+    /// `initial_secs * multiplier^step`, spread by up to `jitter_percent` either
+    /// way (picked by `random`) so devices sharing a server drift apart, and
+    /// never longer than the normal interval `interval_secs`
+    pub fn delay_secs(&self, step: u8, interval_secs: u64, random: u64) -> u64 {
+        let base = u64::from(self.initial_secs)
+            .saturating_mul(u64::from(self.multiplier).saturating_pow(u32::from(step)))
+            .min(interval_secs);
+        let spread = base * u64::from(self.jitter_percent) / 100;
+        let jitter = random % (2 * spread + 1);
+        (base - spread + jitter).min(interval_secs)
+    }
+}
+
+/// Pseudo-random value from a free-running timer reading (like the network stack
+/// seed), scrambled with xorshift so close timestamps give unrelated values
+pub fn jitter_value(ticks: u64) -> u64 {
+    let mut x = ticks | 1;
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    x
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use super::*;
+
+    const POLICY: RetryPolicy = RetryPolicy {
+        initial_secs: 30,
+        multiplier: 2,
+        jitter_percent: 20,
+    };
+
+    const HOUR: u64 = 60 * 60;
+
+    #[test]
+    fn delays_grow_by_the_multiplier() {
+        let policy = RetryPolicy {
+            jitter_percent: 0,
+            ..POLICY
+        };
+        let delays: [u64; 4] = core::array::from_fn(|step| policy.delay_secs(step as u8, HOUR, 7));
+
+        assert_eq!(delays, [30, 60, 120, 240]);
+    }
+
+    #[test]
+    fn delays_never_exceed_the_interval() {
+        // Capped at the interval, the jitter can only shorten the wait
+        assert_eq!(POLICY.delay_secs(10, HOUR, 0), HOUR - HOUR / 5);
+        assert_eq!(POLICY.delay_secs(10, HOUR, 2 * HOUR / 5), HOUR);
+        // The power overflows long before the last step
+        assert!(POLICY.delay_secs(u8::MAX, HOUR, 12345) <= HOUR);
+        assert_eq!(POLICY.delay_secs(0, 10, 4), 10);
+    }
+
+    #[test]
+    fn jitter_stays_within_the_spread() {
+        // Step 1: 60 s, spread by 12 s
+        assert_eq!(POLICY.delay_secs(1, HOUR, 0), 48);
+        assert_eq!(POLICY.delay_secs(1, HOUR, 24), 72);
+        assert_eq!(POLICY.delay_secs(1, HOUR, 25), 48);
+        for ticks in 0..1000 {
+            let delay = POLICY.delay_secs(1, HOUR, jitter_value(ticks));
+            assert!((48..=72).contains(&delay), "{delay}");
+        }
+    }
+
+    #[test]
+    fn jitter_values_differ_for_close_ticks() {
+        assert_ne!(jitter_value(0), 0);
+        assert_eq!(jitter_value(1000), jitter_value(1000));
+        let spread: Vec<u64> = (1000..1010).map(|t| jitter_value(t) % 25).collect();
+        assert!(spread.windows(2).any(|pair| pair[0] != pair[1]));
+    }
+}
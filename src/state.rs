@@ -3,6 +3,13 @@
 
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 use embassy_sync::mutex::Mutex;
+use embassy_time::Instant;
+
+use crate::draw::status::Alert;
+use crate::epd::{EpdError, Orientation};
+use crate::headers::ImageValidators;
+use crate::layout::StatusLayout;
+use crate::network::{NetworkError, ValidationError};
 
 /// Shared application state
 pub struct AppState {
@@ -10,30 +17,107 @@ pub struct AppState {
     pub next_update_delay_secs: u64,
     /// Battery percentage (0-100)
     pub battery_percent: u8,
+    /// RP2040 die temperature in °C, `None` until the first valid reading
+    pub temperature_c: Option<i8>,
     /// Whether WiFi is connected
     pub wifi_connected: bool,
     /// Last image download success
     pub last_download_success: bool,
+    /// Why the latest update cycle failed, `None` after a successful download
+    pub last_update_error: Option<NetworkError>,
+    /// Failed update cycles in a row, the backoff step of the next retry (0 after a success)
+    pub retry_step: u8,
+    /// Wait before the pending retry, `None` while updates follow `next_update_delay_secs`
+    pub retry_delay_secs: Option<u64>,
     /// Number of WiFi join retries in the latest update cycle
     pub wifi_retry_count: u8,
+    /// WiFi signal strength of the latest connection in dBm
+    pub rssi_dbm: Option<i16>,
+    /// When the last image was downloaded successfully
+    pub last_download_at: Option<Instant>,
+    /// Server time of the last successful download, in minutes since local midnight
+    pub last_download_minute: Option<u16>,
+    /// When the scheduler starts the next download
+    pub next_update_at: Option<Instant>,
+    /// Orientation of the stored image (see `crate::store`)
+    pub image_orientation: Orientation,
+    /// Status bar drawn over the stored image
+    pub status_layout: StatusLayout,
+    /// Error of the latest panel update, `None` if it succeeded
+    pub last_display_error: Option<EpdError>,
+    /// Number of failed panel updates since boot
+    pub display_failure_count: u32,
+    /// Why the latest downloaded body was rejected, `None` if it was accepted
+    pub last_image_error: Option<ValidationError>,
+    /// Number of downloaded bodies rejected since boot
+    pub rejected_image_count: u32,
+    /// Validators of the image on display, empty when the next download has
+    /// to fetch the image in full
+    pub image_validators: ImageValidators,
+    /// CRC32 of the frame currently on the panel, `None` if unknown
+    pub last_frame_crc: Option<u32>,
+    /// Warning drawn on the panel, `None` if none or unknown
+    pub shown_alert: Option<Alert>,
+    /// Refreshes skipped in a row because the frame was unchanged
+    pub skipped_refreshes: u32,
+    /// Refresh the panel on the next update even if the frame is unchanged
+    pub force_refresh: bool,
+    /// Full refreshes since the last deep clean
+    pub refreshes_since_clean: u32,
+    /// When the last deep clean ran, `None` if not since boot
+    pub last_clean: Option<Instant>,
+    /// Deep clean requested by the server (`X-Deep-Clean`)
+    pub deep_clean_requested: bool,
+    /// A refresh was held back because the temperature was out of range
+    pub refresh_postponed: bool,
 }
 
 impl AppState {
     /// Create new application state with default values
-    pub const fn new(default_update_interval_minutes: u32) -> Self {
+    pub const fn new(
+        default_update_interval_minutes: u32,
+        orientation: Orientation,
+        status_layout: StatusLayout,
+    ) -> Self {
         Self {
             next_update_delay_secs: default_update_interval_minutes as u64 * 60,
             battery_percent: 0,
+            temperature_c: None,
             wifi_connected: false,
             last_download_success: false,
+            last_update_error: None,
+            retry_step: 0,
+            retry_delay_secs: None,
             wifi_retry_count: 0,
+            rssi_dbm: None,
+            last_download_at: None,
+            last_download_minute: None,
+            next_update_at: None,
+            image_orientation: orientation,
+            status_layout,
+            last_display_error: None,
+            display_failure_count: 0,
+            last_image_error: None,
+            rejected_image_count: 0,
+            image_validators: ImageValidators::new(),
+            last_frame_crc: None,
+            shown_alert: None,
+            skipped_refreshes: 0,
+            force_refresh: false,
+            refreshes_since_clean: 0,
+            last_clean: None,
+            deep_clean_requested: false,
+            refresh_postponed: false,
         }
     }
 }
 
 /// Global application state, protected by mutex
-pub static APP_STATE: Mutex<CriticalSectionRawMutex, AppState> =
-    Mutex::new(AppState::new(crate::config::UPDATE_INTERVAL_MINUTES));
+pub static APP_STATE: Mutex<CriticalSectionRawMutex, AppState> = Mutex::new(AppState::new(
+    crate::config::UPDATE_INTERVAL_MINUTES,
+    crate::config::EPD_ORIENTATION,
+    crate::config::STATUS_LAYOUT,
+));
 
 /// Get a reference to the global application state
 pub async fn get_state()
@@ -0,0 +1,206 @@
+//! Flash copy of the image on display
+//! Downloads land in the RAM staging buffer owned by the network task. Only a
+//! complete, validated image is programmed into the flash area reserved at the
+//! end of `memory.x`; the display task reads it from there through XIP, so a
+//! failed transfer never touches the image that is being shown.
+//!
+//! The first sector of the area holds a header with the length, CRC and
+//! orientation of the image, written after the image itself. At boot an intact
+//! header brings the image back until the first download replaces it.
+
+use crc::{CRC_32_ISO_HDLC, Crc};
+use defmt::{error, info, warn};
+use embassy_rp::Peri;
+use embassy_rp::flash::{Blocking, ERASE_SIZE, Flash};
+use embassy_rp::peripherals::FLASH;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::mutex::{Mutex, MutexGuard};
+
+use crate::epd::{Orientation, Rotation};
+use crate::network::IMAGE_BUFFER_SIZE;
+use crate::state::get_state;
+
+/// Flash size of the Pico W
+const FLASH_SIZE: usize = 2 * 1024 * 1024;
+
+/// Bytes reserved for the image at the end of flash (keep in sync with `memory.x`)
+const STORE_SIZE: usize = 256 * 1024;
+
+/// Offset of the image area (header sector first) from the start of flash
+const STORE_OFFSET: usize = FLASH_SIZE - STORE_SIZE;
+
+/// Offset of the image itself, in the sector after the header
+const IMAGE_OFFSET: usize = STORE_OFFSET + ERASE_SIZE;
+
+/// Address flash is mapped to for execute-in-place reads
+const XIP_BASE: usize = 0x1000_0000;
+
+const _: () = assert!(
+    IMAGE_BUFFER_SIZE <= STORE_SIZE - ERASE_SIZE,
+    "image does not fit the flash area"
+);
+
+/// Marks a programmed header ("EPIM"); erased flash reads as 0xFF
+const HEADER_MAGIC: u32 = 0x4D49_5045;
+
+/// Bytes of the header: magic, length, CRC, rotation in degrees, mirror flag
+const HEADER_BYTES: usize = 16;
+
+/// Checksum of the stored image
+static IMAGE_CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+/// What the header records about the stored image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Header {
+    len: usize,
+    crc: u32,
+    orientation: Orientation,
+}
+
+impl Header {
+    fn new(image: &[u8], orientation: Orientation) -> Self {
+        Self {
+            len: image.len(),
+            crc: IMAGE_CRC.checksum(image),
+            orientation,
+        }
+    }
+
+    fn to_bytes(self) -> [u8; HEADER_BYTES] {
+        let mut bytes = [0u8; HEADER_BYTES];
+        bytes[0..4].copy_from_slice(&HEADER_MAGIC.to_le_bytes());
+        bytes[4..8].copy_from_slice(&(self.len as u32).to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.crc.to_le_bytes());
+        bytes[12..14].copy_from_slice(&self.orientation.rotation.degrees().to_le_bytes());
+        bytes[14] = self.orientation.mirror as u8;
+        bytes
+    }
+
+    /// Header for `bytes` if it is programmed and describes a whole image
+    fn from_bytes(bytes: &[u8; HEADER_BYTES]) -> Option<Self> {
+        let word = |at: usize| {
+            u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+        };
+        if word(0) != HEADER_MAGIC {
+            return None;
+        }
+        let rotation = Rotation::from_degrees(u16::from_le_bytes([bytes[12], bytes[13]]))?;
+        let orientation = Orientation::new(rotation, bytes[14] != 0);
+        let len = word(4) as usize;
+        (len == orientation.frame_bytes()).then_some(Self {
+            len,
+            crc: word(8),
+            orientation,
+        })
+    }
+}
+
+/// The image in the flash area
+pub struct StoredImage {
+    /// Bytes of the stored image, 0 until an image was promoted or restored
+    len: usize,
+}
+
+impl StoredImage {
+    /// Image bytes in the display orientation, empty if there is none
+    pub fn pixels(&self) -> &[u8] {
+        // SAFETY: memory.x keeps the whole area out of the firmware image, so
+        // XIP maps these bytes to nothing else. They are only erased and
+        // programmed by `ImageStore::promote`, which needs `&mut StoredImage`
+        // from the `STORED_IMAGE` lock and sets `len` to 0 before erasing and
+        // back only after programming. While `&self` borrows the guard no
+        // erase or write can run, and the first `len` bytes are programmed.
+        unsafe { core::slice::from_raw_parts((XIP_BASE + IMAGE_OFFSET) as *const u8, self.len) }
+    }
+
+    /// Header in flash, `None` if there is no valid one
+    fn header(&self) -> Option<Header> {
+        // SAFETY: as in `pixels`, the header sector is inside the reserved area
+        // and only `ImageStore::promote` erases or writes it, under the lock
+        // that `&self` is borrowed from.
+        let bytes = unsafe { &*((XIP_BASE + STORE_OFFSET) as *const [u8; HEADER_BYTES]) };
+        Header::from_bytes(bytes)
+    }
+}
+
+/// Held by the display task while it reads the image and by the network task
+/// while it replaces it
+static STORED_IMAGE: Mutex<CriticalSectionRawMutex, StoredImage> =
+    Mutex::new(StoredImage { len: 0 });
+
+/// Lock the stored image
+pub async fn stored_image() -> MutexGuard<'static, CriticalSectionRawMutex, StoredImage> {
+    STORED_IMAGE.lock().await
+}
+
+/// Take over the image a previous boot left in flash, with its orientation,
+/// if its header is intact and its CRC matches
+#[cfg_attr(feature = "streaming", allow(dead_code))]
+pub async fn restore_stored_image() {
+    let mut stored = stored_image().await;
+    let Some(header) = stored.header() else {
+        info!("No image in flash");
+        return;
+    };
+    stored.len = header.len;
+    if IMAGE_CRC.checksum(stored.pixels()) != header.crc {
+        warn!("Image in flash does not match its CRC, ignoring it");
+        stored.len = 0;
+        return;
+    }
+    get_state().await.image_orientation = header.orientation;
+    info!("Restored {} bytes of image from flash", header.len);
+}
+
+/// Programs promoted images into the flash area (owned by the network task)
+pub struct ImageStore {
+    flash: Flash<'static, FLASH, Blocking, FLASH_SIZE>,
+}
+
+impl ImageStore {
+    #[cfg_attr(feature = "streaming", allow(dead_code))]
+    pub fn new(flash: Peri<'static, FLASH>) -> Self {
+        Self {
+            flash: Flash::new_blocking(flash),
+        }
+    }
+
+    /// Replace the stored image with `image` (from RAM, in `orientation`). An
+    /// unchanged image is not written again to spare the flash.
+    ///
+    /// Erasing and programming block the executor (about a second for a full
+    /// frame), so a download timeout cannot leave the area half-written. The
+    /// header goes last: a reset in between leaves no valid image behind.
+    pub fn promote(
+        &mut self,
+        stored: &mut StoredImage,
+        image: &[u8],
+        orientation: Orientation,
+    ) -> Result<(), &'static str> {
+        let header = Header::new(image, orientation);
+        if stored.pixels() == image && stored.header() == Some(header) {
+            info!("Image unchanged, keeping the stored copy");
+            return Ok(());
+        }
+
+        // Nothing valid is stored until programming has finished
+        stored.len = 0;
+        let start = STORE_OFFSET as u32;
+        let end = IMAGE_OFFSET as u32 + image.len().next_multiple_of(ERASE_SIZE) as u32;
+        if let Err(e) = self.flash.blocking_erase(start, end) {
+            error!("Erasing the image area failed: {:?}", e);
+            return Err("Failed to store image");
+        }
+        if let Err(e) = self.flash.blocking_write(IMAGE_OFFSET as u32, image) {
+            error!("Programming the image area failed: {:?}", e);
+            return Err("Failed to store image");
+        }
+        if let Err(e) = self.flash.blocking_write(start, &header.to_bytes()) {
+            error!("Programming the image header failed: {:?}", e);
+            return Err("Failed to store image");
+        }
+        stored.len = image.len();
+        info!("Stored {} bytes of image in flash", image.len());
+        Ok(())
+    }
+}
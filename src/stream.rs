@@ -0,0 +1,200 @@
+//! Streaming hand-off between the network and display tasks
+//! Used with the `streaming` feature: HTTP body chunks go through a small ring
+//! buffer straight into the panel's data transmission instead of a RAM frame.
+//!
+//! The panel only refreshes once every byte has arrived and the network task
+//! has validated the body (`finish`), so an aborted or rejected download leaves
+//! the previous picture on screen.
+
+use defmt::{error, info, warn};
+use embassy_futures::select::{Either, select};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, pipe::Pipe, signal::Signal};
+use embassy_time::{Duration, with_timeout};
+
+use crate::epd::PANEL_FRAME_BYTES;
+use crate::headers::{ContentEncoding, ImageHeaders, ImageOrientation};
+use crate::image::dither::PixelFormat;
+use crate::network::{ImageSink, NetworkError, ValidationError};
+use crate::state::get_state;
+use crate::task::network::HTTP_DOWNLOAD_TIMEOUT_SECS;
+
+/// Ring buffer size between the HTTP reader and the panel
+pub const STREAM_BUFFER_SIZE: usize = 2048;
+
+/// How long the network side waits for the panel to become ready
+/// (covers a deep clean or a refresh of the previous frame that may still be
+/// running). Shorter than the download timeout so the body has time to arrive.
+const PANEL_READY_TIMEOUT_SECS: u64 = 30;
+const _: () = assert!(PANEL_READY_TIMEOUT_SECS < HTTP_DOWNLOAD_TIMEOUT_SECS);
+
+/// How long the display side waits for the next chunk before giving up
+const STREAM_IDLE_TIMEOUT_SECS: u64 = 30;
+
+static IMAGE_PIPE: Pipe<CriticalSectionRawMutex, STREAM_BUFFER_SIZE> = Pipe::new();
+
+/// Network -> display: a new frame is about to be streamed
+static STREAM_START: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+/// Display -> network: panel initialized (true) or failed (false)
+static STREAM_READY: Signal<CriticalSectionRawMutex, bool> = Signal::new();
+/// Network -> display: body validated, refresh the panel
+static STREAM_COMMIT: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+/// Network -> display: transfer aborted, do not refresh
+static STREAM_ABORT: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+/// Display -> network: panel failed mid-frame, stop sending
+static STREAM_FAILED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Image sink that forwards the body to the display task
+#[derive(Default)]
+pub struct PanelStream {
+    /// Frame offset the next write must start at (the panel takes bytes in order)
+    next_offset: usize,
+}
+
+impl PanelStream {
+    pub const fn new() -> Self {
+        Self { next_offset: 0 }
+    }
+}
+
+impl ImageSink for PanelStream {
+    async fn begin(&mut self, headers: &ImageHeaders) -> Result<(), NetworkError> {
+        if headers.orientation != ImageOrientation::Panel {
+            error!("Streaming requires X-Image-Orientation: panel");
+            return Err(NetworkError::Unsupported("Image not in panel order"));
+        }
+        if let Some(length) = headers.content_length
+            && headers.encoding == ContentEncoding::Identity
+            && headers.format == PixelFormat::Native
+            && headers.container.is_none()
+            && length != PANEL_FRAME_BYTES
+        {
+            error!(
+                "Image size mismatch: Content-Length {} bytes, expected {}",
+                length, PANEL_FRAME_BYTES
+            );
+            return Err(ValidationError::ImageSize.into());
+        }
+
+        if headers.deep_clean {
+            // Runs before the frame is streamed, see `display_stream::stream_frame`
+            info!("Deep clean requested by server");
+            get_state().await.deep_clean_requested = true;
+        }
+
+        self.next_offset = 0;
+        IMAGE_PIPE.clear();
+        STREAM_COMMIT.reset();
+        STREAM_ABORT.reset();
+        STREAM_FAILED.reset();
+        STREAM_READY.reset();
+        STREAM_START.signal(());
+
+        match with_timeout(
+            Duration::from_secs(PANEL_READY_TIMEOUT_SECS),
+            STREAM_READY.wait(),
+        )
+        .await
+        {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(NetworkError::Device("Display not ready")),
+            Err(_) => {
+                STREAM_START.reset();
+                Err(NetworkError::Device("Timed out waiting for display"))
+            }
+        }
+    }
+
+    async fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), NetworkError> {
+        if offset + data.len() > PANEL_FRAME_BYTES {
+            return Err(ValidationError::ImageSize.into());
+        }
+        if offset != self.next_offset {
+            // Bottom-up bitmaps, for example
+            error!("Streamed image rows out of order");
+            return Err(NetworkError::Unsupported("Image rows out of order"));
+        }
+        self.next_offset += data.len();
+        match select(IMAGE_PIPE.write_all(data), STREAM_FAILED.wait()).await {
+            Either::First(_) => Ok(()),
+            Either::Second(_) => Err(NetworkError::Device("Display failed during stream")),
+        }
+    }
+
+    async fn finish(&mut self, len: usize) -> Result<(), NetworkError> {
+        if len != PANEL_FRAME_BYTES {
+            warn!(
+                "Image size mismatch: got {} bytes, expected {}",
+                len, PANEL_FRAME_BYTES
+            );
+            return Err(ValidationError::ImageSize.into());
+        }
+        STREAM_COMMIT.signal(());
+        Ok(())
+    }
+
+    async fn abort(&mut self) {
+        STREAM_ABORT.signal(());
+    }
+}
+
+/// Display side: wait until the network task starts a frame
+pub async fn wait_start() {
+    STREAM_START.wait().await;
+    info!("Image stream starting");
+}
+
+/// Display side: report whether the panel is ready to receive the frame
+pub fn ready(ok: bool) {
+    STREAM_READY.signal(ok);
+}
+
+/// Display side: the panel failed while receiving, unblock the sender
+pub fn fail() {
+    STREAM_FAILED.signal(());
+}
+
+/// Display side: read the next chunk of the frame.
+/// Returns `None` if the sender aborted or went silent.
+pub async fn read(buf: &mut [u8]) -> Option<usize> {
+    match with_timeout(
+        Duration::from_secs(STREAM_IDLE_TIMEOUT_SECS),
+        select(IMAGE_PIPE.read(buf), STREAM_ABORT.wait()),
+    )
+    .await
+    {
+        Ok(Either::First(n)) => Some(n),
+        Ok(Either::Second(_)) => {
+            warn!("Image stream aborted by sender");
+            None
+        }
+        Err(_) => {
+            warn!("Image stream stalled for {}s", STREAM_IDLE_TIMEOUT_SECS);
+            None
+        }
+    }
+}
+
+/// Display side: wait for the network task's verdict on a fully received frame.
+/// Returns `true` once the body passed validation, `false` if the sender
+/// aborted or went silent.
+pub async fn committed() -> bool {
+    match with_timeout(
+        Duration::from_secs(STREAM_IDLE_TIMEOUT_SECS),
+        select(STREAM_COMMIT.wait(), STREAM_ABORT.wait()),
+    )
+    .await
+    {
+        Ok(Either::First(_)) => true,
+        Ok(Either::Second(_)) => {
+            warn!("Image stream rejected by sender");
+            false
+        }
+        Err(_) => {
+            warn!(
+                "No verdict on the image stream after {}s",
+                STREAM_IDLE_TIMEOUT_SECS
+            );
+            false
+        }
+    }
+}
@@ -1,112 +1,383 @@
 //! Display management task
 //! Handles e-Paper display updates and rendering
 
-use defmt::{error, info};
+use crc::{CRC_32_ISO_HDLC, Crc};
+use defmt::{error, info, warn};
+use embassy_futures::select::{Either, select};
 use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+use embassy_time::{Duration, Instant};
+use embedded_graphics::prelude::{Point, Size};
+use embedded_graphics::primitives::Rectangle;
 
-use crate::config::EpdPins;
-use crate::epd_5in65f::{
-    EPD_5IN65F_BLACK, EPD_5IN65F_WHITE, Epd5in65f, draw_broken_wifi_warning,
-    draw_low_battery_warning, draw_number,
+use crate::FIRMWARE_VERSION;
+use crate::config::{
+    CLEAN_EVERY_REFRESHES, CLEAN_INTERVAL_HOURS, DEEP_CLEAN_COLOR, Epd, EpdPins,
+    MAX_REFRESH_TEMPERATURE, MAX_SKIPPED_REFRESHES, MIN_REFRESH_TEMPERATURE,
 };
+use crate::draw::overlay::OverlayFrame;
+use crate::draw::status::{Alert, Overlay, StatusInfo};
+use crate::draw::text::{TextStyle, draw_paragraph, draw_text};
+use crate::draw::{EpdColor, Framebuffer};
+use crate::epd::{Display, EpdError, Orientation, WHITE, Window, fill_byte};
+use crate::event::{Event, send_event};
+use crate::layout::StatusLayout;
 use crate::network::IMAGE_BUFFER_SIZE;
 use crate::state::get_state;
+use crate::store::stored_image;
+use crate::task::power::refresh_temperature_ok;
 
 /// Signal for triggering display update
 static DISPLAY_UPDATE_SIGNAL: Signal<CriticalSectionRawMutex, ()> = Signal::new();
 
+/// Signal for refreshing only the status bar
+static STATUS_UPDATE_SIGNAL: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Fingerprint of the frames sent to the panel
+pub static FRAME_CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
 /// Signals the display task to update
 pub fn signal_display_update() {
     DISPLAY_UPDATE_SIGNAL.signal(());
 }
 
+/// Signals the display task to redraw the status bar
+pub fn signal_status_update() {
+    STATUS_UPDATE_SIGNAL.signal(());
+}
+
 /// Display handler task - manages e-Paper display updates
+/// (the `streaming` feature uses `display_stream::display_handler` instead)
+#[cfg_attr(feature = "streaming", allow(dead_code))]
 #[embassy_executor::task]
-pub async fn display_handler(
-    epd_pins: EpdPins<'static>,
-    image_buffer: &'static mut [u8; IMAGE_BUFFER_SIZE],
-) -> ! {
+pub async fn display_handler(epd_pins: EpdPins) -> ! {
     info!("Display handler task started");
 
     // Initialize e-paper driver
-    let mut epd = Epd5in65f::new(epd_pins);
+    let mut epd = epd_pins.into_driver();
 
     loop {
-        // Wait for signal from orchestrator
-        DISPLAY_UPDATE_SIGNAL.wait().await;
+        // Wait for signal from orchestrator; a full update also redraws the status bar.
+        // A status update while a full refresh is still postponed shows the whole image.
+        let status_only = matches!(
+            select(DISPLAY_UPDATE_SIGNAL.wait(), STATUS_UPDATE_SIGNAL.wait()).await,
+            Either::Second(_)
+        );
+        if status_only && !get_state().await.refresh_postponed {
+            // Keep the image (and its settings) from being replaced while it is shown
+            let stored = stored_image().await;
+            let (status, layout, orientation) = status_snapshot().await;
+            info!("Status update: battery {}%", status.battery_percent);
+            let (width, height) = orientation.size();
+            let Some(window) = layout.window(width, height) else {
+                info!("Status bar has no visible widgets, nothing to refresh");
+                continue;
+            };
+            let image = stored.pixels();
+            if image.is_empty() {
+                info!("No image stored yet, skipping status update");
+                continue;
+            }
+            if !refresh_allowed().await {
+                continue;
+            }
+            // The whole overlay is composed so the fingerprint matches a full update
+            let overlay = Overlay::new(layout, status);
+            let alert = overlay.alert();
+            let mut frame = OverlayFrame::new(image, overlay);
+            epd.set_orientation(orientation);
+            match refresh_status(&mut epd, &mut frame, window).await {
+                Ok(()) => {
+                    info!("Status update complete");
+                    frame_shown(frame_crc(image, orientation, &mut frame), alert).await;
+                    send_event(Event::DisplayUpdated).await;
+                }
+                Err(e) => {
+                    error!("Status update failed: {:?}", e);
+                    display_failed(&mut epd, e).await;
+                }
+            }
+            continue;
+        }
 
         info!("Display update signal received");
+        STATUS_UPDATE_SIGNAL.reset();
+
+        // Keep the image (and its settings) from being replaced while it is shown
+        let stored = stored_image().await;
 
         // Get display indicators from state
-        let (battery_percent, wifi_retry_count) = {
-            let state = get_state().await;
-            (state.battery_percent, state.wifi_retry_count)
-        };
-
-        // Validate image size
-        if image_buffer.len() != IMAGE_BUFFER_SIZE {
-            error!(
-                "Invalid image size: got {} bytes, expected {} bytes. Skipping display.",
-                image_buffer.len(),
-                IMAGE_BUFFER_SIZE
-            );
+        let (status, layout, orientation) = status_snapshot().await;
+
+        // Before the first image only the overlays are shown, on white
+        let image = stored.pixels();
+        if image.is_empty() {
+            warn!("No image stored yet, showing overlays only");
+        }
+
+        // Overlays are drawn in image coordinates, so they follow the image orientation.
+        // They are composed on the way to the panel and never touch the stored image.
+        let overlay = Overlay::new(layout, status);
+        match overlay.alert() {
+            Some(Alert::LowBattery) => {
+                info!(
+                    "Battery low ({}%), showing warning icon",
+                    status.battery_percent
+                )
+            }
+            Some(Alert::BrokenWifi) => info!(
+                "Network unstable ({} retries, {}), showing broken WiFi icon",
+                status.wifi_retries, status.failure
+            ),
+            Some(Alert::ServerError) => {
+                info!("Server error ({}), showing server warning", status.failure)
+            }
+            Some(Alert::BadImage) => {
+                info!(
+                    "Image rejected ({}), showing bad image warning",
+                    status.failure
+                )
+            }
+            None => info!("Showing status bar ({} widgets)", layout.slots().len()),
+        }
+        let alert = overlay.alert();
+        let mut frame = OverlayFrame::new(image, overlay);
+
+        // The stored image stays until the panel is warm enough
+        if !refresh_allowed().await {
             continue;
         }
 
-        if battery_percent < 30 {
-            // Replace weather icon area with large low-battery warning.
-            info!("Battery low ({}%), drawing warning icon", battery_percent);
-            draw_low_battery_warning(image_buffer);
-            draw_number(image_buffer, 0, 0, battery_percent, EPD_5IN65F_BLACK, 3);
-        } else if wifi_retry_count > 2 {
-            info!(
-                "Network unstable ({} retries), drawing broken WiFi icon",
-                wifi_retry_count
-            );
-            draw_broken_wifi_warning(image_buffer);
-            draw_number(image_buffer, 0, 0, battery_percent, EPD_5IN65F_BLACK, 3);
-        } else {
-            // Draw small battery percentage in top-left corner.
-            info!("Drawing battery percentage: {}%", battery_percent);
-            draw_number(image_buffer, 0, 0, battery_percent, EPD_5IN65F_BLACK, 3);
+        // Skip the slow full refresh when the panel already shows this frame
+        let crc = frame_crc(image, orientation, &mut frame);
+        if !needs_refresh(crc).await {
+            continue;
+        }
+
+        info!("Image orientation: {}", orientation);
+        epd.set_orientation(orientation);
+        let clean = deep_clean_due().await;
+        match refresh_panel(&mut epd, &mut frame, clean).await {
+            Ok(()) => {
+                info!("Display update complete");
+                frame_shown(crc, alert).await;
+                refresh_done(clean).await;
+                send_event(Event::DisplayUpdated).await;
+            }
+            Err(e) => {
+                error!("Display update failed: {:?}", e);
+                display_failed(&mut epd, e).await;
+            }
         }
+    }
+}
+
+/// Values for the status bar, with the status bar layout and orientation of the stored image
+async fn status_snapshot() -> (StatusInfo, StatusLayout, Orientation) {
+    let state = get_state().await;
+    let now = Instant::now();
+    let status = StatusInfo {
+        battery_percent: state.battery_percent,
+        temperature: state.temperature_c,
+        wifi_retries: state.wifi_retry_count,
+        failure: state.last_update_error,
+        rssi_dbm: state.rssi_dbm,
+        updated_minute: state.last_download_minute,
+        age_secs: state
+            .last_download_at
+            .map(|at| now.saturating_duration_since(at).as_secs()),
+        next_update_secs: state
+            .next_update_at
+            .map(|at| at.saturating_duration_since(now).as_secs()),
+    };
+    (status, state.status_layout, state.image_orientation)
+}
+
+/// Report a failed update and make sure the panel is not left powered
+async fn display_failed(epd: &mut Epd, error: EpdError) {
+    if let Err(e) = epd.sleep().await {
+        warn!("EPD sleep after failure also failed: {:?}", e);
+    }
+    frame_lost().await;
+    send_event(Event::DisplayFailed(error)).await;
+}
+
+/// CRC32 of an image with its overlay, together with the orientation it is shown in
+fn frame_crc(image: &[u8], orientation: Orientation, frame: &mut OverlayFrame) -> u32 {
+    let mut digest = FRAME_CRC.digest();
+    digest.update(image);
+    digest.update(&orientation.rotation.degrees().to_le_bytes());
+    digest.update(&[orientation.mirror as u8]);
+    frame.digest_overlay(&orientation, &mut digest);
+    digest.finalize()
+}
+
+/// Decide whether a frame with fingerprint `crc` has to be pushed to the panel.
+/// Identical frames are skipped up to `MAX_SKIPPED_REFRESHES` times in a row,
+/// unless KEY0 asked for a refresh.
+pub async fn needs_refresh(crc: u32) -> bool {
+    let mut state = get_state().await;
+    if core::mem::take(&mut state.force_refresh) {
+        info!("Refresh forced by KEY0");
+        return true;
+    }
+    if state.deep_clean_requested {
+        info!("Deep clean requested, refreshing");
+        return true;
+    }
+    if state.last_frame_crc != Some(crc) {
+        return true;
+    }
+    if state.skipped_refreshes >= MAX_SKIPPED_REFRESHES {
+        info!(
+            "Frame unchanged, refreshing after {} skips",
+            state.skipped_refreshes
+        );
+        return true;
+    }
 
-        // Initialize display
-        info!("EPD init");
-        epd.init().await;
+    state.skipped_refreshes += 1;
+    info!(
+        "Frame unchanged (crc {=u32:#x}), skipping refresh ({}/{})",
+        crc, state.skipped_refreshes, MAX_SKIPPED_REFRESHES
+    );
+    false
+}
 
-        // Clear display with white background
-        info!("Clear display");
-        epd.clear(EPD_5IN65F_WHITE).await;
+/// Record that the panel now shows the frame with fingerprint `crc` and the warning `alert`
+pub async fn frame_shown(crc: u32, alert: Option<Alert>) {
+    let mut state = get_state().await;
+    state.last_frame_crc = Some(crc);
+    state.shown_alert = alert;
+    state.skipped_refreshes = 0;
+    state.refresh_postponed = false;
+}
 
-        // Display the image
-        info!("Display image data");
-        epd.display(image_buffer).await;
+/// Whether the panel may be refreshed now. Outside the configured temperature
+/// window the refresh is postponed until the power task reports it back in range.
+pub async fn refresh_allowed() -> bool {
+    let mut state = get_state().await;
+    if refresh_temperature_ok(state.temperature_c) {
+        return true;
+    }
+    state.refresh_postponed = true;
+    warn!(
+        "Chip temperature {} C outside {}..{} C, postponing refresh",
+        state.temperature_c, MIN_REFRESH_TEMPERATURE, MAX_REFRESH_TEMPERATURE
+    );
+    false
+}
 
-        // Put panel to sleep to save power
-        info!("EPD sleep");
-        epd.sleep().await;
+/// Forget the on-screen fingerprint after a failed update so the next frame is not skipped
+pub async fn frame_lost() {
+    let mut state = get_state().await;
+    state.last_frame_crc = None;
+    state.shown_alert = None;
+}
 
-        info!("Display update complete");
+/// Whether the next full refresh should start with a deep-clean pass:
+/// requested by the server, every `CLEAN_EVERY_REFRESHES` refreshes, or when
+/// `CLEAN_INTERVAL_HOURS` have passed (also on the first refresh after boot)
+pub async fn deep_clean_due() -> bool {
+    let state = get_state().await;
+    let by_count =
+        CLEAN_EVERY_REFRESHES > 0 && state.refreshes_since_clean + 1 >= CLEAN_EVERY_REFRESHES;
+    let interval = Duration::from_secs(u64::from(CLEAN_INTERVAL_HOURS) * 60 * 60);
+    let by_time =
+        CLEAN_INTERVAL_HOURS > 0 && state.last_clean.is_none_or(|at| at.elapsed() >= interval);
+    state.deep_clean_requested || by_count || by_time
+}
+
+/// Count a successful full refresh, `cleaned` if it included a deep clean
+pub async fn refresh_done(cleaned: bool) {
+    let mut state = get_state().await;
+    if cleaned {
+        state.refreshes_since_clean = 0;
+        state.last_clean = Some(Instant::now());
+        state.deep_clean_requested = false;
+    } else {
+        state.refreshes_since_clean = state.refreshes_since_clean.saturating_add(1);
+    }
+}
+
+/// Run the init/[clean]/display/sleep sequence for one frame
+async fn refresh_panel(
+    epd: &mut Epd,
+    frame: &mut OverlayFrame<'_>,
+    clean: bool,
+) -> Result<(), EpdError> {
+    // Initialize display
+    info!("EPD init");
+    epd.init().await?;
+
+    // Deep clean: clear the whole panel first to shake off ghosting
+    if clean {
+        info!("Deep clean (color {})", DEEP_CLEAN_COLOR);
+        epd.clear(DEEP_CLEAN_COLOR).await?;
+    }
+
+    // Display the image
+    info!("Display image data");
+    epd.display(frame).await?;
+
+    // Put panel to sleep to save power
+    info!("EPD sleep");
+    epd.sleep().await
+}
+
+/// Refresh the panel for a new status bar `window` of `frame`, all of it on
+/// panels without a partial update
+async fn refresh_status(
+    epd: &mut Epd,
+    frame: &mut OverlayFrame<'_>,
+    window: Window,
+) -> Result<(), EpdError> {
+    epd.init().await?;
+    match epd.display_window(&mut *frame, window).await {
+        Err(EpdError::Unsupported) => {
+            info!("No partial update on this panel, refreshing all of it");
+            epd.display(frame).await?;
+        }
+        result => result?,
     }
+    epd.sleep().await
 }
 
 /// Display test pattern (for debugging)
 #[allow(dead_code)]
-pub async fn display_test_pattern(epd: &mut Epd5in65f<'_>) {
+pub async fn display_test_pattern(epd: &mut Epd) -> Result<(), EpdError> {
     info!("Displaying test pattern");
 
-    epd.init().await;
-    epd.clear(EPD_5IN65F_WHITE).await;
+    epd.init().await?;
+    epd.clear(WHITE).await?;
 
     // Create a simple test pattern
-    let mut test_buffer = [0x11u8; IMAGE_BUFFER_SIZE]; // White background
+    let mut test_buffer = [fill_byte(WHITE); IMAGE_BUFFER_SIZE]; // White background
 
     // Draw some test content
-    draw_number(&mut test_buffer, 10, 10, 42, EPD_5IN65F_BLACK, 3);
+    let orientation = epd.orientation();
+    let mut fb = Framebuffer::new(&mut test_buffer, orientation);
+    let heading = draw_text(
+        &mut fb,
+        FIRMWARE_VERSION,
+        Point::new(10, 10),
+        TextStyle::new(EpdColor::Black, 3),
+    );
+    let top = heading.top_left.y + heading.size.height as i32 + 10;
+    let area = Rectangle::new(
+        Point::new(10, top),
+        Size::new(u32::from(fb.width()) - 20, 100),
+    );
+    draw_paragraph(
+        &mut fb,
+        "The quick brown fox jumps over the lazy dog.\n\
+         Съешь же ещё этих мягких французских булок, да выпей чаю.",
+        area,
+        TextStyle::new(EpdColor::Red, 2),
+    );
 
-    epd.display(&test_buffer).await;
-    epd.sleep().await;
+    epd.display(&test_buffer[..]).await?;
+    epd.sleep().await?;
 
     info!("Test pattern displayed");
+    Ok(())
 }
@@ -0,0 +1,156 @@
+//! Streaming display task
+//! Feeds HTTP body chunks from the network task straight into the panel
+//! (enabled by the `streaming` feature; no RAM frame buffer, no overlays)
+
+use defmt::{error, info};
+
+use crate::config::DEEP_CLEAN_COLOR;
+use crate::config::{Epd, EpdPins};
+use crate::epd::{Display, EpdError, PANEL_FRAME_BYTES};
+use crate::event::{Event, send_event};
+use crate::stream;
+use crate::task::display::{
+    FRAME_CRC, deep_clean_due, frame_lost, frame_shown, needs_refresh, refresh_allowed,
+    refresh_done,
+};
+
+/// Bytes moved from the stream to the panel per SPI transfer
+const PANEL_CHUNK_SIZE: usize = 512;
+
+/// Outcome of one streamed frame
+enum Frame {
+    /// The panel was refreshed with the new frame
+    Shown,
+    /// The frame matched the one on screen, refresh skipped
+    Unchanged,
+    /// The sender aborted or rejected the frame; the panel was left untouched
+    Aborted,
+    /// Temperature out of range; the frame was received but not sent to the panel
+    Postponed,
+}
+
+/// Display handler task - refreshes the panel from the image stream
+#[embassy_executor::task]
+pub async fn display_handler(epd_pins: EpdPins) -> ! {
+    info!("Display handler task started (streaming)");
+
+    // Initialize e-paper driver
+    let mut epd = epd_pins.into_driver();
+
+    loop {
+        // Wait for the network task to start a download
+        stream::wait_start().await;
+
+        let result = stream_frame(&mut epd).await;
+        match result {
+            Ok(Frame::Shown) => info!("Display update complete"),
+            Ok(Frame::Unchanged) => info!("Image unchanged, keeping previous image"),
+            Ok(Frame::Aborted) => info!("Image stream incomplete, keeping previous image"),
+            Ok(Frame::Postponed) => {
+                info!("Refresh postponed, keeping previous image");
+                // The panel was not woken up
+                continue;
+            }
+            Err(e) => {
+                error!("Display update failed: {:?}", e);
+                stream::fail();
+            }
+        }
+
+        // Put panel to sleep to save power
+        info!("EPD sleep");
+        if let Err(e) = epd.sleep().await {
+            error!("EPD sleep failed: {:?}", e);
+        }
+
+        match result {
+            Ok(Frame::Shown) => send_event(Event::DisplayUpdated).await,
+            Ok(Frame::Unchanged | Frame::Aborted | Frame::Postponed) => {}
+            Err(e) => send_event(Event::DisplayFailed(e)).await,
+        }
+    }
+}
+
+/// Receive one frame from the stream and refresh the panel once it is complete
+/// and the network task has committed it. The frame data sits in panel RAM
+/// until the refresh, so skipping it (unchanged, aborted or rejected frame)
+/// leaves the previous image on screen.
+///
+/// A due deep clean has to run before the frame data arrives (the sender waits
+/// for it), and then the frame is always shown.
+async fn stream_frame(epd: &mut Epd) -> Result<Frame, EpdError> {
+    if !refresh_allowed().await {
+        // Let the download complete; the image is fetched again once it is warmer
+        stream::ready(true);
+        return Ok(discard_frame().await);
+    }
+
+    let clean = deep_clean_due().await;
+    let prepared = prepare_panel(epd, clean).await;
+    stream::ready(prepared.is_ok());
+    prepared?;
+
+    let mut chunk = [0u8; PANEL_CHUNK_SIZE];
+    let mut received = 0usize;
+    let mut digest = FRAME_CRC.digest();
+    while received < PANEL_FRAME_BYTES {
+        let max = (PANEL_FRAME_BYTES - received).min(PANEL_CHUNK_SIZE);
+        let Some(n) = stream::read(&mut chunk[..max]).await else {
+            return Ok(Frame::Aborted);
+        };
+        epd.write_frame(&chunk[..n]).await?;
+        digest.update(&chunk[..n]);
+        received += n;
+    }
+
+    // The checksum and decoder checks run after the last byte was sent
+    if !stream::committed().await {
+        return Ok(Frame::Aborted);
+    }
+
+    let crc = digest.finalize();
+    if !needs_refresh(crc).await {
+        return Ok(Frame::Unchanged);
+    }
+
+    info!("Frame received ({} bytes), refreshing", received);
+    match epd.finish_frame().await {
+        Ok(()) => {
+            // Streamed frames carry no overlays
+            frame_shown(crc, None).await;
+            refresh_done(clean).await;
+            Ok(Frame::Shown)
+        }
+        Err(e) => {
+            frame_lost().await;
+            Err(e)
+        }
+    }
+}
+
+/// Drain a frame from the stream without touching the panel
+async fn discard_frame() -> Frame {
+    let mut chunk = [0u8; PANEL_CHUNK_SIZE];
+    let mut received = 0usize;
+    while received < PANEL_FRAME_BYTES {
+        let max = (PANEL_FRAME_BYTES - received).min(PANEL_CHUNK_SIZE);
+        let Some(n) = stream::read(&mut chunk[..max]).await else {
+            return Frame::Aborted;
+        };
+        received += n;
+    }
+    Frame::Postponed
+}
+
+/// Initialize the panel, deep-clean it if requested, and start the frame transfer
+async fn prepare_panel(epd: &mut Epd, clean: bool) -> Result<(), EpdError> {
+    info!("EPD init");
+    epd.init().await?;
+    if clean {
+        info!("Deep clean (color {})", DEEP_CLEAN_COLOR);
+        // The panel no longer shows the previous frame
+        frame_lost().await;
+        epd.clear(DEEP_CLEAN_COLOR).await?;
+    }
+    epd.begin_frame().await
+}
@@ -3,13 +3,18 @@
 
 pub mod buttons;
 pub mod display;
+#[cfg(feature = "streaming")]
+pub mod display_stream;
 pub mod network;
 pub mod orchestrator;
 pub mod power;
 
 // Re-export commonly used items
 pub use buttons::button_handler;
+#[cfg(not(feature = "streaming"))]
 pub use display::display_handler;
-pub use network::{WifiPeripherals, network_manager};
+#[cfg(feature = "streaming")]
+pub use display_stream::display_handler;
+pub use network::{ImageTarget, WifiPeripherals, network_manager};
 pub use orchestrator::{orchestrator, scheduler};
 pub use power::{battery_monitor, wait_battery_ready};
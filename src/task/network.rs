@@ -1,23 +1,28 @@
 //! Network and WiFi management task
 //! Handles WiFi connection, network stack, and image downloads
 
-use cyw43::JoinOptions;
-use cyw43_pio::{DEFAULT_CLOCK_DIVIDER, PioSpi};
 use cortex_m::{interrupt, peripheral::SCB};
+use cyw43::{JoinOptions, ScanOptions};
+use cyw43_pio::{DEFAULT_CLOCK_DIVIDER, PioSpi};
 use defmt::{error, info, warn};
-use embassy_futures::select::{Either, select};
 use embassy_executor::Spawner;
+use embassy_futures::select::{Either, select};
 use embassy_net::{Config, StackResources};
-use embassy_rp::dma::{Channel, InterruptHandler as DmaInterruptHandler};
+use embassy_rp::dma::Channel;
 use embassy_rp::gpio::{Level, Output};
 use embassy_rp::peripherals::{DMA_CH0, PIN_23, PIN_24, PIN_25, PIN_29, PIO0};
-use embassy_rp::pio::{InterruptHandler, Pio};
+use embassy_rp::pio::Pio;
 use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, mutex::Mutex, signal::Signal};
 use embassy_time::{Duration, Instant, Timer, with_timeout};
 use static_cell::StaticCell;
 
+use crate::draw::status::Alert;
 use crate::event::{Event, send_event};
-use crate::network::{IMAGE_BUFFER_SIZE, download_image};
+use crate::headers::ImageValidators;
+use crate::network::{
+    Download, ErrorClass, ImageSink, MAX_WIFI_NETWORKS, NetworkError, PartialDownload,
+    WIFI_NETWORKS, WifiNetwork, download_image,
+};
 use crate::state::get_state;
 use crate::task::display::signal_display_update;
 
@@ -36,20 +41,43 @@ static mut CYW43_CONTROL_REF: Option<
     &'static Mutex<CriticalSectionRawMutex, cyw43::Control<'static>>,
 > = None;
 
+/// Where downloaded images go: the RAM frame buffer, or the panel stream
+/// when built with the `streaming` feature
+#[cfg(not(feature = "streaming"))]
+pub type ImageTarget = crate::network::FrameSink<'static>;
+#[cfg(feature = "streaming")]
+pub type ImageTarget = crate::stream::PanelStream;
+
 /// Upper bounds to prevent waiting forever in bad network conditions.
 const WIFI_JOIN_TOTAL_TIMEOUT_SECS: u64 = 90;
+/// Failed joins before giving up, raised so every candidate network gets its attempts
 const WIFI_JOIN_MAX_RETRIES: u8 = 8;
+/// Failed joins before falling back to the next network in range
+const WIFI_JOIN_ATTEMPTS_PER_NETWORK: u8 = 2;
+/// A scan normally completes within a few seconds
+const WIFI_SCAN_TIMEOUT_SECS: u64 = 10;
 const WIFI_JOIN_PROGRESS_LOG_SECS: u64 = 10;
 /// If `join()` never returns (driver stuck), we cannot cancel the future safely — reset the MCU.
 const WIFI_JOIN_STUCK_RESET_SECS: u64 = 90;
 const WIFI_LINK_TIMEOUT_SECS: u64 = 20;
 const DHCP_TIMEOUT_SECS: u64 = 20;
-const HTTP_DOWNLOAD_TIMEOUT_SECS: u64 = 45;
+pub const HTTP_DOWNLOAD_TIMEOUT_SECS: u64 = 45;
+/// Pause before another download attempt within the same cycle
+const DOWNLOAD_RETRY_DELAY_SECS: u64 = 5;
 
 /// Clamp dynamic server delay to safe bounds.
 const MIN_NEXT_UPDATE_DELAY_SECS: u64 = 30;
 const MAX_NEXT_UPDATE_DELAY_SECS: u64 = 12 * 60 * 60;
-const WIFI_WARNING_RETRY_COUNT: u8 = 3;
+
+/// Download attempts per cycle for failures of `class`: network trouble often
+/// clears within seconds, server and content errors would only repeat until
+/// the next scheduled update
+const fn download_attempts(class: ErrorClass) -> u8 {
+    match class {
+        ErrorClass::Network => 3,
+        ErrorClass::Server | ErrorClass::Content | ErrorClass::Device => 1,
+    }
+}
 
 fn sanitize_next_delay_secs(delay_secs: u64) -> u64 {
     delay_secs.clamp(MIN_NEXT_UPDATE_DELAY_SECS, MAX_NEXT_UPDATE_DELAY_SECS)
@@ -58,7 +86,9 @@ fn sanitize_next_delay_secs(delay_secs: u64) -> u64 {
 fn resolve_next_delay_secs(server_delay: Option<u64>) -> u64 {
     server_delay
         .map(sanitize_next_delay_secs)
-        .unwrap_or_else(|| sanitize_next_delay_secs(crate::config::UPDATE_INTERVAL_MINUTES as u64 * 60))
+        .unwrap_or_else(|| {
+            sanitize_next_delay_secs(crate::config::UPDATE_INTERVAL_MINUTES as u64 * 60)
+        })
 }
 
 async fn apply_next_delay(server_delay: Option<u64>) -> bool {
@@ -80,39 +110,102 @@ async fn apply_next_delay(server_delay: Option<u64>) -> bool {
 
     state.next_update_delay_secs = new_delay;
     state.last_download_success = true;
+    state.last_update_error = None;
+    // The scheduler restarts its timer with the new delay right away
+    let now = Instant::now();
+    state.last_download_at = Some(now);
+    state.next_update_at = Some(now + Duration::from_secs(new_delay));
     old_delay != new_delay
 }
 
-async fn mark_download_failed(wifi_issue: bool) {
+async fn mark_download_failed(error: NetworkError) {
     let mut state = get_state().await;
     state.last_download_success = false;
-    if wifi_issue {
-        state.wifi_retry_count = WIFI_WARNING_RETRY_COUNT;
+    state.last_update_error = Some(error);
+    if error.class() == ErrorClass::Device {
+        // The panel or the stored image may not show what the validators describe
+        state.image_validators = ImageValidators::new();
     }
 }
 
-async fn fail_download_and_refresh(wifi_issue: bool) {
-    mark_download_failed(wifi_issue).await;
-    send_event(Event::ImageDownloadFailed).await;
+async fn fail_download_and_refresh(error: NetworkError) {
+    mark_download_failed(error).await;
+    send_event(Event::ImageDownloadFailed(error)).await;
 }
 
 #[derive(Clone, Copy)]
 enum NetworkCycleState {
     JoinWifi,
     WaitNetworkReady,
-    DownloadImage,
-    FinalizeSuccess,
-    FinalizeFailure { wifi_issue: bool },
+    /// `attempt` counts from 1, see `download_attempts`
+    DownloadImage {
+        attempt: u8,
+    },
+    /// `download` tells whether the image on display has to be replaced
+    FinalizeSuccess {
+        download: Download,
+    },
+    FinalizeFailure(NetworkError),
     Disconnect,
 }
 
+/// Scan for the configured networks.
+/// Returns a mask of the `WIFI_NETWORKS` indices that are in range.
+async fn scan_networks(control: &mut cyw43::Control<'static>) -> u8 {
+    let mut visible = 0u8;
+    let mut scanner = control.scan(ScanOptions::default()).await;
+    let scan = async {
+        while let Some(bss) = scanner.next().await {
+            let ssid = &bss.ssid[..usize::from(bss.ssid_len).min(bss.ssid.len())];
+            let rssi = bss.rssi;
+            for (index, network) in WIFI_NETWORKS.iter().enumerate() {
+                if network.ssid.as_bytes() == ssid && visible & (1 << index) == 0 {
+                    info!("WiFi network {} in range ({} dBm)", network.ssid, rssi);
+                    visible |= 1 << index;
+                }
+            }
+        }
+    };
+    if with_timeout(Duration::from_secs(WIFI_SCAN_TIMEOUT_SECS), scan)
+        .await
+        .is_err()
+    {
+        warn!("WiFi scan timed out after {}s", WIFI_SCAN_TIMEOUT_SECS);
+    }
+    visible
+}
+
+/// Networks to try in order: those in range by priority, or all of them if
+/// the scan found none (hidden SSIDs do not show up in a scan)
+fn wifi_candidates(visible: u8) -> heapless::Vec<&'static WifiNetwork, MAX_WIFI_NETWORKS> {
+    let in_range = WIFI_NETWORKS
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| visible & (1 << index) != 0)
+        .map(|(_, network)| network);
+    let candidates: heapless::Vec<_, MAX_WIFI_NETWORKS> = in_range.collect();
+    if candidates.is_empty() {
+        warn!("No configured WiFi network found in scan, trying all");
+        return WIFI_NETWORKS.iter().collect();
+    }
+    candidates
+}
+
 async fn connect_wifi_with_retries(
     control_mutex: &Mutex<CriticalSectionRawMutex, cyw43::Control<'static>>,
-) -> bool {
-    info!("Joining WiFi network: {}", crate::network::WIFI_SSID);
+) -> Result<(), NetworkError> {
+    let join_start = Instant::now();
+    let candidates = {
+        let mut control = control_mutex.lock().await;
+        control
+            .set_power_management(cyw43::PowerManagementMode::Performance)
+            .await;
+        wifi_candidates(scan_networks(&mut control).await)
+    };
+    let max_retries =
+        WIFI_JOIN_MAX_RETRIES.max(candidates.len() as u8 * WIFI_JOIN_ATTEMPTS_PER_NETWORK);
 
     let mut join_retry_count: u8 = 0;
-    let join_start = Instant::now();
 
     loop {
         if join_start.elapsed().as_secs() >= WIFI_JOIN_TOTAL_TIMEOUT_SECS {
@@ -120,9 +213,22 @@ async fn connect_wifi_with_retries(
                 "WiFi join timed out after {}s total",
                 WIFI_JOIN_TOTAL_TIMEOUT_SECS
             );
-            return false;
+            return Err(NetworkError::JoinFailed);
         }
 
+        // Fall back to the next network after a few failed joins, then start over
+        let index = usize::from(join_retry_count / WIFI_JOIN_ATTEMPTS_PER_NETWORK);
+        let network = candidates[index % candidates.len()];
+        info!(
+            "Joining WiFi network: {} (priority {})",
+            network.ssid, network.priority
+        );
+        let options = if network.password.is_empty() {
+            JoinOptions::new_open()
+        } else {
+            JoinOptions::new(network.password.as_bytes())
+        };
+
         let mut control = control_mutex.lock().await;
 
         control
@@ -131,10 +237,7 @@ async fn connect_wifi_with_retries(
 
         let join_result = {
             let attempt_started = Instant::now();
-            let mut join_future = core::pin::pin!(control.join(
-                crate::network::WIFI_SSID,
-                JoinOptions::new(crate::network::WIFI_PASSWORD.as_bytes()),
-            ));
+            let mut join_future = core::pin::pin!(control.join(network.ssid, options));
             loop {
                 match select(
                     join_future.as_mut(),
@@ -172,7 +275,10 @@ async fn connect_wifi_with_retries(
         };
 
         match join_result {
-            Ok(_) => return true,
+            Ok(_) => {
+                info!("Joined WiFi network: {}", network.ssid);
+                return Ok(());
+            }
             Err(err) => {
                 warn!("WiFi join failed with error: {:?}, retrying...", err);
             }
@@ -184,9 +290,9 @@ async fn connect_wifi_with_retries(
             state.wifi_retry_count = join_retry_count;
         }
 
-        if join_retry_count >= WIFI_JOIN_MAX_RETRIES {
-            warn!("WiFi join reached retry limit: {}", WIFI_JOIN_MAX_RETRIES);
-            return false;
+        if join_retry_count >= max_retries {
+            warn!("WiFi join reached retry limit: {}", max_retries);
+            return Err(NetworkError::JoinFailed);
         }
 
         drop(control);
@@ -194,7 +300,7 @@ async fn connect_wifi_with_retries(
     }
 }
 
-async fn wait_network_ready(stack: &embassy_net::Stack<'_>) -> bool {
+async fn wait_network_ready(stack: &embassy_net::Stack<'_>) -> Result<(), NetworkError> {
     info!("WiFi connected, waiting for link...");
     if with_timeout(
         Duration::from_secs(WIFI_LINK_TIMEOUT_SECS),
@@ -203,11 +309,8 @@ async fn wait_network_ready(stack: &embassy_net::Stack<'_>) -> bool {
     .await
     .is_err()
     {
-        warn!(
-            "Timeout waiting for link up ({}s)",
-            WIFI_LINK_TIMEOUT_SECS
-        );
-        return false;
+        warn!("Timeout waiting for link up ({}s)", WIFI_LINK_TIMEOUT_SECS);
+        return Err(NetworkError::LinkTimeout);
     }
 
     info!("Waiting for DHCP...");
@@ -219,10 +322,10 @@ async fn wait_network_ready(stack: &embassy_net::Stack<'_>) -> bool {
     .is_err()
     {
         warn!("Timeout waiting for DHCP config ({}s)", DHCP_TIMEOUT_SECS);
-        return false;
+        return Err(NetworkError::DhcpTimeout);
     }
 
-    true
+    Ok(())
 }
 
 /// Signals the network task to start update
@@ -264,7 +367,7 @@ async fn net_task(mut runner: embassy_net::Runner<'static, cyw43::NetDriver<'sta
 pub async fn network_manager(
     spawner: Spawner,
     peripherals: WifiPeripherals,
-    image_buffer: &'static mut [u8; IMAGE_BUFFER_SIZE],
+    mut image_target: ImageTarget,
 ) -> ! {
     info!("Network manager task started");
     Timer::after(Duration::from_secs(1)).await;
@@ -286,14 +389,9 @@ pub async fn network_manager(
     let pwr = Output::new(peripherals.pwr_pin, Level::Low);
     let cs = Output::new(peripherals.cs_pin, Level::High);
 
-    // Bind interrupts for PIO
-    embassy_rp::bind_interrupts!(struct Irqs {
-        PIO0_IRQ_0 => InterruptHandler<PIO0>;
-        DMA_IRQ_0 => DmaInterruptHandler<DMA_CH0>;
-    });
-
-    let mut pio = Pio::new(peripherals.pio, Irqs);
-    let dma = Channel::new(peripherals.dma_ch, Irqs);
+    // PIO0 and DMA_CH0 interrupts are bound in main.rs
+    let mut pio = Pio::new(peripherals.pio, crate::Irqs);
+    let dma = Channel::new(peripherals.dma_ch, crate::Irqs);
     let spi = PioSpi::new(
         &mut pio.common,
         pio.sm0,
@@ -382,75 +480,108 @@ pub async fn network_manager(
 
         let mut cycle_state = NetworkCycleState::JoinWifi;
         let mut delay_changed = false;
+        // Bytes of an interrupted download, continued by the next attempt of this cycle
+        let mut partial = PartialDownload::default();
         while !matches!(cycle_state, NetworkCycleState::Disconnect) {
             cycle_state = match cycle_state {
                 NetworkCycleState::JoinWifi => {
-                    if connect_wifi_with_retries(control_mutex).await {
-                        NetworkCycleState::WaitNetworkReady
-                    } else {
-                        NetworkCycleState::FinalizeFailure { wifi_issue: true }
+                    match connect_wifi_with_retries(control_mutex).await {
+                        Ok(()) => NetworkCycleState::WaitNetworkReady,
+                        Err(e) => NetworkCycleState::FinalizeFailure(e),
                     }
                 }
                 NetworkCycleState::WaitNetworkReady => {
-                    if wait_network_ready(&stack).await {
+                    if let Err(e) = wait_network_ready(&stack).await {
+                        NetworkCycleState::FinalizeFailure(e)
+                    } else {
                         info!("Network stack is up!");
                         if let Some(config) = stack.config_v4() {
                             info!("IP address: {}", config.address);
                         }
+                        let rssi = control_mutex.lock().await.get_rssi().await;
+                        info!("WiFi signal: {} dBm", rssi);
                         {
                             let mut state = get_state().await;
                             state.wifi_connected = true;
                             state.wifi_retry_count = 0;
+                            state.rssi_dbm = i16::try_from(rssi).ok();
                         }
                         send_event(Event::NetworkConnected).await;
-                        NetworkCycleState::DownloadImage
-                    } else {
-                        NetworkCycleState::FinalizeFailure { wifi_issue: true }
+                        NetworkCycleState::DownloadImage { attempt: 1 }
                     }
                 }
-                NetworkCycleState::DownloadImage => {
-                    info!("Downloading image...");
-                    match with_timeout(
+                NetworkCycleState::DownloadImage { attempt } => {
+                    info!("Downloading image (attempt {})...", attempt);
+                    let result = match with_timeout(
                         Duration::from_secs(HTTP_DOWNLOAD_TIMEOUT_SECS),
-                        download_image(&stack, image_buffer),
+                        download_image(&stack, &mut image_target, &mut partial),
                     )
                     .await
                     {
-                        Ok(Ok((image_data, server_delay))) => {
-                            info!("Image downloaded: {} bytes", image_data.len());
-                            delay_changed = apply_next_delay(server_delay).await;
-                            NetworkCycleState::FinalizeSuccess
-                        }
-                        Ok(Err(e)) => {
-                            error!("Download failed: {}", e);
-                            NetworkCycleState::FinalizeFailure { wifi_issue: true }
-                        }
+                        Ok(result) => result,
                         Err(_) => {
                             error!(
                                 "Image download timed out after {} seconds",
                                 HTTP_DOWNLOAD_TIMEOUT_SECS
                             );
-                            NetworkCycleState::FinalizeFailure { wifi_issue: true }
+                            image_target.abort().await;
+                            Err(NetworkError::Timeout)
+                        }
+                    };
+                    match result {
+                        Ok((download, server_delay)) => {
+                            match download {
+                                Download::Image(image_len) => {
+                                    info!("Image downloaded: {} bytes", image_len)
+                                }
+                                Download::NotModified => info!("Image not modified"),
+                            }
+                            delay_changed = apply_next_delay(server_delay).await;
+                            NetworkCycleState::FinalizeSuccess { download }
+                        }
+                        Err(e) if attempt < download_attempts(e.class()) => {
+                            warn!(
+                                "Download failed: {}, retrying in {}s",
+                                e, DOWNLOAD_RETRY_DELAY_SECS
+                            );
+                            Timer::after(Duration::from_secs(DOWNLOAD_RETRY_DELAY_SECS)).await;
+                            NetworkCycleState::DownloadImage {
+                                attempt: attempt + 1,
+                            }
+                        }
+                        Err(e) => {
+                            error!("Download failed: {}", e);
+                            NetworkCycleState::FinalizeFailure(e)
                         }
                     }
                 }
-                NetworkCycleState::FinalizeSuccess => {
-                    send_event(Event::ImageDownloaded).await;
+                NetworkCycleState::FinalizeSuccess { download } => {
+                    send_event(match download {
+                        Download::Image(_) => Event::ImageDownloaded,
+                        Download::NotModified => Event::ImageNotModified,
+                    })
+                    .await;
                     if delay_changed {
                         info!("Update delay changed, notifying scheduler");
                         send_event(Event::SchedulerUpdateRequested).await;
                     }
                     NetworkCycleState::Disconnect
                 }
-                NetworkCycleState::FinalizeFailure { wifi_issue } => {
-                    {
+                NetworkCycleState::FinalizeFailure(error) => {
+                    let warning_changed = {
                         let mut state = get_state().await;
-                        state.wifi_connected = false;
-                    }
-                    fail_download_and_refresh(wifi_issue).await;
-                    if wifi_issue {
-                        // Trigger a single final render for this cycle.
-                        signal_display_update();
+                        if error.class() == ErrorClass::Network {
+                            state.wifi_connected = false;
+                        }
+                        Alert::new(state.battery_percent, Some(error)) != state.shown_alert
+                    };
+                    fail_download_and_refresh(error).await;
+                    match error.class() {
+                        ErrorClass::Device => {}
+                        // A full refresh only when the warning on the panel changes,
+                        // retries of the same kind of failure keep the screen as is
+                        _ if warning_changed => signal_display_update(),
+                        _ => info!("Warning already on the panel, refresh skipped"),
                     }
                     NetworkCycleState::Disconnect
                 }
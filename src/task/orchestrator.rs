@@ -1,11 +1,14 @@
 //! Orchestrator task
 //! Coordinates events and manages the main application flow
 
-use defmt::info;
+use defmt::{info, warn};
 use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
-use embassy_time::{Duration, Timer};
+use embassy_time::{Duration, Instant, Timer};
 
+use crate::config::{RETRY_INITIAL_SECS, RETRY_JITTER_PERCENT, RETRY_MULTIPLIER};
 use crate::event::{Event, receive_event, send_event};
+use crate::headers::ImageValidators;
+use crate::retry::{RetryPolicy, jitter_value};
 use crate::state::get_state;
 use crate::task::display::signal_display_update;
 use crate::task::network::{signal_led_blink, signal_network_update};
@@ -19,6 +22,39 @@ pub fn signal_scheduler_update() {
     SCHEDULER_INTERRUPT_SIGNAL.signal(());
 }
 
+/// Backoff of failed update cycles, from the `image.retry_*` settings
+const RETRY_POLICY: RetryPolicy = RetryPolicy {
+    initial_secs: RETRY_INITIAL_SECS,
+    multiplier: RETRY_MULTIPLIER,
+    jitter_percent: RETRY_JITTER_PERCENT,
+};
+
+/// Schedule the next try after a failed update cycle and advance the backoff
+async fn schedule_retry() {
+    let mut state = get_state().await;
+    let delay_secs = RETRY_POLICY.delay_secs(
+        state.retry_step,
+        state.next_update_delay_secs,
+        jitter_value(Instant::now().as_ticks()),
+    );
+    state.retry_step = state.retry_step.saturating_add(1);
+    state.retry_delay_secs = Some(delay_secs);
+    info!(
+        "Retry {} in {} seconds (normal interval {} seconds)",
+        state.retry_step, delay_secs, state.next_update_delay_secs
+    );
+}
+
+/// Back to the normal interval after a successful update.
+/// Returns whether a retry was pending and the scheduler has to restart.
+async fn reset_retries() -> bool {
+    let mut state = get_state().await;
+    let retrying = state.retry_step > 0;
+    state.retry_step = 0;
+    state.retry_delay_secs = None;
+    retrying
+}
+
 /// Main orchestrator task - coordinates application flow based on events
 #[embassy_executor::task]
 pub async fn orchestrator() -> ! {
@@ -34,6 +70,8 @@ pub async fn orchestrator() -> ! {
         match event {
             Event::Key0Pressed => {
                 info!("KEY0 pressed - triggering immediate display refresh");
+                // Refresh even if the server returns the same image
+                get_state().await.force_refresh = true;
                 // Signal network task to download image
                 signal_network_update();
             }
@@ -62,15 +100,62 @@ pub async fn orchestrator() -> ! {
                 info!("Image downloaded successfully - signaling display update");
                 // Signal display task to update screen
                 signal_display_update();
+                if reset_retries().await {
+                    info!("Update succeeded, back to the normal interval");
+                    signal_scheduler_update();
+                }
             }
-            Event::ImageDownloadFailed => {
-                info!("Image download failed");
+            Event::ImageNotModified => {
+                info!("Image not modified - keeping the picture on display");
+                if reset_retries().await {
+                    info!("Update succeeded, back to the normal interval");
+                    // Redraw once to remove the warning of the failed update
+                    signal_display_update();
+                    signal_scheduler_update();
+                }
+            }
+            Event::ImageDownloadFailed(error) => {
+                warn!(
+                    "Image download failed: {} ({} problem)",
+                    error,
+                    error.class()
+                );
+                // Try again sooner than the normal interval
+                schedule_retry().await;
+                signal_scheduler_update();
             }
             Event::SchedulerUpdateRequested => {
                 info!("Scheduler update requested - interrupting scheduler");
                 // Signal scheduler to restart with new delay
                 signal_scheduler_update();
             }
+            Event::DisplayUpdated => {
+                info!("Display updated");
+                get_state().await.last_display_error = None;
+            }
+            Event::DisplayFailed(error) => {
+                let mut state = get_state().await;
+                state.last_display_error = Some(error);
+                state.display_failure_count = state.display_failure_count.saturating_add(1);
+                // Download the image in full next time so it is shown again
+                state.image_validators = ImageValidators::new();
+                warn!(
+                    "Display update failed: {} ({} failures since boot)",
+                    error, state.display_failure_count
+                );
+            }
+            Event::TemperatureInRange => {
+                info!("Temperature back in range - showing postponed image");
+                get_state().await.refresh_postponed = false;
+                // The buffered build still holds the image, streaming has to fetch it again
+                #[cfg(not(feature = "streaming"))]
+                signal_display_update();
+                #[cfg(feature = "streaming")]
+                {
+                    get_state().await.image_validators = ImageValidators::new();
+                    signal_network_update();
+                }
+            }
         }
     }
 }
@@ -82,15 +167,20 @@ pub async fn scheduler() -> ! {
     info!("Scheduler task started");
 
     loop {
-        // Get next update delay from state
-        let delay_secs = {
-            let state = get_state().await;
-            state.next_update_delay_secs
+        // Get next update delay from state, shorter while retrying a failed update
+        let (delay_secs, retry) = {
+            let mut state = get_state().await;
+            let delay_secs = state
+                .retry_delay_secs
+                .unwrap_or(state.next_update_delay_secs);
+            state.next_update_at = Some(Instant::now() + Duration::from_secs(delay_secs));
+            (delay_secs, state.retry_delay_secs.is_some())
         };
 
         info!(
-            "Scheduler: waiting {} seconds until next update",
-            delay_secs
+            "Scheduler: waiting {} seconds until next {}",
+            delay_secs,
+            if retry { "retry" } else { "update" }
         );
 
         // Wait for either timer expiration or scheduler interrupt signal
@@ -103,6 +193,10 @@ pub async fn scheduler() -> ! {
             embassy_futures::select::Either::First(_) => {
                 // Timer expired normally
                 info!("Scheduler: timer expired, sending event");
+                if retry {
+                    // Normal interval from now on, unless this retry fails as well
+                    get_state().await.retry_delay_secs = None;
+                }
                 send_event(Event::TimerExpired).await;
             }
             embassy_futures::select::Either::Second(_) => {
@@ -6,14 +6,21 @@
 //! - Divider ratio: (220 + 100) / 100 = 3.2
 //! - This allows measuring up to ~10.5V on a 3.3V ADC
 //! - GPIO28 does not conflict with WiFi pins, so no coordination needed
+//!
+//! The RP2040 on-die temperature sensor (ADC4) is sampled alongside; the panel
+//! is only refreshed while it is inside the configured temperature window.
 
 use defmt::{info, warn};
 use embassy_rp::adc::{Adc, Channel};
 use embassy_rp::gpio::Pull;
+use embassy_rp::{Peri, peripherals::ADC_TEMP_SENSOR};
 use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
 use embassy_time::{Duration, Timer};
 
+use crate::config::{MAX_REFRESH_TEMPERATURE, MIN_REFRESH_TEMPERATURE};
+use crate::event::{Event, send_event};
 use crate::state::get_state;
+use crate::task::display::signal_status_update;
 
 /// Signal for triggering on-demand battery measurement
 static BATTERY_MEASURE_SIGNAL: Signal<CriticalSectionRawMutex, ()> = Signal::new();
@@ -31,45 +38,78 @@ pub async fn wait_battery_ready() {
     BATTERY_READY_SIGNAL.wait().await;
 }
 
-/// Battery voltage reader task - periodically measures battery voltage
+/// Whether the panel may be refreshed at `temperature` (°C, `None` if unknown)
+pub fn refresh_temperature_ok(temperature: Option<i8>) -> bool {
+    temperature.is_none_or(|t| (MIN_REFRESH_TEMPERATURE..=MAX_REFRESH_TEMPERATURE).contains(&t))
+}
+
+/// Battery voltage reader task - periodically measures battery voltage and chip temperature
 #[embassy_executor::task]
-pub async fn battery_monitor(mut adc: Adc<'static, embassy_rp::adc::Async>) -> ! {
-    info!("Battery monitor task started (GPIO28/ADC2)");
+pub async fn battery_monitor(
+    mut adc: Adc<'static, embassy_rp::adc::Async>,
+    temp_sensor: Peri<'static, ADC_TEMP_SENSOR>,
+) -> ! {
+    info!("Battery monitor task started (GPIO28/ADC2, temperature/ADC4)");
 
     // Configure GPIO28 as ADC input (ADC channel 2)
     let pin_28 = unsafe { embassy_rp::peripherals::PIN_28::steal() };
     let mut adc_channel = Channel::new_pin(pin_28, Pull::None);
+    let mut temp_channel = Channel::new_temp_sensor(temp_sensor);
 
     // Perform initial measurement immediately
     info!("Performing initial battery measurement...");
     let battery_percent = measure_battery_percentage(&mut adc, &mut adc_channel).await;
+    let temperature = measure_temperature(&mut adc, &mut temp_channel).await;
     {
         let mut state = get_state().await;
         state.battery_percent = battery_percent;
+        state.temperature_c = temperature;
     }
-    info!("Initial battery: {}%", battery_percent);
+    info!(
+        "Initial battery: {}%, temperature: {} C",
+        battery_percent, temperature
+    );
 
     // Signal that first measurement is complete
     BATTERY_READY_SIGNAL.signal(());
 
     loop {
         // Wait for either periodic timer (5 minutes) or manual trigger signal
-        embassy_futures::select::select(
-            Timer::after(Duration::from_secs(300)),
-            BATTERY_MEASURE_SIGNAL.wait(),
-        )
-        .await;
-
-        // Measure battery voltage
+        let requested = matches!(
+            embassy_futures::select::select(
+                Timer::after(Duration::from_secs(300)),
+                BATTERY_MEASURE_SIGNAL.wait(),
+            )
+            .await,
+            embassy_futures::select::Either::Second(_)
+        );
+
+        // Measure battery voltage and chip temperature
         let battery_percent = measure_battery_percentage(&mut adc, &mut adc_channel).await;
+        let temperature = measure_temperature(&mut adc, &mut temp_channel).await;
 
         // Update state
-        {
+        let resume = {
             let mut state = get_state().await;
             state.battery_percent = battery_percent;
+            state.temperature_c = temperature;
+            state.refresh_postponed && refresh_temperature_ok(temperature)
+        };
+
+        info!(
+            "Battery: {}%, temperature: {} C",
+            battery_percent, temperature
+        );
+
+        // Show the image that was held back while it was too cold (or hot)
+        if resume {
+            send_event(Event::TemperatureInRange).await;
         }
 
-        info!("Battery: {}%", battery_percent);
+        // Show an on-demand reading right away without a full-screen refresh
+        if requested {
+            signal_status_update();
+        }
     }
 }
 
@@ -94,9 +134,9 @@ async fn measure_battery_percentage(
     Timer::after(Duration::from_micros(100)).await;
 
     // Collect ADC samples
-    for i in 0..SAMPLE_COUNT {
+    for sample in samples.iter_mut() {
         if let Ok(adc_value) = adc.read(adc_channel).await {
-            samples[i] = adc_value;
+            *sample = adc_value;
             valid_samples += 1;
         }
         Timer::after(Duration::from_millis(SAMPLE_DELAY_MS)).await;
@@ -138,3 +178,50 @@ async fn measure_battery_percentage(
 
     percentage.clamp(0.0, 100.0) as u8
 }
+
+/// Measure the RP2040 die temperature in whole °C
+/// Uses median filtering like the battery reading; `None` if no sample could be read
+///
+/// Conversion per RP2040 datasheet 4.9.5: T = 27 - (V - 0.706) / 0.001721
+async fn measure_temperature(
+    adc: &mut Adc<'static, embassy_rp::adc::Async>,
+    temp_channel: &mut Channel<'static>,
+) -> Option<i8> {
+    const SAMPLE_COUNT: usize = 5;
+    const SAMPLE_DELAY_MS: u64 = 5;
+
+    let mut samples = [0u16; SAMPLE_COUNT];
+    let mut valid_samples = 0;
+
+    for _ in 0..SAMPLE_COUNT {
+        if let Ok(adc_value) = adc.read(temp_channel).await {
+            samples[valid_samples] = adc_value;
+            valid_samples += 1;
+        }
+        Timer::after(Duration::from_millis(SAMPLE_DELAY_MS)).await;
+    }
+
+    if valid_samples == 0 {
+        warn!("No valid temperature samples");
+        return None;
+    }
+
+    samples[..valid_samples].sort_unstable();
+    let median_adc = samples[valid_samples / 2];
+
+    let sensor_voltage = f32::from(median_adc) * 3.3 / 4096.0;
+    let temperature = 27.0 - (sensor_voltage - 0.706) / 0.001721;
+
+    info!(
+        "Chip temperature: {} C (ADC: {}, sensor voltage: {}V)",
+        temperature, median_adc, sensor_voltage
+    );
+
+    // Round to the nearest degree (`f32::round` needs std)
+    let rounded = if temperature < 0.0 {
+        temperature - 0.5
+    } else {
+        temperature + 0.5
+    };
+    Some(rounded.clamp(f32::from(i8::MIN), f32::from(i8::MAX)) as i8)
+}
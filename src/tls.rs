@@ -0,0 +1,240 @@
+//! TLS for `https://` image URLs
+//! The server is identified by a pin from `[tls]` instead of a CA chain: the
+//! SHA-256 of its public key (SubjectPublicKeyInfo) or of its whole certificate.
+//! The frame has no clock to check validity periods, and a pin needs no trust
+//! store in flash. The server proves it holds the pinned key with its TLS 1.3
+//! CertificateVerify signature, which must be ECDSA P-256 with SHA-256.
+
+use defmt::{error, info};
+use embassy_rp::clocks::RoscRng;
+use embedded_tls::{
+    Aes128GcmSha256, CertificateEntryRef, CertificateRef, CertificateVerifyRef, CryptoProvider,
+    SignatureScheme, TlsError, TlsVerifier,
+};
+use p256::ecdsa::signature::Verifier;
+use p256::ecdsa::{Signature, VerifyingKey};
+use rand_core::CryptoRngCore;
+use sha2::{Digest, Sha256};
+
+/// Bytes of a TLS record with its header and tag, the size of the read buffer
+pub const TLS_READ_BUFFER_SIZE: usize = 16_640;
+
+/// Outgoing records only carry the request headers
+pub const TLS_WRITE_BUFFER_SIZE: usize = 4096;
+
+/// What `[tls]` pins, as a SHA-256 hash
+// Only constructed by the generated config when a pin is set
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Pin {
+    /// DER SubjectPublicKeyInfo of the server certificate (`tls.public_key_sha256`),
+    /// survives certificate renewals that keep the key
+    PublicKey([u8; 32]),
+    /// DER server certificate (`tls.certificate_sha256`)
+    Certificate([u8; 32]),
+}
+
+/// Why the TLS handshake failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum TlsFailure {
+    /// The server sent a certificate that does not match the pin
+    Pin,
+    /// The server key is not ECDSA P-256 or its signature is wrong
+    Signature,
+    /// Any other handshake error (protocol version, cipher suite, alert, ...)
+    Handshake,
+}
+
+impl TlsFailure {
+    /// Failure for an error of `TlsConnection::open`
+    pub fn from_error(error: TlsError) -> Self {
+        error!("TLS handshake failed: {:?}", error);
+        match error {
+            TlsError::InvalidCertificate => Self::Pin,
+            TlsError::InvalidSignature | TlsError::InvalidSignatureScheme => Self::Signature,
+            _ => Self::Handshake,
+        }
+    }
+
+    /// Short name for logs and status captions
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::Pin => "pin",
+            Self::Signature => "signature",
+            Self::Handshake => "handshake",
+        }
+    }
+}
+
+/// Crypto for `embedded_tls`: ring oscillator randomness and the pin check
+pub struct PinnedProvider {
+    rng: RoscRng,
+    verifier: PinVerifier,
+}
+
+impl PinnedProvider {
+    pub fn new(pin: Pin) -> Self {
+        Self {
+            rng: RoscRng,
+            verifier: PinVerifier {
+                pin,
+                key: None,
+                transcript: None,
+            },
+        }
+    }
+}
+
+impl CryptoProvider for PinnedProvider {
+    type CipherSuite = Aes128GcmSha256;
+    type Signature = &'static [u8];
+
+    fn rng(&mut self) -> impl CryptoRngCore {
+        &mut self.rng
+    }
+
+    fn verifier(&mut self) -> Result<&mut impl TlsVerifier<Self::CipherSuite>, TlsError> {
+        Ok(&mut self.verifier)
+    }
+}
+
+/// Accepts the server if its certificate matches `pin` and it signs the
+/// handshake with the certificate's key
+struct PinVerifier {
+    pin: Pin,
+    /// Key of the accepted certificate
+    key: Option<VerifyingKey>,
+    /// Handshake transcript up to the certificate, signed by the server
+    transcript: Option<Sha256>,
+}
+
+impl TlsVerifier<Aes128GcmSha256> for PinVerifier {
+    /// The pin identifies the server, whatever name its certificate has
+    fn set_hostname_verification(&mut self, _hostname: &str) -> Result<(), TlsError> {
+        Ok(())
+    }
+
+    fn verify_certificate(
+        &mut self,
+        transcript: &Sha256,
+        certificate: CertificateRef,
+    ) -> Result<(), TlsError> {
+        let Some(CertificateEntryRef::X509(der)) = certificate.entries.first() else {
+            error!("TLS: server sent no X.509 certificate");
+            return Err(TlsError::InvalidCertificate);
+        };
+        let Some(public_key) = subject_public_key_info(der) else {
+            error!("TLS: cannot parse the server certificate");
+            return Err(TlsError::InvalidCertificate);
+        };
+
+        let (name, pinned, hash) = match self.pin {
+            Pin::PublicKey(pinned) => ("public key", pinned, Sha256::digest(public_key)),
+            Pin::Certificate(pinned) => ("certificate", pinned, Sha256::digest(der)),
+        };
+        if hash[..] != pinned {
+            error!(
+                "TLS: server {} SHA-256 {=[u8]:02x} does not match the pin",
+                name,
+                hash[..]
+            );
+            return Err(TlsError::InvalidCertificate);
+        }
+        info!("TLS: server {} matches the pin", name);
+
+        let Some(key) = ec_public_key(public_key) else {
+            error!("TLS: server key is not ECDSA P-256");
+            return Err(TlsError::InvalidSignatureScheme);
+        };
+        self.key = Some(key);
+        self.transcript = Some(transcript.clone());
+        Ok(())
+    }
+
+    fn verify_signature(&mut self, verify: CertificateVerifyRef) -> Result<(), TlsError> {
+        if verify.signature_scheme != SignatureScheme::EcdsaSecp256r1Sha256 {
+            error!(
+                "TLS: unsupported signature scheme {:?}",
+                verify.signature_scheme
+            );
+            return Err(TlsError::InvalidSignatureScheme);
+        }
+        let (Some(key), Some(transcript)) = (self.key.take(), self.transcript.take()) else {
+            return Err(TlsError::InvalidSignature);
+        };
+
+        // RFC 8446 4.4.3: 64 spaces, context string and the transcript hash
+        let mut message = [b' '; 64 + CERTIFICATE_VERIFY_CONTEXT.len() + 32];
+        message[64..64 + CERTIFICATE_VERIFY_CONTEXT.len()]
+            .copy_from_slice(CERTIFICATE_VERIFY_CONTEXT);
+        message[64 + CERTIFICATE_VERIFY_CONTEXT.len()..].copy_from_slice(&transcript.finalize());
+
+        let signature = Signature::from_der(verify.signature).map_err(|_| {
+            error!("TLS: malformed server signature");
+            TlsError::InvalidSignature
+        })?;
+        key.verify(&message, &signature).map_err(|_| {
+            error!("TLS: server signature does not verify");
+            TlsError::InvalidSignature
+        })
+    }
+}
+
+/// Context of the server's CertificateVerify signature, with its terminating zero
+const CERTIFICATE_VERIFY_CONTEXT: &[u8] = b"TLS 1.3, server CertificateVerify\0";
+
+/// Split the next DER element off `input`.
+/// Returns its tag, the whole element and its contents.
+fn der_element<'a>(input: &mut &'a [u8]) -> Option<(u8, &'a [u8], &'a [u8])> {
+    let data = *input;
+    let tag = *data.first()?;
+    let first = *data.get(1)?;
+    let (len, header) = if first < 0x80 {
+        (usize::from(first), 2)
+    } else {
+        // Long form; certificates stay well below 16 MB
+        let count = usize::from(first & 0x7f);
+        if count == 0 || count > 3 {
+            return None;
+        }
+        let bytes = data.get(2..2 + count)?;
+        let len = bytes
+            .iter()
+            .fold(0, |len, &byte| (len << 8) | usize::from(byte));
+        (len, 2 + count)
+    };
+    let element = data.get(..header + len)?;
+    *input = &data[element.len()..];
+    Some((tag, element, &element[header..]))
+}
+
+/// DER SubjectPublicKeyInfo of an X.509 certificate
+fn subject_public_key_info(certificate: &[u8]) -> Option<&[u8]> {
+    let mut input = certificate;
+    let (_, _, mut certificate) = der_element(&mut input)?;
+    let (_, _, mut tbs) = der_element(&mut certificate)?;
+    // Optional [0] version
+    if tbs.first() == Some(&0xa0) {
+        der_element(&mut tbs)?;
+    }
+    // Serial number, signature algorithm, issuer, validity, subject
+    for _ in 0..5 {
+        der_element(&mut tbs)?;
+    }
+    let (tag, public_key, _) = der_element(&mut tbs)?;
+    (tag == 0x30).then_some(public_key)
+}
+
+/// P-256 key of a SubjectPublicKeyInfo, `None` for other key types
+fn ec_public_key(public_key: &[u8]) -> Option<VerifyingKey> {
+    let mut input = public_key;
+    let (_, _, mut fields) = der_element(&mut input)?;
+    // AlgorithmIdentifier; other curves and RSA fail to decode as a P-256 point
+    der_element(&mut fields)?;
+    let (tag, _, bits) = der_element(&mut fields)?;
+    let (&unused_bits, point) = bits.split_first()?;
+    if tag != 0x03 || unused_bits != 0 {
+        return None;
+    }
+    VerifyingKey::from_sec1_bytes(point).ok()
+}